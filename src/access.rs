@@ -0,0 +1,74 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    FileSessionStorage,
+};
+
+/// Name of the folder inside the sessions folder that holds the last access times.
+pub(crate) const ACCESS_FOLDER: &str = ".access";
+
+impl FileSessionStorage {
+    /// Record when each session was last loaded, available as
+    /// [`SessionMetadata::last_accessed`](crate::SessionMetadata::last_accessed).
+    ///
+    /// The time is kept as the modified date of an empty file at `.access/<session id>`, so the
+    /// session file itself and its modified date are left alone. This costs an extra write for
+    /// every load.
+    pub fn set_track_last_access(mut self, enabled: bool) -> Self {
+        self.track_last_access = enabled;
+        self
+    }
+
+    fn access_path(&self, session_id: &Id) -> PathBuf {
+        self.folder()
+            .join(ACCESS_FOLDER)
+            .join(crate::naming::encode_id(session_id))
+    }
+
+    /// Mark a session as accessed just now, if last access tracking is enabled.
+    pub(crate) async fn touch_last_access(&self, session_id: &Id) -> session_store::Result<()> {
+        if !self.track_last_access {
+            return Ok(());
+        }
+        let folder = self.folder().join(ACCESS_FOLDER);
+        self.fs
+            .create_dir_all(&folder)
+            .await
+            .context("create folder", &folder)?;
+        let path = self.access_path(session_id);
+        self.fs
+            .write(&path, &[])
+            .await
+            .context("write access time", &path)?;
+        self.fs
+            .set_modified(&path, self.now())
+            .await
+            .context("write access time", &path)
+    }
+
+    /// When a session was last loaded, `None` if it wasn't loaded since tracking was enabled.
+    pub(crate) async fn last_access(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<SystemTime>> {
+        let path = self.access_path(session_id);
+        match self.fs.metadata(&path).await {
+            Ok(metadata) => Ok(metadata.modified().ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(FileError::new("get metadata of", &path, e).into()),
+        }
+    }
+
+    /// Forget the last access time of a session, called when the session is removed.
+    pub(crate) async fn remove_last_access(&self, session_id: &Id) -> session_store::Result<()> {
+        let path = self.access_path(session_id);
+        match self.fs.remove_file(&path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::new("delete", &path, e).into()),
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use tower_layer::Layer;
+use tower_service::Service;
+use tower_sessions_core::{session::Id, session_store, ExpiredDeletion, SessionStore};
+
+use crate::FileSessionStorage;
+
+/// Number of sessions listed per page if the request doesn't specify a limit.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Most sessions listed per page, larger limits are lowered to this.
+const MAX_PAGE_SIZE: usize = 1000;
+
+struct AdminError(StatusCode, String);
+
+impl From<session_store::Error> for AdminError {
+    fn from(error: session_store::Error) -> Self {
+        AdminError(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn parse_id(id: &str) -> Result<Id, AdminError> {
+    Id::from_str(id).map_err(|_| {
+        AdminError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid session ID {id:?}"),
+        )
+    })
+}
+
+async fn list(
+    State(store): State<FileSessionStorage>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    let cursor = query.get("cursor").map(|id| parse_id(id)).transpose()?;
+    let limit = match query.get("limit") {
+        Some(limit) => limit
+            .parse()
+            .map_err(|_| AdminError(StatusCode::BAD_REQUEST, "Invalid limit".to_string()))?,
+        None => DEFAULT_PAGE_SIZE,
+    }
+    .clamp(1, MAX_PAGE_SIZE);
+    let page = store.list_sessions(cursor.as_ref(), limit).await?;
+    Ok(Json(json!({
+        "sessions": page.session_ids.iter().map(Id::to_string).collect::<Vec<_>>(),
+        "next_cursor": page.next_cursor.map(|id| id.to_string()),
+    })))
+}
+
+async fn metadata(
+    State(store): State<FileSessionStorage>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    let session_id = parse_id(&id)?;
+    let metadata = store
+        .session_metadata(&session_id)
+        .await?
+        .ok_or_else(|| AdminError(StatusCode::NOT_FOUND, "No such session".to_string()))?;
+    let unix_secs = |time: std::time::SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .ok()
+    };
+    Ok(Json(json!({
+        "id": session_id.to_string(),
+        "size": metadata.size,
+        "created": metadata.created.and_then(unix_secs),
+        "modified": unix_secs(metadata.modified),
+        "last_accessed": metadata.last_accessed.and_then(unix_secs),
+    })))
+}
+
+async fn delete(
+    State(store): State<FileSessionStorage>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    store.delete(&parse_id(&id)?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The body of `PUT /settings`, fields that are left out keep their current value.
+#[derive(Deserialize)]
+struct SettingsUpdate {
+    minimum_expiry_secs: Option<u64>,
+    /// `null` disables the threshold.
+    #[serde(default, deserialize_with = "present")]
+    slow_operation_threshold_ms: Option<Option<u64>>,
+    /// `null` removes the limit.
+    #[serde(default, deserialize_with = "present")]
+    sweep_rate_limit: Option<Option<u32>>,
+    memory_cache_capacity: Option<usize>,
+}
+
+/// Tell a field that is `null` apart from one that is left out.
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+fn settings_json(store: &FileSessionStorage) -> serde_json::Value {
+    json!({
+        "minimum_expiry_secs": store.minimum_expiry_date().as_secs(),
+        "slow_operation_threshold_ms": store
+            .slow_operation_threshold()
+            .map(|threshold| threshold.as_millis() as u64),
+        "sweep_rate_limit": store.sweep_rate_limit(),
+        "memory_cache_capacity": store.memory_cache_capacity(),
+    })
+}
+
+async fn get_settings(State(store): State<FileSessionStorage>) -> Json<serde_json::Value> {
+    Json(settings_json(&store))
+}
+
+async fn put_settings(
+    State(store): State<FileSessionStorage>,
+    Json(update): Json<SettingsUpdate>,
+) -> Json<serde_json::Value> {
+    if let Some(secs) = update.minimum_expiry_secs {
+        store.update_minimum_expiry_date(Duration::from_secs(secs));
+    }
+    if let Some(millis) = update.slow_operation_threshold_ms {
+        store.update_slow_operation_threshold(millis.map(Duration::from_millis));
+    }
+    if let Some(per_second) = update.sweep_rate_limit {
+        store.update_sweep_rate_limit(per_second);
+    }
+    if let Some(capacity) = update.memory_cache_capacity {
+        store.update_memory_cache_capacity(capacity);
+    }
+    Json(settings_json(&store))
+}
+
+async fn sweep(State(store): State<FileSessionStorage>) -> Result<StatusCode, AdminError> {
+    store.delete_expired().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+impl FileSessionStorage {
+    /// An axum [`Router`] for administrating the sessions in this store.
+    ///
+    /// - `GET /` lists session IDs, paged using the `cursor` and `limit` query parameters, at most
+    ///   1000 per page
+    /// - `GET /{id}` returns the size and modified date of a session
+    /// - `DELETE /{id}` deletes a session
+    /// - `POST /sweep` deletes expired sessions
+    /// - `GET /settings` returns the settings that can be changed at runtime, `PUT /settings`
+    ///   changes them and returns the result, taking a JSON object with any of
+    ///   - `minimum_expiry_secs`
+    ///   - `slow_operation_threshold_ms`, `null` disables the warnings
+    ///   - `sweep_rate_limit` in sessions per second, `null` removes the limit
+    ///   - `memory_cache_capacity` in sessions
+    ///
+    ///   Fields that are left out keep their current value, as do `minimum_expiry_secs` and
+    ///   `memory_cache_capacity` when they are `null`.
+    ///
+    /// Every route is wrapped in `auth_layer`, which should reject anyone who isn't allowed to
+    /// manage sessions. Nest the router under a path of your choice:
+    ///
+    /// ```rs
+    /// let app = Router::new().nest("/admin/sessions", session_store.admin_router(require_admin));
+    /// ```
+    pub fn admin_router<L>(&self, auth_layer: L) -> Router
+    where
+        L: Layer<axum::routing::Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        Router::new()
+            .route("/", get(list))
+            .route("/sweep", post(sweep))
+            .route("/settings", get(get_settings).put(put_settings))
+            .route("/:id", get(metadata).delete(delete))
+            .layer(auth_layer)
+            .with_state(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use tower_layer::Identity;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    async fn send(
+        router: &mut Router,
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = if body.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&body).unwrap()
+        };
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn list_pages_through_sessions() {
+        let (store, _, clock) = store();
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let mut session = record(&clock, Duration::from_secs(60));
+            store.create(&mut session).await.unwrap();
+            ids.push(session.id.to_string());
+        }
+        ids.sort();
+        let mut router = store.admin_router(Identity::new());
+
+        let (status, page) = send(&mut router, "GET", "/?limit=2", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["sessions"], json!(ids[..2]));
+        let cursor = page["next_cursor"].as_str().unwrap();
+        let (_, page) = send(&mut router, "GET", &format!("/?cursor={cursor}"), None).await;
+        assert_eq!(page["sessions"], json!(ids[2..]));
+        assert_eq!(page["next_cursor"], json!(null));
+
+        let (status, _) = send(&mut router, "GET", "/?limit=lots", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn settings_change_only_given_fields() {
+        let (store, _, _) = store();
+        let store = store
+            .set_slow_operation_threshold(Duration::from_millis(500))
+            .set_sweep_rate_limit(100);
+        let mut router = store.admin_router(Identity::new());
+
+        let (status, settings) = send(
+            &mut router,
+            "PUT",
+            "/settings",
+            Some(json!({
+                "minimum_expiry_secs": 30,
+                "slow_operation_threshold_ms": null,
+                "memory_cache_capacity": 10,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let expected = json!({
+            "minimum_expiry_secs": 30,
+            "slow_operation_threshold_ms": null,
+            "sweep_rate_limit": 100,
+            "memory_cache_capacity": 10,
+        });
+        assert_eq!(settings, expected);
+        assert_eq!(
+            send(&mut router, "GET", "/settings", None).await.1,
+            expected
+        );
+        assert_eq!(store.slow_operation_threshold(), None);
+
+        let (_, settings) = send(
+            &mut router,
+            "PUT",
+            "/settings",
+            Some(json!({ "sweep_rate_limit": null, "memory_cache_capacity": null })),
+        )
+        .await;
+        assert_eq!(settings["sweep_rate_limit"], json!(null));
+        assert_eq!(settings["memory_cache_capacity"], json!(10));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_session() {
+        let (store, _, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        let mut router = store.admin_router(Identity::new());
+        let uri = format!("/{}", session.id);
+
+        assert_eq!(send(&mut router, "GET", &uri, None).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&mut router, "DELETE", &uri, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+        assert_eq!(
+            send(&mut router, "GET", &uri, None).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(&mut router, "DELETE", "/not-an-id", None).await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn sweep_deletes_expired_sessions() {
+        let (store, _, clock) = store();
+        let mut expiring = record(&clock, Duration::from_secs(60));
+        store.create(&mut expiring).await.unwrap();
+        let mut session = record(&clock, Duration::from_secs(3600));
+        store.create(&mut session).await.unwrap();
+        let mut router = store.admin_router(Identity::new());
+
+        let (status, metadata) = send(&mut router, "GET", &format!("/{}", session.id), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(metadata["id"], json!(session.id.to_string()));
+        assert!(metadata["size"].as_u64().unwrap() > 0);
+
+        clock.advance(Duration::from_secs(120));
+        let (status, _) = send(&mut router, "POST", "/sweep", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, page) = send(&mut router, "GET", "/", None).await;
+        assert_eq!(page["sessions"], json!([session.id.to_string()]));
+    }
+}
@@ -0,0 +1,66 @@
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{error::IoResultExt, FileSessionStorage, SessionEvent};
+
+impl FileSessionStorage {
+    /// Rewrite every session `predicate` matches with `transform`, for example to strip email
+    /// addresses and IP addresses from sessions kept past a retention deadline. Returns the
+    /// number of sessions rewritten.
+    ///
+    /// Each session is locked while it is checked and rewritten, so concurrent saves aren't lost.
+    /// Changes to the ID are ignored. The older versions kept with
+    /// [`set_keep_versions`](Self::set_keep_versions) of rewritten sessions are deleted, since
+    /// they still hold the old data. Sessions in the trash or archive are not changed. This needs
+    /// to load every session.
+    pub async fn anonymize_where(
+        &self,
+        predicate: impl Fn(&Record) -> bool + Send + Sync,
+        transform: impl Fn(&mut Record) + Send + Sync,
+    ) -> session_store::Result<usize> {
+        let mut anonymized = 0;
+        for session_id in self.list_session_ids().await? {
+            if let Some(record) = self
+                .anonymize_session(&session_id, &predicate, &transform)
+                .await?
+            {
+                self.emit(SessionEvent::Saved(session_id));
+                self.hooks.saved(&record).await;
+                anonymized += 1;
+            }
+        }
+        Ok(anonymized)
+    }
+
+    /// Rewrite one session if it matches, returns the new record if it did.
+    pub(crate) async fn anonymize_session(
+        &self,
+        session_id: &Id,
+        predicate: &(impl Fn(&Record) -> bool + Send + Sync),
+        transform: &(impl Fn(&mut Record) + Send + Sync),
+    ) -> session_store::Result<Option<Record>> {
+        let _guard = self.locks.lock(*session_id).await;
+        self.migrate_session(session_id).await?;
+        let Some(old) = self.read_record(session_id).await? else {
+            return Ok(None);
+        };
+        if !predicate(&old) {
+            return Ok(None);
+        }
+        let mut record = old.clone();
+        transform(&mut record);
+        // The file name decides which session it is
+        record.id = *session_id;
+
+        let path = self.session_path(session_id);
+        let lock = self.lock_file(&path, true).await.context("lock", &path)?;
+        self.replace_file(session_id, &record, None).await?;
+        drop(lock);
+        self.remove_versions(session_id).await?;
+        self.remove_from_indexes(&old, Some(&record)).await?;
+        self.add_to_indexes(&record).await?;
+        Ok(Some(record))
+    }
+}
@@ -0,0 +1,46 @@
+use std::{fs::File, path::PathBuf};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use tower_sessions_core::session_store;
+
+use crate::{error::IoResultExt, FileSessionStorage};
+
+impl FileSessionStorage {
+    /// Write a compressed `.tar.gz` archive of the entire sessions folder to `path`.
+    ///
+    /// Sessions written while the archive is being created may or may not be included.
+    pub async fn snapshot_to(&self, path: impl Into<PathBuf>) -> session_store::Result<()> {
+        let folder_name = self.folder().to_path_buf();
+        let path = path.into();
+        crate::rt::unblock(move || {
+            let file = File::create(&path).context("create archive", &path)?;
+            let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            archive
+                .append_dir_all(".", &folder_name)
+                .context("write archive", &path)?;
+            archive
+                .into_inner()
+                .and_then(|encoder| encoder.finish())
+                .context("write archive", &path)?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Extract an archive created by [`snapshot_to`](Self::snapshot_to) into the sessions folder.
+    ///
+    /// Sessions in the archive replace existing sessions with the same ID, other sessions are
+    /// left alone.
+    pub async fn restore_from(&self, path: impl Into<PathBuf>) -> session_store::Result<()> {
+        let folder_name = self.folder().to_path_buf();
+        let path = path.into();
+        crate::rt::unblock(move || {
+            let file = File::open(&path).context("open archive", &path)?;
+            std::fs::create_dir_all(&folder_name).context("create folder", &folder_name)?;
+            tar::Archive::new(GzDecoder::new(file))
+                .unpack(&folder_name)
+                .context("read archive", &path)
+        })
+        .await
+    }
+}
@@ -0,0 +1,117 @@
+//! Inspect and clean up a folder of sessions created by `FileSessionStorage`.
+//!
+//! Run `sessions-file-tool help` for usage.
+
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use tower_sessions_core::{session::Id, session_store, ExpiredDeletion, SessionStore};
+use tower_sessions_file_store::FileSessionStorage;
+
+const USAGE: &str = "\
+Usage: sessions-file-tool [--folder <FOLDER>] <COMMAND>
+
+Commands:
+  list           List all sessions with their size and age
+  inspect <ID>   Pretty print a session
+  delete <ID>    Delete a session
+  purge-expired  Delete all expired sessions
+
+Options:
+  --folder <FOLDER>  The sessions folder [default: .sessions]";
+
+enum Command {
+    Help,
+    List,
+    Inspect(Id),
+    Delete(Id),
+    PurgeExpired,
+}
+
+fn parse_id(id: Option<String>) -> Result<Id, String> {
+    let id = id.ok_or("Missing session ID")?;
+    Id::from_str(&id).map_err(|_| format!("Invalid session ID {id:?}"))
+}
+
+fn parse_args() -> Result<(PathBuf, Command), String> {
+    let mut folder = PathBuf::from(".sessions");
+    let mut args = std::env::args().skip(1);
+    let command = loop {
+        match args.next().as_deref() {
+            Some("--folder") => folder = args.next().ok_or("Missing folder")?.into(),
+            Some("list") => break Command::List,
+            Some("inspect") => break Command::Inspect(parse_id(args.next())?),
+            Some("delete") => break Command::Delete(parse_id(args.next())?),
+            Some("purge-expired") => break Command::PurgeExpired,
+            Some("help" | "--help" | "-h") => break Command::Help,
+            None => return Err(USAGE.to_string()),
+            Some(other) => return Err(format!("Unknown argument {other:?}\n\n{USAGE}")),
+        }
+    };
+    if let Some(extra) = args.next() {
+        return Err(format!("Unexpected argument {extra:?}\n\n{USAGE}"));
+    }
+    Ok((folder, command))
+}
+
+async fn run(store: FileSessionStorage, command: Command) -> session_store::Result<()> {
+    match command {
+        Command::Help => println!("{USAGE}"),
+        Command::List => {
+            for session_id in store.list_session_ids().await? {
+                let Some(metadata) = store.session_metadata(&session_id).await? else {
+                    continue;
+                };
+                let age = SystemTime::now()
+                    .duration_since(metadata.modified)
+                    .unwrap_or_default();
+                println!(
+                    "{session_id}\t{} bytes\tsaved {}s ago",
+                    metadata.size,
+                    age.as_secs()
+                );
+            }
+        }
+        Command::Inspect(session_id) => match store.load(&session_id).await? {
+            Some(record) => println!(
+                "{}",
+                serde_json::to_string_pretty(&record)
+                    .map_err(|e| session_store::Error::Encode(e.to_string()))?
+            ),
+            None => println!("No session with ID {session_id}"),
+        },
+        Command::Delete(session_id) => store.delete(&session_id).await?,
+        Command::PurgeExpired => {
+            store
+                .set_minimum_expiry_date(Duration::ZERO)
+                .delete_expired()
+                .await?
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let (folder, command) = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the runtime");
+    match runtime.block_on(run(FileSessionStorage::new_in_folder(folder), command)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{
+    error::{FileError, IoResultExt, NO_SUCH_SESSION},
+    FileSessionStorage,
+};
+
+/// Whether `name` can be used as the file name of a blob.
+fn is_valid_blob_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl FileSessionStorage {
+    /// The folder holding the blobs of a session.
+    pub(crate) fn blob_folder(&self, session_id: &Id) -> PathBuf {
+        self.folder()
+            .join(format!("{}.blobs", crate::naming::encode_id(session_id)))
+    }
+
+    fn blob_path(&self, session_id: &Id, name: &str) -> session_store::Result<PathBuf> {
+        if !is_valid_blob_name(name) {
+            return Err(session_store::Error::Backend(format!(
+                "Invalid blob name {name:?}"
+            )));
+        }
+        Ok(self.blob_folder(session_id).join(name))
+    }
+
+    /// Store a binary blob alongside a session, replacing any existing blob with the same name.
+    ///
+    /// Useful for data that doesn't belong in the session record, like upload staging. Blobs are
+    /// deleted together with the session. Names may contain ASCII letters, digits, `-`, `_` and
+    /// `.`, but can't start with a `.`.
+    pub async fn put_blob(
+        &self,
+        session_id: &Id,
+        name: &str,
+        bytes: impl AsRef<[u8]>,
+    ) -> session_store::Result<()> {
+        let path = self.blob_path(session_id, name)?;
+        let _guard = self.locks.lock(*session_id).await;
+        self.migrate_session(session_id).await?;
+        if !self.exists(session_id).await? {
+            return Err(session_store::Error::Backend(NO_SUCH_SESSION.to_string()));
+        }
+        let folder = self.blob_folder(session_id);
+        self.fs
+            .create_dir_all(&folder)
+            .await
+            .context("create folder", &folder)?;
+        self.fs
+            .write(&path, bytes.as_ref())
+            .await
+            .context("write blob", &path)
+    }
+
+    /// Read a blob stored with [`put_blob`](Self::put_blob), `None` if it doesn't exist.
+    pub async fn get_blob(
+        &self,
+        session_id: &Id,
+        name: &str,
+    ) -> session_store::Result<Option<Vec<u8>>> {
+        self.ensure_migrated(session_id).await?;
+        let path = self.blob_path(session_id, name)?;
+        match self.fs.read(&path).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(FileError::new("read blob", &path, e).into()),
+        }
+    }
+
+    /// Delete a single blob of a session, does nothing if it doesn't exist.
+    pub async fn delete_blob(&self, session_id: &Id, name: &str) -> session_store::Result<()> {
+        self.ensure_migrated(session_id).await?;
+        let path = self.blob_path(session_id, name)?;
+        match self.fs.remove_file(&path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::new("delete blob", &path, e).into()),
+        }
+    }
+
+    /// List the names of all blobs of a session.
+    pub async fn list_blobs(&self, session_id: &Id) -> session_store::Result<Vec<String>> {
+        self.ensure_migrated(session_id).await?;
+        let mut names = Vec::new();
+        let folder = self.blob_folder(session_id);
+        let mut entries = match crate::fs::read_dir(&self.fs, &folder).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+            Err(e) => return Err(FileError::new("list folder", &folder, e).into()),
+        };
+        while let Some(dir_entry) = entries.next_entry().await.context("list folder", &folder)? {
+            if let Some(name) = dir_entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Delete all blobs of a session, called when the session is removed.
+    pub(crate) async fn remove_blobs(&self, session_id: &Id) -> session_store::Result<()> {
+        let folder = self.blob_folder(session_id);
+        match self.fs.remove_dir_all(&folder).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::new("delete", &folder, e).into()),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! A synchronous version of the store, for CLI tools and frameworks without an async runtime.
+//!
+//! [`FileSessionStorageSync`] uses the same folder layout and file format as
+//! [`FileSessionStorage`], so both can be used on the same folder at the same time.
+
+use std::{borrow::Cow, io, path::Path};
+
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+use crate::FileSessionStorage;
+
+/// A blocking wrapper around [`FileSessionStorage`].
+///
+/// Every method blocks the current thread until the operation finished. Don't call them from
+/// inside an async runtime, use [`FileSessionStorage`] there instead.
+#[derive(Debug, Clone)]
+pub struct FileSessionStorageSync {
+    inner: FileSessionStorage,
+    #[cfg(feature = "tokio")]
+    runtime: std::sync::Arc<tokio::runtime::Runtime>,
+}
+
+impl TryFrom<FileSessionStorage> for FileSessionStorageSync {
+    type Error = io::Error;
+
+    /// Wrap a configured store, failing if the runtime the operations block on can't be
+    /// created.
+    fn try_from(inner: FileSessionStorage) -> io::Result<Self> {
+        Ok(FileSessionStorageSync {
+            inner,
+            #[cfg(feature = "tokio")]
+            runtime: std::sync::Arc::new(
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()?,
+            ),
+        })
+    }
+}
+
+impl FileSessionStorageSync {
+    /// Store sessions in the `.sessions` folder, see [`FileSessionStorage::new`].
+    pub fn new() -> io::Result<Self> {
+        FileSessionStorage::new().try_into()
+    }
+
+    /// Store sessions in a custom folder, see [`FileSessionStorage::new_in_folder`].
+    pub fn new_in_folder(folder: impl Into<Cow<'static, Path>>) -> io::Result<Self> {
+        FileSessionStorage::new_in_folder(folder).try_into()
+    }
+
+    /// The async store this wraps, for the options and tools that have no blocking version.
+    pub fn as_async(&self) -> &FileSessionStorage {
+        &self.inner
+    }
+
+    /// Run a future of the async store to completion on the current thread.
+    pub fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tokio")]
+        return self.runtime.block_on(future);
+        #[cfg(not(feature = "tokio"))]
+        return futures::executor::block_on(future);
+    }
+
+    /// Create a new session, changing the ID of `record` if it is already taken.
+    pub fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.block_on(self.inner.create(record))
+    }
+
+    /// Save a session, creating it if it doesn't exist.
+    pub fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.block_on(self.inner.save(record))
+    }
+
+    /// Load a session, `None` if it doesn't exist or expired.
+    pub fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        self.block_on(self.inner.load(session_id))
+    }
+
+    /// Delete a session, doing nothing if it doesn't exist.
+    pub fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.block_on(self.inner.delete(session_id))
+    }
+
+    /// Delete all expired sessions, see [`FileSessionStorage::set_minimum_expiry_date`].
+    pub fn delete_expired(&self) -> session_store::Result<()> {
+        self.block_on(self.inner.delete_expired())
+    }
+
+    /// Delete expired sessions every `period`, blocking the current thread forever. Failed sweeps
+    /// are logged and tried again the next period.
+    pub fn continuously_delete_expired(
+        &self,
+        period: std::time::Duration,
+    ) -> session_store::Result<()> {
+        loop {
+            std::thread::sleep(period);
+            if let Err(e) = self.delete_expired() {
+                crate::rt::sweep_failed(&e);
+            }
+        }
+    }
+}
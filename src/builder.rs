@@ -0,0 +1,481 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tower_sessions_core::session::Record;
+
+use crate::{
+    deletion::Deletion,
+    hooks::{ExpiredCallback, HookFailure, Hooks},
+    index::{SessionIndex, USER_INDEX},
+    is_valid_folder_name,
+    ttl::TtlPolicies,
+    versions::MAX_KEEP_VERSIONS,
+    ArchiveExpired, Clock, DeletionStrategy, FileNaming, FileSessionStorage, Fs, IndexKey,
+    ReadFallback, SessionStoreHooks, SweepPartition, TtlPolicy,
+};
+
+/// Configures and creates a [`FileSessionStorage`].
+///
+/// Created with [`FileSessionStorage::builder`]. Every option defaults to the same value
+/// [`FileSessionStorage::new`] uses.
+#[derive(Debug, Clone)]
+pub struct FileSessionStorageBuilder {
+    folder_name: Cow<'static, Path>,
+    minimum_expiry_date: Duration,
+    sweep_partition: (u32, u32),
+    cross_process_locking: bool,
+    user_index: Option<String>,
+    indexes: Vec<SessionIndex>,
+    minimum_free_space: u64,
+    legacy_folder: Option<PathBuf>,
+    hooks: Hooks,
+    before_expire_failure: HookFailure,
+    slow_operation_threshold: Option<Duration>,
+    sweep_rate_limit: Option<u32>,
+    track_last_access: bool,
+    clock: Option<Arc<dyn Clock>>,
+    fs: Option<Arc<dyn Fs>>,
+    naming: Option<Arc<dyn FileNaming>>,
+    reject_expired_writes: bool,
+    degrade_on_disk_full: bool,
+    create_rate_limit: Option<(f64, u32)>,
+    circuit_breaker: Option<(u32, Duration)>,
+    quarantine_corrupt: bool,
+    soft_delete: Option<Duration>,
+    keep_versions: u32,
+    deletion: Option<Deletion>,
+    ttl_policies: TtlPolicies,
+    mirror_folder: Option<PathBuf>,
+    read_fallback: ReadFallback,
+}
+
+impl Default for FileSessionStorageBuilder {
+    fn default() -> Self {
+        FileSessionStorageBuilder {
+            folder_name: Cow::Borrowed(Path::new(".sessions")),
+            minimum_expiry_date: Duration::from_secs(60),
+            sweep_partition: (0, 1),
+            cross_process_locking: false,
+            user_index: None,
+            indexes: Vec::new(),
+            minimum_free_space: 0,
+            legacy_folder: None,
+            hooks: Hooks::default(),
+            before_expire_failure: HookFailure::Skip,
+            slow_operation_threshold: None,
+            sweep_rate_limit: None,
+            track_last_access: false,
+            clock: None,
+            fs: None,
+            naming: None,
+            reject_expired_writes: false,
+            degrade_on_disk_full: false,
+            create_rate_limit: None,
+            circuit_breaker: None,
+            quarantine_corrupt: false,
+            soft_delete: None,
+            keep_versions: 0,
+            deletion: None,
+            ttl_policies: TtlPolicies::default(),
+            mirror_folder: None,
+            read_fallback: ReadFallback::FailFast,
+        }
+    }
+}
+
+impl FileSessionStorageBuilder {
+    /// The folder sessions are placed in.
+    pub fn folder(mut self, folder: impl Into<Cow<'static, Path>>) -> Self {
+        self.folder_name = folder.into();
+        self
+    }
+
+    /// See [`FileSessionStorage::set_legacy_folder`].
+    pub fn legacy_folder(mut self, legacy_folder: impl Into<PathBuf>) -> Self {
+        self.legacy_folder = Some(legacy_folder.into());
+        self
+    }
+
+    /// See [`FileSessionStorage::set_minimum_expiry_date`].
+    pub fn minimum_expiry_date(mut self, duration: Duration) -> Self {
+        self.minimum_expiry_date = duration;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_sweep_partition`].
+    pub fn sweep_partition(mut self, index: u32, count: u32) -> Self {
+        self.sweep_partition = (index, count);
+        self
+    }
+
+    /// See [`FileSessionStorage::set_cross_process_locking`].
+    pub fn cross_process_locking(mut self, enabled: bool) -> Self {
+        self.cross_process_locking = enabled;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_user_index`].
+    pub fn user_index(mut self, data_key: impl Into<String>) -> Self {
+        self.user_index = Some(data_key.into());
+        self
+    }
+
+    /// See [`FileSessionStorage::set_minimum_free_space`].
+    pub fn minimum_free_space(mut self, bytes: u64) -> Self {
+        self.minimum_free_space = bytes;
+        self
+    }
+
+    /// See [`FileSessionStorage::add_index`], the name is checked by [`build`](Self::build).
+    pub fn index(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        extractor: impl Fn(&Record) -> Vec<IndexKey> + Send + Sync + 'static,
+    ) -> Self {
+        self.indexes.push(SessionIndex::new(name.into(), extractor));
+        self
+    }
+
+    /// See [`FileSessionStorage::add_hooks`], can be called multiple times.
+    pub fn hooks(mut self, hooks: impl SessionStoreHooks) -> Self {
+        self.hooks.push(hooks);
+        self
+    }
+
+    /// See [`FileSessionStorage::on_expired`], can be called multiple times.
+    pub fn on_expired<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: Fn(Record) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.push(ExpiredCallback(callback));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_before_expire_failure`].
+    pub fn before_expire_failure(mut self, on_failure: HookFailure) -> Self {
+        self.before_expire_failure = on_failure;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_slow_operation_threshold`].
+    pub fn slow_operation_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_operation_threshold = Some(threshold);
+        self
+    }
+
+    /// See [`FileSessionStorage::set_sweep_rate_limit`].
+    pub fn sweep_rate_limit(mut self, per_second: u32) -> Self {
+        self.sweep_rate_limit = Some(per_second);
+        self
+    }
+
+    /// See [`FileSessionStorage::set_track_last_access`].
+    pub fn track_last_access(mut self, enabled: bool) -> Self {
+        self.track_last_access = enabled;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_clock`].
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_fs`].
+    pub fn fs(mut self, fs: impl Fs) -> Self {
+        self.fs = Some(Arc::new(fs));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_file_naming`].
+    pub fn file_naming(mut self, naming: impl FileNaming) -> Self {
+        self.naming = Some(Arc::new(naming));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_reject_expired_writes`].
+    pub fn reject_expired_writes(mut self, enabled: bool) -> Self {
+        self.reject_expired_writes = enabled;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_degrade_on_disk_full`].
+    pub fn degrade_on_disk_full(mut self, enabled: bool) -> Self {
+        self.degrade_on_disk_full = enabled;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_create_rate_limit`].
+    pub fn create_rate_limit(mut self, per_second: f64, burst: u32) -> Self {
+        self.create_rate_limit = Some((per_second, burst));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_circuit_breaker`].
+    pub fn circuit_breaker(mut self, failures: u32, cool_down: Duration) -> Self {
+        self.circuit_breaker = Some((failures, cool_down));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_quarantine_corrupt_sessions`].
+    pub fn quarantine_corrupt_sessions(mut self, enabled: bool) -> Self {
+        self.quarantine_corrupt = enabled;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_soft_delete`].
+    pub fn soft_delete(mut self, retention: Duration) -> Self {
+        self.soft_delete = Some(retention);
+        self
+    }
+
+    /// See [`FileSessionStorage::set_keep_versions`].
+    pub fn keep_versions(mut self, count: u32) -> Self {
+        self.keep_versions = count;
+        self
+    }
+
+    /// See [`FileSessionStorage::set_archive_expired`].
+    pub fn archive_expired(self, options: ArchiveExpired) -> Self {
+        self.deletion_strategy(options)
+    }
+
+    /// See [`FileSessionStorage::set_ttl_class`].
+    pub fn ttl_class(
+        mut self,
+        classify: impl Fn(&Record) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.ttl_policies.set_classify(classify);
+        self
+    }
+
+    /// See [`FileSessionStorage::add_ttl_policy`], can be called multiple times.
+    pub fn ttl_policy(mut self, class: impl Into<String>, policy: TtlPolicy) -> Self {
+        self.ttl_policies.insert(class.into(), policy);
+        self
+    }
+
+    /// See [`FileSessionStorage::set_deletion_strategy`].
+    pub fn deletion_strategy(mut self, strategy: impl DeletionStrategy) -> Self {
+        self.deletion = Some(Deletion(Arc::new(strategy)));
+        self
+    }
+
+    /// See [`FileSessionStorage::set_mirror_folder`].
+    pub fn mirror_folder(mut self, mirror_folder: impl Into<PathBuf>) -> Self {
+        self.mirror_folder = Some(mirror_folder.into());
+        self
+    }
+
+    /// See [`FileSessionStorage::set_read_fallback`].
+    pub fn read_fallback(mut self, read_fallback: ReadFallback) -> Self {
+        self.read_fallback = read_fallback;
+        self
+    }
+
+    /// Check the configuration and create the store.
+    pub fn build(self) -> Result<FileSessionStorage, BuildError> {
+        let (index, count) = self.sweep_partition;
+        if index >= count {
+            return Err(BuildError::InvalidSweepPartition { index, count });
+        }
+        if self.folder_name.as_os_str().is_empty() {
+            return Err(BuildError::EmptyFolder);
+        }
+        if self.legacy_folder.as_deref() == Some(self.folder_name.as_ref()) {
+            return Err(BuildError::LegacyFolderIsFolder);
+        }
+        if self.mirror_folder.as_deref() == Some(self.folder_name.as_ref()) {
+            return Err(BuildError::MirrorFolderIsFolder);
+        }
+        if cfg!(target_os = "wasi") && self.cross_process_locking && self.fs.is_none() {
+            return Err(BuildError::CrossProcessLockingUnsupported);
+        }
+        let mut names: Vec<&str> = self.indexes.iter().map(SessionIndex::name).collect();
+        if self.user_index.is_some() {
+            names.push(USER_INDEX);
+        }
+        names.sort_unstable();
+        if let Some(name) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(BuildError::DuplicateIndexName(name[0].to_string()));
+        }
+        if let Some((per_second, burst)) = self.create_rate_limit {
+            if !per_second.is_finite() || per_second <= 0.0 || burst == 0 {
+                return Err(BuildError::InvalidCreateRateLimit);
+            }
+        }
+        if self
+            .circuit_breaker
+            .is_some_and(|(failures, _)| failures == 0)
+        {
+            return Err(BuildError::InvalidCircuitBreaker);
+        }
+        if self.keep_versions > MAX_KEEP_VERSIONS {
+            return Err(BuildError::TooManyVersions(self.keep_versions));
+        }
+        self.ttl_policies.validate()?;
+
+        let mut storage = FileSessionStorage::new_in_folder(self.folder_name);
+        storage = storage.set_minimum_expiry_date(self.minimum_expiry_date);
+        storage.sweep_partition = SweepPartition { index, count };
+        storage.cross_process_locking = self.cross_process_locking;
+        storage.minimum_free_space = self.minimum_free_space;
+        storage.hooks = self.hooks;
+        storage.before_expire_failure = self.before_expire_failure;
+        if let Some(threshold) = self.slow_operation_threshold {
+            storage = storage.set_slow_operation_threshold(threshold);
+        }
+        if let Some(per_second) = self.sweep_rate_limit {
+            storage = storage.set_sweep_rate_limit(per_second);
+        }
+        storage.track_last_access = self.track_last_access;
+        storage.reject_expired_writes = self.reject_expired_writes;
+        storage.degrade_on_disk_full = self.degrade_on_disk_full;
+        storage.quarantine_corrupt = self.quarantine_corrupt;
+        storage.soft_delete = self.soft_delete;
+        storage.keep_versions = self.keep_versions;
+        storage.ttl_policies = self.ttl_policies;
+        if let Some(deletion) = self.deletion {
+            storage.deletion = deletion;
+        }
+        if let Some((failures, cool_down)) = self.circuit_breaker {
+            storage = storage.set_circuit_breaker(failures, cool_down);
+        }
+        if let Some((per_second, burst)) = self.create_rate_limit {
+            storage = storage.set_create_rate_limit(per_second, burst);
+        }
+        if let Some(clock) = self.clock {
+            storage.clock = clock;
+        }
+        if let Some(fs) = self.fs {
+            storage.fs = fs;
+        }
+        if let Some(naming) = self.naming {
+            storage.naming = naming;
+        }
+        if let Some(legacy_folder) = self.legacy_folder {
+            storage = storage.set_legacy_folder(legacy_folder);
+        }
+        if let Some(mirror_folder) = self.mirror_folder {
+            storage = storage.set_mirror_folder(mirror_folder);
+        }
+        storage.read_fallback = self.read_fallback;
+        if let Some(data_key) = self.user_index {
+            storage = storage.set_user_index(data_key);
+        }
+        for index in self.indexes {
+            if !is_valid_folder_name(index.name()) {
+                return Err(BuildError::InvalidIndexName(index.name().to_string()));
+            }
+            storage.insert_index(index);
+        }
+        Ok(storage)
+    }
+}
+
+/// The options passed to [`FileSessionStorageBuilder`] can't be combined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// The sweep partition index is not less than the number of partitions.
+    InvalidSweepPartition {
+        /// The index of this instance.
+        index: u32,
+        /// The total number of instances.
+        count: u32,
+    },
+    /// An index name is empty or contains characters other than ASCII letters, digits, `-` and
+    /// `_`.
+    InvalidIndexName(String),
+    /// Two indexes have the same name, or an index is named `user` next to
+    /// [`user_index`](FileSessionStorageBuilder::user_index).
+    DuplicateIndexName(String),
+    /// The folder is an empty path.
+    EmptyFolder,
+    /// The legacy folder is the same as the folder, so sessions would be moved onto themselves.
+    LegacyFolderIsFolder,
+    /// The mirror folder is the same as the folder, so sessions would be copied onto themselves.
+    MirrorFolderIsFolder,
+    /// Cross process locking was enabled on a platform without file locks, like WASI.
+    CrossProcessLockingUnsupported,
+    /// An environment variable read by [`FileSessionStorageBuilder::from_env`] can't be parsed.
+    InvalidEnvVar {
+        /// The name of the variable.
+        name: &'static str,
+        /// Its value.
+        value: String,
+    },
+    /// The create rate limit isn't a positive number of sessions per second, or its burst is
+    /// zero.
+    InvalidCreateRateLimit,
+    /// The circuit breaker opens after zero failures.
+    InvalidCircuitBreaker,
+    /// More than [`MAX_KEEP_VERSIONS`] versions are kept.
+    TooManyVersions(u32),
+    /// A TTL policy has no limits or a limit of zero, or policies were added without a
+    /// [`ttl_class`](FileSessionStorageBuilder::ttl_class) to sort sessions into them.
+    InvalidTtlPolicy(String),
+    /// A [`ShardedNaming`](crate::ShardedNaming) has folders of width 0, or its folders need more
+    /// characters than a session ID has.
+    InvalidShardedNaming {
+        /// The number of nested folders.
+        depth: usize,
+        /// The characters of the ID each folder is named after.
+        width: usize,
+    },
+    /// A [`ShardedStore`](crate::ShardedStore) was created without any roots.
+    NoShardRoots,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::InvalidSweepPartition { index, count } => write!(
+                f,
+                "sweep partition index {index} must be less than the partition count {count}"
+            ),
+            BuildError::InvalidIndexName(name) => write!(f, "invalid index name {name:?}"),
+            BuildError::DuplicateIndexName(name) => write!(f, "duplicate index name {name:?}"),
+            BuildError::EmptyFolder => write!(f, "the sessions folder must not be empty"),
+            BuildError::LegacyFolderIsFolder => {
+                write!(f, "the legacy folder must differ from the sessions folder")
+            }
+            BuildError::MirrorFolderIsFolder => {
+                write!(f, "the mirror folder must differ from the sessions folder")
+            }
+            BuildError::CrossProcessLockingUnsupported => {
+                write!(f, "cross process locking is not supported on this platform")
+            }
+            BuildError::InvalidEnvVar { name, value } => {
+                write!(f, "invalid value {value:?} for environment variable {name}")
+            }
+            BuildError::InvalidCreateRateLimit => write!(
+                f,
+                "the create rate limit must be a positive number with a burst of at least 1"
+            ),
+            BuildError::InvalidCircuitBreaker => {
+                write!(f, "the circuit breaker must allow at least 1 failure")
+            }
+            BuildError::TooManyVersions(count) => write!(
+                f,
+                "can't keep {count} versions, at most {MAX_KEEP_VERSIONS} are supported"
+            ),
+            BuildError::InvalidTtlPolicy(message) => write!(f, "invalid TTL policy: {message}"),
+            BuildError::InvalidShardedNaming { depth, width } => write!(
+                f,
+                "invalid sharded naming with {depth} folders of {width} characters, folders need at \
+                 least 1 character and together at most the length of a session ID"
+            ),
+            BuildError::NoShardRoots => write!(f, "a sharded store needs at least one root"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
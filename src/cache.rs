@@ -0,0 +1,236 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, CachingSessionStore, SessionStore,
+};
+
+use crate::{FileSessionStorage, SessionEvent};
+
+/// A bounded in-memory cache in front of a [`FileSessionStorage`], created with
+/// [`FileSessionStorage::with_memory_cache`].
+///
+/// Once the cache is full the session that was cached first is dropped. Sessions deleted or
+/// expired through the store or any of its clones are dropped from the cache as well, changes
+/// made by other processes are only seen once a session drops out of the cache. Records are
+/// cached with the expiry date the store writes them with, after the
+/// [TTL policy](FileSessionStorage::add_ttl_policy) of their class.
+#[derive(Debug, Clone)]
+pub struct SessionCache {
+    state: Arc<Mutex<CacheState>>,
+    store: FileSessionStorage,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    /// Each record with when the session was created if it is known, for TTL policies, and the
+    /// generation it was inserted in.
+    records: HashMap<Id, (Record, Option<OffsetDateTime>, u64)>,
+    /// Insertion order, entries whose generation doesn't match `records` are stale.
+    order: VecDeque<(Id, u64)>,
+    generation: u64,
+    events: broadcast::Receiver<SessionEvent>,
+}
+
+impl CacheState {
+    /// Forget sessions the store deleted since the last call.
+    fn apply_events(&mut self) {
+        loop {
+            match self.events.try_recv() {
+                Ok(SessionEvent::Deleted(session_id) | SessionEvent::Expired(session_id)) => {
+                    self.records.remove(&session_id);
+                }
+                Ok(_) => {}
+                // Missed some deletions, the only safe option is to start over
+                Err(TryRecvError::Lagged(_)) => {
+                    self.records.clear();
+                    self.order.clear();
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn insert(&mut self, record: Record, created_at: Option<OffsetDateTime>, capacity: usize) {
+        self.generation += 1;
+        let session_id = record.id;
+        self.records
+            .insert(session_id, (record, created_at, self.generation));
+        self.order.push_back((session_id, self.generation));
+        while self.records.len() > capacity {
+            let Some((session_id, generation)) = self.order.pop_front() else {
+                break;
+            };
+            if self.records.get(&session_id).map(|(_, _, g)| *g) == Some(generation) {
+                self.records.remove(&session_id);
+            }
+        }
+        // Drop stale entries so the queue doesn't grow with every save of the same session
+        if self.order.len() > capacity.saturating_mul(2) {
+            let records = &self.records;
+            self.order.retain(|(session_id, generation)| {
+                records.get(session_id).map(|(_, _, g)| g) == Some(generation)
+            });
+        }
+    }
+}
+
+impl SessionCache {
+    fn with_state<T>(&self, f: impl FnOnce(&mut CacheState) -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        state.apply_events();
+        f(&mut state)
+    }
+
+    /// Cache `record` with the TTL policy of its class applied, like the store writes it.
+    fn insert(&self, record: &Record, created_at: Option<OffsetDateTime>) {
+        let capped = match created_at {
+            Some(created_at) => self.store.apply_ttl_policy(record, created_at),
+            None => std::borrow::Cow::Borrowed(record),
+        };
+        let capped = capped.into_owned();
+        let capacity = self.store.memory_cache_capacity();
+        self.with_state(|state| state.insert(capped, created_at, capacity));
+    }
+}
+
+#[async_trait]
+impl SessionStore for SessionCache {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.insert(record, Some(self.store.now_utc()));
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        if !self.store.has_ttl_policy(record) {
+            self.insert(record, None);
+            return Ok(());
+        }
+        let cached = self.with_state(|state| {
+            state
+                .records
+                .get(&record.id)
+                .and_then(|(_, created_at, _)| *created_at)
+        });
+        let created_at = match cached {
+            Some(created_at) => Ok(Some(created_at)),
+            None => {
+                let path = self.store.session_path(&record.id);
+                self.store.created_at_of(&path).await
+            }
+        };
+        match created_at {
+            Ok(created_at) => self.insert(
+                record,
+                Some(created_at.unwrap_or_else(|| self.store.now_utc())),
+            ),
+            // Not knowing the expiry date the store writes, leave the session to the store
+            Err(_) => {
+                self.with_state(|state| state.records.remove(&record.id));
+            }
+        }
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let now = self.store.now_utc();
+        Ok(
+            self.with_state(|state| match state.records.get(session_id) {
+                Some((record, _, _)) if record.expiry_date < now => {
+                    state.records.remove(session_id);
+                    None
+                }
+                Some((record, _, _)) => Some(record.clone()),
+                None => None,
+            }),
+        )
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.with_state(|state| state.records.remove(session_id));
+        Ok(())
+    }
+}
+
+impl FileSessionStorage {
+    /// Put an in-memory cache of up to `capacity` sessions in front of the store, using
+    /// tower-sessions' [`CachingSessionStore`].
+    ///
+    /// `CachingSessionStore` doesn't implement `ExpiredDeletion`, so keep a clone of this store
+    /// for the deletion task. Sessions it deletes are dropped from the cache. Caches of the store
+    /// and its clones share the capacity, change it with
+    /// [`update_memory_cache_capacity`](Self::update_memory_cache_capacity).
+    pub fn with_memory_cache(
+        &self,
+        capacity: usize,
+    ) -> CachingSessionStore<SessionCache, FileSessionStorage> {
+        self.update_memory_cache_capacity(capacity);
+        let cache = SessionCache {
+            state: Arc::new(Mutex::new(CacheState {
+                records: HashMap::new(),
+                order: VecDeque::new(),
+                generation: 0,
+                events: self.subscribe(),
+            })),
+            store: self.clone(),
+        };
+        CachingSessionStore::new(cache, self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn cached_sessions_load_without_the_disk() {
+        let (store, fs, clock) = store();
+        let cached = store.with_memory_cache(1);
+        let mut first = record(&clock, Duration::from_secs(60));
+        cached.create(&mut first).await.unwrap();
+        let mut second = record(&clock, Duration::from_secs(60));
+        cached.create(&mut second).await.unwrap();
+
+        fs.fail_reads(Some(std::io::ErrorKind::Other));
+        assert_eq!(cached.load(&second.id).await.unwrap(), Some(second));
+        // Dropped from the cache to make room
+        assert!(cached.load(&first.id).await.is_err());
+        fs.fail_reads(None);
+        assert_eq!(cached.load(&first.id).await.unwrap(), Some(first));
+    }
+
+    #[tokio::test]
+    async fn sessions_deleted_through_the_store_leave_the_cache() {
+        let (store, _, clock) = store();
+        let cached = store.with_memory_cache(10);
+        let mut session = record(&clock, Duration::from_secs(60));
+        cached.create(&mut session).await.unwrap();
+        assert_eq!(
+            cached.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+
+        store.delete(&session.id).await.unwrap();
+        assert_eq!(cached.load(&session.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_not_served_from_the_cache() {
+        let (store, _, clock) = store();
+        let cached = store.with_memory_cache(10);
+        let mut session = record(&clock, Duration::from_secs(60));
+        cached.create(&mut session).await.unwrap();
+
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(cached.load(&session.id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+/// Wraps any store and makes it fail, slow down or write partial sessions, so applications can
+/// test how they cope with session store outages. Only meant for tests.
+///
+/// Failures are random but reproducible with [`set_seed`](Self::set_seed). Clones share the same
+/// configuration, so a test can keep a clone to [`set_outage`](Self::set_outage) while the
+/// application uses the store.
+#[derive(Debug, Clone)]
+pub struct ChaosStore<S> {
+    inner: S,
+    state: Arc<Mutex<ChaosState>>,
+}
+
+#[derive(Debug)]
+struct ChaosState {
+    failure_rate: f64,
+    partial_write_rate: f64,
+    latency: Duration,
+    outage: bool,
+    rng: u64,
+}
+
+/// What happens to an operation, decided before it runs.
+enum Fate {
+    Fail,
+    PartialWrite,
+    Run,
+}
+
+impl ChaosState {
+    /// The next random number in `0..1`, from xorshift64*.
+    fn next_f64(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let value = self.rng.wrapping_mul(0x2545f4914f6cdd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn injected(operation: &str) -> session_store::Error {
+    session_store::Error::Backend(format!("Injected failure during {operation}"))
+}
+
+impl<S: SessionStore> ChaosStore<S> {
+    /// Wrap `inner`, which behaves normally until a failure mode is configured.
+    pub fn new(inner: S) -> Self {
+        ChaosStore {
+            inner,
+            state: Arc::new(Mutex::new(ChaosState {
+                failure_rate: 0.0,
+                partial_write_rate: 0.0,
+                latency: Duration::ZERO,
+                outage: false,
+                rng: 0x9e3779b97f4a7c15,
+            })),
+        }
+    }
+
+    /// The wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Make a share of all operations fail without reaching the inner store, from `0.0` for none
+    /// to `1.0` for all.
+    pub fn set_failure_rate(self, rate: f64) -> Self {
+        self.state.lock().unwrap().failure_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Make a share of `create` and `save` calls write only part of the session data and then
+    /// fail, like a crash in the middle of a write.
+    pub fn set_partial_write_rate(self, rate: f64) -> Self {
+        self.state.lock().unwrap().partial_write_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Wait `latency` before every operation.
+    pub fn set_latency(self, latency: Duration) -> Self {
+        self.state.lock().unwrap().latency = latency;
+        self
+    }
+
+    /// Seed the random failures, so a failing test can be replayed.
+    pub fn set_seed(self, seed: u64) -> Self {
+        // xorshift gets stuck at 0
+        self.state.lock().unwrap().rng = seed.max(1);
+        self
+    }
+
+    /// Make every operation fail until called with `false`, like the store being unreachable.
+    pub fn set_outage(&self, outage: bool) {
+        self.state.lock().unwrap().outage = outage;
+    }
+
+    /// Wait for the latency, then decide what happens to the next operation.
+    async fn fate(&self, write: bool) -> Fate {
+        let latency = self.state.lock().unwrap().latency;
+        if !latency.is_zero() {
+            crate::rt::sleep(latency).await;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.outage || state.next_f64() < state.failure_rate {
+            Fate::Fail
+        } else if write && state.next_f64() < state.partial_write_rate {
+            Fate::PartialWrite
+        } else {
+            Fate::Run
+        }
+    }
+
+    /// `record` with only the first half of its data.
+    fn partial(record: &Record) -> Record {
+        let mut partial = record.clone();
+        let keep = partial.data.len() / 2;
+        let mut keys: Vec<String> = partial.data.keys().cloned().collect();
+        keys.sort_unstable();
+        for key in &keys[keep..] {
+            partial.data.remove(key);
+        }
+        partial
+    }
+}
+
+#[async_trait]
+impl<S: SessionStore> SessionStore for ChaosStore<S> {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self.fate(true).await {
+            Fate::Fail => Err(injected("create")),
+            Fate::PartialWrite => {
+                let mut partial = Self::partial(record);
+                self.inner.create(&mut partial).await?;
+                record.id = partial.id;
+                Err(injected("create"))
+            }
+            Fate::Run => self.inner.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self.fate(true).await {
+            Fate::Fail => Err(injected("save")),
+            Fate::PartialWrite => {
+                self.inner.save(&Self::partial(record)).await?;
+                Err(injected("save"))
+            }
+            Fate::Run => self.inner.save(record).await,
+        }
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        match self.fate(false).await {
+            Fate::Fail => Err(injected("load")),
+            _ => self.inner.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        match self.fate(false).await {
+            Fate::Fail => Err(injected("delete")),
+            _ => self.inner.delete(session_id).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: SessionStore + ExpiredDeletion> ExpiredDeletion for ChaosStore<S> {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        match self.fate(false).await {
+            Fate::Fail => Err(injected("delete_expired")),
+            _ => self.inner.delete_expired().await,
+        }
+    }
+
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn outage_fails_every_operation() {
+        let (inner, _, clock) = store();
+        let chaos = ChaosStore::new(inner);
+        let mut session = record(&clock, Duration::from_secs(60));
+        chaos.create(&mut session).await.unwrap();
+
+        chaos.set_outage(true);
+        assert!(chaos.load(&session.id).await.is_err());
+        assert!(chaos.save(&session).await.is_err());
+        assert!(chaos.delete(&session.id).await.is_err());
+        assert!(chaos.delete_expired().await.is_err());
+
+        chaos.set_outage(false);
+        assert_eq!(chaos.load(&session.id).await.unwrap(), Some(session));
+    }
+
+    #[tokio::test]
+    async fn failures_are_reproducible_with_a_seed() {
+        async fn outcomes(seed: u64) -> Vec<bool> {
+            let (inner, _, _) = store();
+            let chaos = ChaosStore::new(inner).set_failure_rate(0.5).set_seed(seed);
+            let mut outcomes = Vec::new();
+            for _ in 0..32 {
+                outcomes.push(chaos.load(&Id::default()).await.is_ok());
+            }
+            outcomes
+        }
+
+        let first = outcomes(7).await;
+        assert_eq!(first, outcomes(7).await);
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+
+    #[tokio::test]
+    async fn partial_writes_keep_half_the_data() {
+        let (inner, _, clock) = store();
+        let chaos = ChaosStore::new(inner).set_partial_write_rate(1.0);
+        let mut session = record(&clock, Duration::from_secs(60));
+        for key in ["a", "b", "c", "d"] {
+            session.data.insert(key.to_string(), true.into());
+        }
+
+        assert!(chaos.create(&mut session).await.is_err());
+        let written = chaos.inner().load(&session.id).await.unwrap().unwrap();
+        let mut keys: Vec<_> = written.data.keys().cloned().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["a", "b"]);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tower_sessions_core::session_store;
+
+use crate::{FileSessionStorage, SessionEvent};
+
+/// Start of the message of errors returned while the circuit breaker is open, see
+/// [`FileSessionStorage::is_circuit_open_error`].
+const CIRCUIT_OPEN_PREFIX: &str = "Circuit open: ";
+
+/// The circuit breaker set with [`FileSessionStorage::set_circuit_breaker`], shared between
+/// clones of a store.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cool_down: Duration,
+    consecutive_failures: u32,
+    /// When the breaker opened or last let an operation through to test the disk, `None` while
+    /// closed.
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// A closed breaker with the same settings, for a store that shouldn't share this one.
+    pub(crate) fn fresh(&self) -> Self {
+        CircuitBreaker {
+            consecutive_failures: 0,
+            opened_at: None,
+            ..*self
+        }
+    }
+}
+
+impl FileSessionStorage {
+    /// Fail every operation right away for `cool_down` after `failures` operations in a row
+    /// failed, so a dying disk doesn't add its timeouts to every request.
+    ///
+    /// Applications can fall back to anonymous sessions while it is open, recognize its errors
+    /// with [`is_circuit_open_error`](Self::is_circuit_open_error).
+    /// [`SessionEvent::CircuitOpened`] is sent when it opens. After the cool-down one operation
+    /// is let through, if it succeeds the breaker closes and [`SessionEvent::CircuitClosed`] is
+    /// sent, otherwise it stays open for another cool-down. Only failed file system operations
+    /// count, not sessions that fail to decode, missing sessions, refused writes or a full disk.
+    /// The cool-down is measured with the monotonic clock, not the clock set with
+    /// [`set_clock`](Self::set_clock). Clones share the breaker. Disabled by default.
+    pub fn set_circuit_breaker(mut self, failures: u32, cool_down: Duration) -> Self {
+        self.circuit_breaker = Some(Arc::new(Mutex::new(CircuitBreaker {
+            threshold: failures.max(1),
+            cool_down,
+            consecutive_failures: 0,
+            opened_at: None,
+        })));
+        self
+    }
+
+    /// Whether the breaker set with [`set_circuit_breaker`](Self::set_circuit_breaker) is open.
+    pub fn is_circuit_open(&self) -> bool {
+        self.circuit_breaker
+            .as_ref()
+            .is_some_and(|breaker| breaker.lock().unwrap().opened_at.is_some())
+    }
+
+    /// Whether `error` was returned without trying the operation because the breaker set with
+    /// [`set_circuit_breaker`](Self::set_circuit_breaker) is open.
+    ///
+    /// `session_store::Error` can only hold a message, these errors are recognized by their
+    /// `Circuit open: ` prefix.
+    pub fn is_circuit_open_error(error: &session_store::Error) -> bool {
+        matches!(error, session_store::Error::Backend(message) if message.starts_with(CIRCUIT_OPEN_PREFIX))
+    }
+
+    /// Run an operation unless the breaker is open, and update the breaker with its result.
+    pub(crate) async fn guard_circuit<T>(
+        &self,
+        operation: impl Future<Output = session_store::Result<T>>,
+    ) -> session_store::Result<T> {
+        let Some(breaker) = &self.circuit_breaker else {
+            return operation.await;
+        };
+        {
+            let mut breaker = breaker.lock().unwrap();
+            if let Some(opened_at) = breaker.opened_at {
+                if opened_at.elapsed() < breaker.cool_down {
+                    return Err(session_store::Error::Backend(format!(
+                        "{CIRCUIT_OPEN_PREFIX}too many failed operations"
+                    )));
+                }
+                // Let this one through, the others wait for another cool-down in case it hangs
+                breaker.opened_at = Some(Instant::now());
+            }
+        }
+
+        let result = operation.await;
+        // Other errors, like a refused write, say nothing about the disk either way
+        let worked = match &result {
+            Ok(_) | Err(session_store::Error::Decode(_)) => Some(true),
+            Err(e) if crate::error::is_io_error(e) => Some(false),
+            Err(_) => None,
+        };
+        let mut breaker = breaker.lock().unwrap();
+        if worked == Some(false) {
+            breaker.consecutive_failures = breaker.consecutive_failures.saturating_add(1);
+            if breaker.opened_at.is_none() && breaker.consecutive_failures >= breaker.threshold {
+                breaker.opened_at = Some(Instant::now());
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    failures = breaker.consecutive_failures,
+                    "too many failed operations, opening the circuit breaker"
+                );
+                self.emit(SessionEvent::CircuitOpened);
+            }
+        } else if worked == Some(true) {
+            breaker.consecutive_failures = 0;
+            if breaker.opened_at.take().is_some() {
+                self.emit(SessionEvent::CircuitClosed);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use tower_sessions_core::{session::Id, SessionStore};
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn opens_after_consecutive_failures() {
+        let (store, fs, _) = store();
+        let store = store.set_circuit_breaker(2, Duration::from_secs(3600));
+        fs.fail_reads(Some(io::ErrorKind::Other));
+
+        for _ in 0..2 {
+            let error = store.load(&Id::default()).await.unwrap_err();
+            assert!(!FileSessionStorage::is_circuit_open_error(&error));
+        }
+        assert!(store.is_circuit_open());
+
+        fs.fail_reads(None);
+        let error = store.load(&Id::default()).await.unwrap_err();
+        assert!(FileSessionStorage::is_circuit_open_error(&error));
+    }
+
+    #[tokio::test]
+    async fn closes_when_an_operation_works_after_the_cool_down() {
+        let (store, fs, _) = store();
+        let store = store.set_circuit_breaker(1, Duration::ZERO);
+        let mut events = store.subscribe();
+        fs.fail_reads(Some(io::ErrorKind::Other));
+        store.load(&Id::default()).await.unwrap_err();
+        assert!(store.is_circuit_open());
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::CircuitOpened);
+
+        fs.fail_reads(None);
+        assert_eq!(store.load(&Id::default()).await.unwrap(), None);
+        assert!(!store.is_circuit_open());
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::CircuitClosed);
+    }
+
+    #[tokio::test]
+    async fn success_resets_the_failure_count() {
+        let (store, fs, _) = store();
+        let store = store.set_circuit_breaker(2, Duration::from_secs(3600));
+        for _ in 0..3 {
+            fs.fail_reads(Some(io::ErrorKind::Other));
+            store.load(&Id::default()).await.unwrap_err();
+            fs.fail_reads(None);
+            store.load(&Id::default()).await.unwrap();
+        }
+        assert!(!store.is_circuit_open());
+    }
+
+    #[tokio::test]
+    async fn refused_writes_dont_count() {
+        let (store, _, clock) = store();
+        let store = store
+            .set_circuit_breaker(1, Duration::from_secs(3600))
+            .set_create_rate_limit(0.0, 1);
+        let mut record = record(&clock, Duration::from_secs(60));
+        store.create(&mut record).await.unwrap();
+        store.create(&mut record).await.unwrap_err();
+        assert!(!store.is_circuit_open());
+    }
+}
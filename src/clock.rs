@@ -0,0 +1,83 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use time::OffsetDateTime;
+
+use crate::FileSessionStorage;
+
+/// The source of the current time the store compares expiry dates and file ages against.
+///
+/// Replace it with [`FileSessionStorage::set_clock`] or
+/// [`FileSessionStorageBuilder::clock`](crate::FileSessionStorageBuilder::clock), for example with
+/// a [`ManualClock`] in tests. Durations of operations are still measured with the real clock.
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    /// The current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The real time, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to, so tests can fast-forward past expiry dates and the
+/// minimum expiry age without sleeping.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new(SystemTime::now())
+    }
+}
+
+impl ManualClock {
+    /// Create a clock standing still at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock(Arc::new(Mutex::new(now)))
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+
+    /// Set the clock to `now`, which may be in the past.
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl FileSessionStorage {
+    /// Use `clock` instead of the system time to decide which sessions expired.
+    pub fn set_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The current time according to the store's clock.
+    pub(crate) fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// The current time according to the store's clock, as an [`OffsetDateTime`].
+    pub(crate) fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::from(self.clock.now())
+    }
+}
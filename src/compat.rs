@@ -0,0 +1,99 @@
+//! `SessionStore` implementations for other versions of tower-sessions-core.
+//!
+//! Records are converted to the version the store is built on and back, the on-disk format is
+//! the same for every version.
+
+macro_rules! compat_impl {
+    ($module:ident, $core:ident) => {
+        mod $module {
+            use async_trait::async_trait;
+            use $core::{
+                session::{Id, Record},
+                session_store, ExpiredDeletion, SessionStore,
+            };
+
+            use crate::FileSessionStorage;
+
+            fn to_current(record: &Record) -> tower_sessions_core::session::Record {
+                tower_sessions_core::session::Record {
+                    id: tower_sessions_core::session::Id(record.id.0),
+                    data: record.data.clone(),
+                    expiry_date: record.expiry_date,
+                }
+            }
+
+            fn from_current(record: tower_sessions_core::session::Record) -> Record {
+                Record {
+                    id: Id(record.id.0),
+                    data: record.data,
+                    expiry_date: record.expiry_date,
+                }
+            }
+
+            fn from_current_error(
+                error: tower_sessions_core::session_store::Error,
+            ) -> session_store::Error {
+                match error {
+                    tower_sessions_core::session_store::Error::Encode(message) => {
+                        session_store::Error::Encode(message)
+                    }
+                    tower_sessions_core::session_store::Error::Decode(message) => {
+                        session_store::Error::Decode(message)
+                    }
+                    tower_sessions_core::session_store::Error::Backend(message) => {
+                        session_store::Error::Backend(message)
+                    }
+                }
+            }
+
+            #[async_trait]
+            impl SessionStore for FileSessionStorage {
+                async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+                    let mut current = to_current(record);
+                    tower_sessions_core::SessionStore::create(self, &mut current)
+                        .await
+                        .map_err(from_current_error)?;
+                    record.id = Id(current.id.0);
+                    Ok(())
+                }
+
+                async fn save(&self, record: &Record) -> session_store::Result<()> {
+                    tower_sessions_core::SessionStore::save(self, &to_current(record))
+                        .await
+                        .map_err(from_current_error)
+                }
+
+                async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+                    let session_id = tower_sessions_core::session::Id(session_id.0);
+                    tower_sessions_core::SessionStore::load(self, &session_id)
+                        .await
+                        .map(|record| record.map(from_current))
+                        .map_err(from_current_error)
+                }
+
+                async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+                    let session_id = tower_sessions_core::session::Id(session_id.0);
+                    tower_sessions_core::SessionStore::delete(self, &session_id)
+                        .await
+                        .map_err(from_current_error)
+                }
+            }
+
+            #[async_trait]
+            impl ExpiredDeletion for FileSessionStorage {
+                async fn delete_expired(&self) -> session_store::Result<()> {
+                    tower_sessions_core::ExpiredDeletion::delete_expired(self)
+                        .await
+                        .map_err(from_current_error)
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "tower-sessions-012")]
+compat_impl!(v012, tower_sessions_core_012);
+#[cfg(feature = "tower-sessions-014")]
+compat_impl!(v014, tower_sessions_core_014);
+#[cfg(feature = "tower-sessions-015")]
+compat_impl!(v015, tower_sessions_core_015);
@@ -0,0 +1,194 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use time::OffsetDateTime;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{error::FileError, FileMetadata, FileSessionStorage};
+
+/// Identifies one version of a session file, based on its modified date and size.
+///
+/// Returned by [`FileSessionStorage::load_if_modified_since`] and passed back on the next call to
+/// skip loading a session that hasn't changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModificationToken {
+    version: u64,
+    /// The expiry date of the session when the token was handed out, so an unchanged session
+    /// isn't reported as not modified after it expired.
+    expiry_date: OffsetDateTime,
+}
+
+impl ModificationToken {
+    /// The version of the session file `metadata` describes.
+    fn version(metadata: &FileMetadata) -> Option<u64> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        let mut hasher = DefaultHasher::new();
+        modified.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        Some(hasher.finish())
+    }
+}
+
+/// The result of [`FileSessionStorage::load_if_modified_since`].
+#[derive(Debug, Clone)]
+pub enum ConditionalLoad {
+    /// The session file still matches the given token.
+    NotModified,
+    /// The session changed, this is the current record and the token to use next time.
+    Modified(Record, ModificationToken),
+    /// There is no session with this ID.
+    NotFound,
+}
+
+impl FileSessionStorage {
+    /// Load a session, unless the file hasn't changed since `token` was handed out.
+    ///
+    /// Useful for caching layers that want to avoid deserializing sessions that didn't change.
+    /// A session that expired since is never reported as not modified, it is removed like
+    /// [`load`](tower_sessions_core::SessionStore::load) does and reported as not found.
+    pub async fn load_if_modified_since(
+        &self,
+        session_id: &Id,
+        token: Option<ModificationToken>,
+    ) -> session_store::Result<ConditionalLoad> {
+        let path = self.session_path(session_id);
+        // Taken before the session is read, so a save in between only causes another load
+        let version = self.file_version(&path).await?;
+        if let (Some(token), Some(version)) = (token, version) {
+            if self.now_utc() <= token.expiry_date && token.version == version {
+                return Ok(ConditionalLoad::NotModified);
+            }
+        }
+
+        let Some(record) = self.read_record(session_id).await? else {
+            return Ok(ConditionalLoad::NotFound);
+        };
+        if self.is_expired(&record) {
+            self.expire_session(&record).await?;
+            return Ok(ConditionalLoad::NotFound);
+        }
+        let version = match version {
+            Some(version) => version,
+            // The file was only now moved there from an older name or the mirror
+            None => self.file_version(&path).await?.ok_or_else(|| {
+                FileError::new(
+                    "get metadata of",
+                    &path,
+                    std::io::ErrorKind::NotFound.into(),
+                )
+            })?,
+        };
+        let token = ModificationToken {
+            version,
+            expiry_date: record.expiry_date,
+        };
+        Ok(ConditionalLoad::Modified(record, token))
+    }
+
+    /// The [version](ModificationToken::version) of the file at `path`, `None` if it doesn't
+    /// exist.
+    async fn file_version(&self, path: &Path) -> session_store::Result<Option<u64>> {
+        let metadata = match self.fs.metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileError::new("get metadata of", path, e).into()),
+        };
+        ModificationToken::version(&metadata)
+            .map(Some)
+            .ok_or_else(|| session_store::Error::Backend("Failed to get modified date".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn unchanged_sessions_are_not_modified() {
+        let (store, _, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+
+        let ConditionalLoad::Modified(loaded, token) = store
+            .load_if_modified_since(&session.id, None)
+            .await
+            .unwrap()
+        else {
+            panic!("expected the session to be loaded");
+        };
+        assert_eq!(loaded, session);
+        assert!(matches!(
+            store
+                .load_if_modified_since(&session.id, Some(token))
+                .await
+                .unwrap(),
+            ConditionalLoad::NotModified
+        ));
+
+        session
+            .data
+            .insert("key".to_string(), "changed value".into());
+        store.save(&session).await.unwrap();
+        let ConditionalLoad::Modified(loaded, new_token) = store
+            .load_if_modified_since(&session.id, Some(token))
+            .await
+            .unwrap()
+        else {
+            panic!("expected the changed session to be loaded");
+        };
+        assert_eq!(loaded, session);
+        assert_ne!(new_token, token);
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_not_found_even_if_unchanged() {
+        let (store, fs, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        let ConditionalLoad::Modified(_, token) = store
+            .load_if_modified_since(&session.id, None)
+            .await
+            .unwrap()
+        else {
+            panic!("expected the session to be loaded");
+        };
+
+        clock.advance(Duration::from_secs(120));
+        assert!(matches!(
+            store
+                .load_if_modified_since(&session.id, Some(token))
+                .await
+                .unwrap(),
+            ConditionalLoad::NotFound
+        ));
+        assert!(
+            !crate::Fs::try_exists(&fs, &store.session_path(&session.id))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_sessions_are_not_found() {
+        let (store, _, clock) = store();
+        let session = record(&clock, Duration::from_secs(60));
+        assert!(matches!(
+            store
+                .load_if_modified_since(&session.id, None)
+                .await
+                .unwrap(),
+            ConditionalLoad::NotFound
+        ));
+    }
+}
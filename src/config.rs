@@ -0,0 +1,197 @@
+use std::{fmt, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{BuildError, FileSessionStorage, FileSessionStorageBuilder, ReadFallback};
+
+/// The configuration of a [`FileSessionStorage`], for loading from the configuration file of an
+/// application.
+///
+/// Every field is optional and defaults to the same value [`FileSessionStorage::new`] uses.
+/// Unknown fields are rejected, so typos don't go unnoticed. For example in TOML:
+///
+/// ```toml
+/// [sessions]
+/// folder = "/var/lib/my-app/sessions"
+/// minimum_expiry_secs = 3600
+/// sweep_partition = { index = 0, count = 2 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct FileSessionStorageConfig {
+    /// The folder sessions are placed in.
+    pub folder: Option<PathBuf>,
+    /// See [`FileSessionStorage::set_legacy_folder`].
+    pub legacy_folder: Option<PathBuf>,
+    /// See [`FileSessionStorage::set_mirror_folder`].
+    pub mirror_folder: Option<PathBuf>,
+    /// See [`FileSessionStorage::set_read_fallback`], `"fail-fast"` or `"fail-over"`.
+    pub read_fallback: Option<ReadFallback>,
+    /// See [`FileSessionStorage::set_minimum_expiry_date`], in seconds.
+    pub minimum_expiry_secs: Option<u64>,
+    /// See [`FileSessionStorage::set_sweep_partition`].
+    pub sweep_partition: Option<SweepPartitionConfig>,
+    /// See [`FileSessionStorage::set_cross_process_locking`].
+    pub cross_process_locking: Option<bool>,
+    /// See [`FileSessionStorage::set_user_index`].
+    pub user_index: Option<String>,
+    /// See [`FileSessionStorage::set_minimum_free_space`], in bytes.
+    pub minimum_free_space: Option<u64>,
+    /// See [`FileSessionStorage::set_slow_operation_threshold`], in milliseconds.
+    pub slow_operation_threshold_ms: Option<u64>,
+    /// See [`FileSessionStorage::set_track_last_access`].
+    pub track_last_access: Option<bool>,
+    /// See [`FileSessionStorage::set_reject_expired_writes`].
+    pub reject_expired_writes: Option<bool>,
+    /// See [`FileSessionStorage::set_degrade_on_disk_full`].
+    pub degrade_on_disk_full: Option<bool>,
+}
+
+/// The `sweep_partition` field of a [`FileSessionStorageConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepPartitionConfig {
+    /// The index of this instance.
+    pub index: u32,
+    /// The total number of instances.
+    pub count: u32,
+}
+
+/// A field of a [`FileSessionStorageConfig`] has an invalid value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// The path of the field, like `sweep_partition.index`.
+    pub field: &'static str,
+    /// What is wrong with it.
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid `{}`: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl FileSessionStorageConfig {
+    /// Check every field, reporting the first one that is invalid.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self
+            .folder
+            .as_ref()
+            .is_some_and(|f| f.as_os_str().is_empty())
+        {
+            return Err(ConfigError {
+                field: "folder",
+                message: "must not be empty".to_string(),
+            });
+        }
+        if let Some(SweepPartitionConfig { index, count }) = self.sweep_partition {
+            if count == 0 {
+                return Err(ConfigError {
+                    field: "sweep_partition.count",
+                    message: "must be at least 1".to_string(),
+                });
+            }
+            if index >= count {
+                return Err(ConfigError {
+                    field: "sweep_partition.index",
+                    message: format!("must be less than the partition count {count}"),
+                });
+            }
+        }
+        if self.user_index.as_ref().is_some_and(String::is_empty) {
+            return Err(ConfigError {
+                field: "user_index",
+                message: "must not be empty".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// A builder with these options, for adding the options that can't be loaded from a file,
+    /// like indexes.
+    pub fn into_builder(self) -> Result<FileSessionStorageBuilder, ConfigError> {
+        self.validate()?;
+        let mut builder = FileSessionStorage::builder();
+        if let Some(folder) = self.folder {
+            builder = builder.folder(folder);
+        }
+        if let Some(legacy_folder) = self.legacy_folder {
+            builder = builder.legacy_folder(legacy_folder);
+        }
+        if let Some(mirror_folder) = self.mirror_folder {
+            builder = builder.mirror_folder(mirror_folder);
+        }
+        if let Some(read_fallback) = self.read_fallback {
+            builder = builder.read_fallback(read_fallback);
+        }
+        if let Some(secs) = self.minimum_expiry_secs {
+            builder = builder.minimum_expiry_date(Duration::from_secs(secs));
+        }
+        if let Some(SweepPartitionConfig { index, count }) = self.sweep_partition {
+            builder = builder.sweep_partition(index, count);
+        }
+        if let Some(enabled) = self.cross_process_locking {
+            builder = builder.cross_process_locking(enabled);
+        }
+        if let Some(data_key) = self.user_index {
+            builder = builder.user_index(data_key);
+        }
+        if let Some(bytes) = self.minimum_free_space {
+            builder = builder.minimum_free_space(bytes);
+        }
+        if let Some(millis) = self.slow_operation_threshold_ms {
+            builder = builder.slow_operation_threshold(Duration::from_millis(millis));
+        }
+        if let Some(enabled) = self.track_last_access {
+            builder = builder.track_last_access(enabled);
+        }
+        if let Some(enabled) = self.reject_expired_writes {
+            builder = builder.reject_expired_writes(enabled);
+        }
+        if let Some(enabled) = self.degrade_on_disk_full {
+            builder = builder.degrade_on_disk_full(enabled);
+        }
+        Ok(builder)
+    }
+
+    /// Check the configuration and create the store.
+    pub fn build(self) -> Result<FileSessionStorage, ConfigError> {
+        self.into_builder()?.build().map_err(|e| ConfigError {
+            field: field_of(&e),
+            message: e.to_string(),
+        })
+    }
+}
+
+/// The field of a [`FileSessionStorageConfig`] that caused `error`.
+fn field_of(error: &BuildError) -> &'static str {
+    match error {
+        BuildError::InvalidSweepPartition { .. } => "sweep_partition.index",
+        // The only index a configuration can add
+        BuildError::InvalidIndexName(_) | BuildError::DuplicateIndexName(_) => "user_index",
+        BuildError::EmptyFolder => "folder",
+        BuildError::LegacyFolderIsFolder => "legacy_folder",
+        BuildError::MirrorFolderIsFolder => "mirror_folder",
+        BuildError::CrossProcessLockingUnsupported => "cross_process_locking",
+        BuildError::InvalidEnvVar { name, .. } => name,
+        // Only set through the builder, named after its methods
+        BuildError::InvalidCreateRateLimit => "create_rate_limit",
+        BuildError::InvalidCircuitBreaker => "circuit_breaker",
+        BuildError::TooManyVersions(_) => "keep_versions",
+        BuildError::InvalidTtlPolicy(_) => "ttl_policy",
+        BuildError::InvalidShardedNaming { .. } => "file_naming",
+        BuildError::NoShardRoots => "roots",
+    }
+}
+
+impl TryFrom<FileSessionStorageConfig> for FileSessionStorage {
+    type Error = ConfigError;
+
+    fn try_from(config: FileSessionStorageConfig) -> Result<Self, Self::Error> {
+        config.build()
+    }
+}
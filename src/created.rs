@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tower_sessions_core::{session::Record, session_store};
+
+use crate::{
+    error::{encode_json, FileError},
+    FileSessionStorage,
+};
+
+/// A session as it is written to its file, the record with when the session was created.
+///
+/// Older versions didn't write `created_at`, everything else ignores it while parsing.
+#[derive(Serialize)]
+struct StoredRecord<'a> {
+    #[serde(flatten)]
+    record: &'a Record,
+    created_at: OffsetDateTime,
+}
+
+/// Only the creation time of a stored session, all other fields are skipped while parsing.
+#[derive(Deserialize)]
+struct CreatedOnly {
+    #[serde(default)]
+    created_at: Option<OffsetDateTime>,
+}
+
+/// Encode a session the way it is stored, with when it was created.
+pub(crate) fn encode_record(
+    record: &Record,
+    created_at: OffsetDateTime,
+) -> session_store::Result<Vec<u8>> {
+    encode_json(&StoredRecord { record, created_at })
+}
+
+/// When the session in `contents` was created, `None` if it was stored by an older version.
+pub(crate) fn decode_created_at(contents: &[u8]) -> Option<OffsetDateTime> {
+    serde_json::from_slice::<CreatedOnly>(contents)
+        .ok()
+        .and_then(|created| created.created_at)
+}
+
+impl FileSessionStorage {
+    /// When the session stored at `path` was created, `None` if the file doesn't exist.
+    ///
+    /// For sessions stored by older versions this is the birth time of the file if the file
+    /// system records it, otherwise the time the file was last written.
+    pub(crate) async fn created_at_of(
+        &self,
+        path: &Path,
+    ) -> session_store::Result<Option<OffsetDateTime>> {
+        let contents = match self.fs.read(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileError::new("read", path, e).into()),
+        };
+        if let Some(created_at) = decode_created_at(&contents) {
+            return Ok(Some(created_at));
+        }
+        let metadata = match self.fs.metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileError::new("get metadata of", path, e).into()),
+        };
+        let created = metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .unwrap_or_else(|_| self.now());
+        Ok(Some(OffsetDateTime::from(created)))
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use tower_sessions_core::{session::Record, session_store};
+
+use crate::{trash::Discard, ArchiveExpired, FileSessionStorage};
+
+/// Decides what happens to expired sessions, so the same sweep can hard delete, archive,
+/// anonymize or do anything else with them depending on the deployment.
+///
+/// Set it with [`FileSessionStorage::set_deletion_strategy`] or
+/// [`FileSessionStorageBuilder::deletion_strategy`](crate::FileSessionStorageBuilder::deletion_strategy),
+/// the default is [`HardDelete`]. [`ArchiveExpired`] and [`Anonymize`] are the other built-in
+/// strategies, custom ones can do their own work and then call one of those.
+#[async_trait]
+pub trait DeletionStrategy: Send + Sync + 'static {
+    /// Take an expired session out of the store, returns `false` if it didn't exist anymore or was
+    /// saved with a later expiry date since `record` was read.
+    ///
+    /// `record` is its last saved version. The session has to be gone from the store when this
+    /// returns `true`, otherwise every sweep calls it again.
+    /// [`before_expire`](crate::SessionStoreHooks::before_expire) hooks run before it, and
+    /// [`SessionEvent::Expired`](crate::SessionEvent::Expired) and
+    /// [`expired`](crate::SessionStoreHooks::expired) hooks after it.
+    async fn remove(
+        &self,
+        store: &FileSessionStorage,
+        record: &Record,
+    ) -> session_store::Result<bool>;
+
+    /// Run at the end of every `delete_expired`, for example to purge old archives. Does nothing
+    /// by default.
+    async fn after_sweep(&self, _store: &FileSessionStorage) -> session_store::Result<()> {
+        Ok(())
+    }
+}
+
+/// Delete the files of expired sessions, the default [`DeletionStrategy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HardDelete;
+
+#[async_trait]
+impl DeletionStrategy for HardDelete {
+    async fn remove(
+        &self,
+        store: &FileSessionStorage,
+        record: &Record,
+    ) -> session_store::Result<bool> {
+        store
+            .remove_expired_session(&record.id, Discard::Delete)
+            .await
+    }
+}
+
+#[async_trait]
+impl DeletionStrategy for ArchiveExpired {
+    async fn remove(
+        &self,
+        store: &FileSessionStorage,
+        record: &Record,
+    ) -> session_store::Result<bool> {
+        store
+            .remove_expired_session(&record.id, Discard::Archive(*self))
+            .await
+    }
+
+    async fn after_sweep(&self, store: &FileSessionStorage) -> session_store::Result<()> {
+        match self.retention {
+            Some(retention) => store.purge_expired_archive(retention).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Rewrite expired sessions with a transform, for example removing personal data, and then
+/// archive them like [`ArchiveExpired`], so only the scrubbed data is kept.
+pub struct Anonymize<F> {
+    transform: F,
+    archive: ArchiveExpired,
+}
+
+impl<F> Anonymize<F>
+where
+    F: Fn(&mut Record) + Send + Sync + 'static,
+{
+    /// Scrub sessions with `transform`, then archive them with `archive`.
+    pub fn new(transform: F, archive: ArchiveExpired) -> Self {
+        Anonymize { transform, archive }
+    }
+}
+
+impl<F> fmt::Debug for Anonymize<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Anonymize")
+            .field("archive", &self.archive)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<F> DeletionStrategy for Anonymize<F>
+where
+    F: Fn(&mut Record) + Send + Sync + 'static,
+{
+    async fn remove(
+        &self,
+        store: &FileSessionStorage,
+        record: &Record,
+    ) -> session_store::Result<bool> {
+        if store
+            .anonymize_session(
+                &record.id,
+                &|record: &Record| store.is_expired(record),
+                &self.transform,
+            )
+            .await?
+            .is_none()
+        {
+            return Ok(false);
+        }
+        self.archive.remove(store, record).await
+    }
+
+    async fn after_sweep(&self, store: &FileSessionStorage) -> session_store::Result<()> {
+        self.archive.after_sweep(store).await
+    }
+}
+
+/// The strategy set on a store, shared between clones.
+#[derive(Clone)]
+pub(crate) struct Deletion(pub(crate) Arc<dyn DeletionStrategy>);
+
+impl Default for Deletion {
+    fn default() -> Self {
+        Deletion(Arc::new(HardDelete))
+    }
+}
+
+impl fmt::Debug for Deletion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deletion").finish_non_exhaustive()
+    }
+}
+
+impl FileSessionStorage {
+    /// Decide what happens to expired sessions, see [`DeletionStrategy`]. Defaults to
+    /// [`HardDelete`].
+    pub fn set_deletion_strategy(mut self, strategy: impl DeletionStrategy) -> Self {
+        self.deletion = Deletion(Arc::new(strategy));
+        self
+    }
+}
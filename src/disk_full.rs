@@ -0,0 +1,89 @@
+use std::{future::Future, io, time::Duration};
+
+use tower_sessions_core::{session_store, ExpiredDeletion};
+
+use crate::{FileSessionStorage, SessionEvent};
+
+/// Start of the message of errors caused by a full disk, see
+/// [`FileSessionStorage::is_disk_full_error`].
+pub(crate) const DISK_FULL_PREFIX: &str = "Disk full: ";
+
+/// How long writes are refused in degraded mode before the next one tries the disk again.
+const DEGRADED_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether an I/O error means there is no space left for the file.
+pub(crate) fn is_disk_full(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+    )
+}
+
+impl FileSessionStorage {
+    /// When creating or saving a session fails because the disk is full, switch into a degraded
+    /// mode instead of trying every following write.
+    ///
+    /// Expired sessions are deleted right away to free space. Until a write succeeds again,
+    /// creating and saving sessions fail without touching the disk, except for one attempt every
+    /// few seconds. Loading and deleting sessions keep working, so existing sessions are served
+    /// read-only. Defaults to `false`.
+    pub fn set_degrade_on_disk_full(mut self, enabled: bool) -> Self {
+        self.degrade_on_disk_full = enabled;
+        self
+    }
+
+    /// Whether the store is in the degraded mode of
+    /// [`set_degrade_on_disk_full`](Self::set_degrade_on_disk_full).
+    pub fn is_degraded(&self) -> bool {
+        self.disk_full_since.lock().unwrap().is_some()
+    }
+
+    /// Whether `error` was returned because the disk holding the sessions is full, for example
+    /// to show a maintenance page instead of a generic error.
+    ///
+    /// `session_store::Error` can only hold a message, these errors are recognized by their
+    /// `Disk full: ` prefix. [`SessionEvent::DiskFull`] is also sent for them.
+    pub fn is_disk_full_error(error: &session_store::Error) -> bool {
+        matches!(error, session_store::Error::Backend(message) if message.starts_with(DISK_FULL_PREFIX))
+    }
+
+    /// Run a write, refusing it while degraded and entering degraded mode if it fills the disk.
+    pub(crate) async fn guard_disk_full<T>(
+        &self,
+        write: impl Future<Output = session_store::Result<T>>,
+    ) -> session_store::Result<T> {
+        let since = *self.disk_full_since.lock().unwrap();
+        if let Some(since) = since {
+            let elapsed = self.now().duration_since(since).unwrap_or_default();
+            if elapsed < DEGRADED_RETRY_INTERVAL {
+                return Err(session_store::Error::Backend(format!(
+                    "{DISK_FULL_PREFIX}refusing writes until space is freed"
+                )));
+            }
+        }
+
+        let result = write.await;
+        match &result {
+            Ok(_) => *self.disk_full_since.lock().unwrap() = None,
+            Err(e) if Self::is_disk_full_error(e) => {
+                self.emit(SessionEvent::DiskFull);
+                if self.degrade_on_disk_full {
+                    let entered = self
+                        .disk_full_since
+                        .lock()
+                        .unwrap()
+                        .replace(self.now())
+                        .is_none();
+                    if entered {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!("disk is full, refusing to write sessions");
+                        // Errors are in `dump_report`, the write failed either way
+                        let _ = self.delete_expired().await;
+                    }
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+}
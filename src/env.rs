@@ -0,0 +1,127 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{BuildError, FileSessionStorage, FileSessionStorageBuilder, ReadFallback};
+
+/// Read an environment variable, `None` if it isn't set.
+fn var(name: &'static str) -> Result<Option<String>, BuildError> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(value)) => Err(BuildError::InvalidEnvVar {
+            name,
+            value: value.to_string_lossy().into_owned(),
+        }),
+    }
+}
+
+/// Read and parse an environment variable, `None` if it isn't set.
+fn parse_var<T>(
+    name: &'static str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>, BuildError> {
+    var(name)?
+        .map(|value| parse(value.trim()).ok_or(BuildError::InvalidEnvVar { name, value }))
+        .transpose()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_partition(value: &str) -> Option<(u32, u32)> {
+    let (index, count) = value.split_once('/')?;
+    Some((index.trim().parse().ok()?, count.trim().parse().ok()?))
+}
+
+fn parse_read_fallback(value: &str) -> Option<ReadFallback> {
+    match value.to_ascii_lowercase().as_str() {
+        "fail-fast" => Some(ReadFallback::FailFast),
+        "fail-over" => Some(ReadFallback::FailOver),
+        _ => None,
+    }
+}
+
+impl FileSessionStorageBuilder {
+    /// A builder configured from environment variables, so the store can be configured without
+    /// code changes in container deployments.
+    ///
+    /// Every variable is optional, options that aren't set keep their default:
+    ///
+    /// - `SESSION_STORE_DIR`: the folder sessions are placed in.
+    /// - `SESSION_STORE_LEGACY_DIR`: see [`legacy_folder`](Self::legacy_folder).
+    /// - `SESSION_STORE_MIRROR_DIR`: see [`mirror_folder`](Self::mirror_folder).
+    /// - `SESSION_STORE_READ_FALLBACK`: `fail-fast` or `fail-over`, see
+    ///   [`read_fallback`](Self::read_fallback).
+    /// - `SESSION_STORE_MIN_EXPIRY_SECS`: see [`minimum_expiry_date`](Self::minimum_expiry_date).
+    /// - `SESSION_STORE_SWEEP_PARTITION`: `<index>/<count>`, see
+    ///   [`sweep_partition`](Self::sweep_partition).
+    /// - `SESSION_STORE_CROSS_PROCESS_LOCKING`: `true` or `false`, see
+    ///   [`cross_process_locking`](Self::cross_process_locking).
+    /// - `SESSION_STORE_USER_INDEX`: see [`user_index`](Self::user_index).
+    /// - `SESSION_STORE_MIN_FREE_SPACE`: in bytes, see
+    ///   [`minimum_free_space`](Self::minimum_free_space).
+    /// - `SESSION_STORE_SLOW_OPERATION_MS`: see
+    ///   [`slow_operation_threshold`](Self::slow_operation_threshold).
+    /// - `SESSION_STORE_TRACK_LAST_ACCESS`: `true` or `false`, see
+    ///   [`track_last_access`](Self::track_last_access).
+    /// - `SESSION_STORE_REJECT_EXPIRED_WRITES`: `true` or `false`, see
+    ///   [`reject_expired_writes`](Self::reject_expired_writes).
+    /// - `SESSION_STORE_DEGRADE_ON_DISK_FULL`: `true` or `false`, see
+    ///   [`degrade_on_disk_full`](Self::degrade_on_disk_full).
+    pub fn from_env() -> Result<Self, BuildError> {
+        let mut builder = FileSessionStorage::builder();
+        if let Some(folder) = var("SESSION_STORE_DIR")? {
+            builder = builder.folder(PathBuf::from(folder));
+        }
+        if let Some(legacy_folder) = var("SESSION_STORE_LEGACY_DIR")? {
+            builder = builder.legacy_folder(legacy_folder);
+        }
+        if let Some(mirror_folder) = var("SESSION_STORE_MIRROR_DIR")? {
+            builder = builder.mirror_folder(mirror_folder);
+        }
+        if let Some(read_fallback) = parse_var("SESSION_STORE_READ_FALLBACK", parse_read_fallback)?
+        {
+            builder = builder.read_fallback(read_fallback);
+        }
+        if let Some(secs) = parse_var("SESSION_STORE_MIN_EXPIRY_SECS", |v| v.parse().ok())? {
+            builder = builder.minimum_expiry_date(Duration::from_secs(secs));
+        }
+        if let Some((index, count)) = parse_var("SESSION_STORE_SWEEP_PARTITION", parse_partition)? {
+            builder = builder.sweep_partition(index, count);
+        }
+        if let Some(enabled) = parse_var("SESSION_STORE_CROSS_PROCESS_LOCKING", parse_bool)? {
+            builder = builder.cross_process_locking(enabled);
+        }
+        if let Some(data_key) = var("SESSION_STORE_USER_INDEX")? {
+            builder = builder.user_index(data_key);
+        }
+        if let Some(bytes) = parse_var("SESSION_STORE_MIN_FREE_SPACE", |v| v.parse().ok())? {
+            builder = builder.minimum_free_space(bytes);
+        }
+        if let Some(millis) = parse_var("SESSION_STORE_SLOW_OPERATION_MS", |v| v.parse().ok())? {
+            builder = builder.slow_operation_threshold(Duration::from_millis(millis));
+        }
+        if let Some(enabled) = parse_var("SESSION_STORE_TRACK_LAST_ACCESS", parse_bool)? {
+            builder = builder.track_last_access(enabled);
+        }
+        if let Some(enabled) = parse_var("SESSION_STORE_REJECT_EXPIRED_WRITES", parse_bool)? {
+            builder = builder.reject_expired_writes(enabled);
+        }
+        if let Some(enabled) = parse_var("SESSION_STORE_DEGRADE_ON_DISK_FULL", parse_bool)? {
+            builder = builder.degrade_on_disk_full(enabled);
+        }
+        Ok(builder)
+    }
+}
+
+impl FileSessionStorage {
+    /// Create a store configured from environment variables, see
+    /// [`FileSessionStorageBuilder::from_env`] for the variables that are read.
+    pub fn from_env() -> Result<Self, BuildError> {
+        FileSessionStorageBuilder::from_env()?.build()
+    }
+}
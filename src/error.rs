@@ -0,0 +1,162 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tower_sessions_core::session_store;
+
+use crate::FileSessionStorage;
+
+/// Start of the message of errors from [`FileError`], see [`is_io_error`].
+const IO_ERROR_PREFIX: &str = "Failed to ";
+
+/// Start of the message of writes refused because the session already expired.
+pub(crate) const EXPIRED_WRITE_PREFIX: &str = "Refusing to write session ";
+
+/// The message of operations on a session that doesn't exist.
+pub(crate) const NO_SUCH_SESSION: &str = "No such session";
+
+/// What went wrong in a `session_store::Error` returned by the store, see
+/// [`FileSessionStorage::error_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StoreErrorKind {
+    /// A file system operation failed, with the kind of the I/O error if the operating system
+    /// reported one.
+    Io(Option<io::ErrorKind>),
+    /// The disk is full, or the store refuses writes until space is freed.
+    DiskFull,
+    /// The create rate limit was reached.
+    RateLimited,
+    /// The circuit breaker is open, the operation wasn't tried.
+    CircuitOpen,
+    /// The session already expired and expired writes are rejected.
+    ExpiredWrite,
+    /// The session doesn't exist.
+    NoSuchSession,
+    /// A stored session couldn't be decoded.
+    Decode,
+    /// A session couldn't be encoded.
+    Encode,
+    /// Anything else, like invalid arguments.
+    Other,
+}
+
+impl FileSessionStorage {
+    /// What went wrong in an error returned by the store, for example to respond with
+    /// `429 Too Many Requests` when it is [`StoreErrorKind::RateLimited`] or fall back to
+    /// anonymous sessions when it is [`StoreErrorKind::CircuitOpen`].
+    ///
+    /// `session_store::Error` can only hold a message, so errors are recognized by how their
+    /// message starts. Errors that didn't come from this store are
+    /// [`StoreErrorKind::Other`], unless they are `Decode` or `Encode` errors.
+    pub fn error_kind(error: &session_store::Error) -> StoreErrorKind {
+        let message = match error {
+            session_store::Error::Decode(_) => return StoreErrorKind::Decode,
+            session_store::Error::Encode(_) => return StoreErrorKind::Encode,
+            session_store::Error::Backend(message) => message,
+        };
+        if Self::is_disk_full_error(error) {
+            StoreErrorKind::DiskFull
+        } else if Self::is_rate_limited_error(error) {
+            StoreErrorKind::RateLimited
+        } else if Self::is_circuit_open_error(error) {
+            StoreErrorKind::CircuitOpen
+        } else if is_io_error(error) {
+            StoreErrorKind::Io(os_error_kind(message))
+        } else if message.starts_with(EXPIRED_WRITE_PREFIX) {
+            StoreErrorKind::ExpiredWrite
+        } else if message == NO_SUCH_SESSION {
+            StoreErrorKind::NoSuchSession
+        } else {
+            StoreErrorKind::Other
+        }
+    }
+}
+
+/// The kind of the OS error an [`io::Error`] rendered into `message` ends with, like
+/// `(os error 2)`.
+fn os_error_kind(message: &str) -> Option<io::ErrorKind> {
+    let code = message.strip_suffix(')')?.rsplit_once("(os error ")?.1;
+    Some(io::Error::from_raw_os_error(code.parse().ok()?).kind())
+}
+
+/// Whether `error` is a file system operation that failed, not a decision of the store like a
+/// refused write or a missing session. Writes that failed because the disk is full don't count,
+/// they start with [`DISK_FULL_PREFIX`](crate::disk_full::DISK_FULL_PREFIX).
+pub(crate) fn is_io_error(error: &session_store::Error) -> bool {
+    matches!(error, session_store::Error::Backend(message) if message.starts_with(IO_ERROR_PREFIX))
+}
+
+/// A file system operation that failed, with the path it failed on and the error reported by the
+/// operating system.
+///
+/// `session_store::Error` can only hold a message, so this is rendered into
+/// [`session_store::Error::Backend`] when it leaves the store.
+#[derive(Debug)]
+pub(crate) struct FileError {
+    operation: &'static str,
+    path: PathBuf,
+    source: io::Error,
+}
+
+impl FileError {
+    pub(crate) fn new(operation: &'static str, path: impl AsRef<Path>, source: io::Error) -> Self {
+        FileError {
+            operation,
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{IO_ERROR_PREFIX}{} {}: {}",
+            self.operation,
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<FileError> for session_store::Error {
+    fn from(error: FileError) -> Self {
+        if crate::disk_full::is_disk_full(&error.source) {
+            session_store::Error::Backend(format!("{}{error}", crate::disk_full::DISK_FULL_PREFIX))
+        } else {
+            session_store::Error::Backend(error.to_string())
+        }
+    }
+}
+
+/// Attach the operation and path to an I/O error.
+pub(crate) trait IoResultExt<T> {
+    fn context(self, operation: &'static str, path: impl AsRef<Path>) -> session_store::Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn context(self, operation: &'static str, path: impl AsRef<Path>) -> session_store::Result<T> {
+        self.map_err(|e| FileError::new(operation, path, e).into())
+    }
+}
+
+/// Encode a session the way it is stored, failing with [`session_store::Error::Encode`].
+pub(crate) fn encode_json(value: &impl Serialize) -> session_store::Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| session_store::Error::Encode(e.to_string()))
+}
+
+/// Decode a stored session, failing with [`session_store::Error::Decode`] so corrupted sessions
+/// can be told apart from I/O problems.
+pub(crate) fn decode_json<T: DeserializeOwned>(contents: &[u8]) -> session_store::Result<T> {
+    serde_json::from_slice(contents).map_err(|e| session_store::Error::Decode(e.to_string()))
+}
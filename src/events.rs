@@ -0,0 +1,48 @@
+use tokio::sync::broadcast;
+use tower_sessions_core::session::Id;
+
+use crate::FileSessionStorage;
+
+/// How many events a subscriber can fall behind before it starts missing them.
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// A change to a session made through the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionEvent {
+    /// A new session was created.
+    Created(Id),
+    /// An existing session was saved.
+    Saved(Id),
+    /// A session was deleted, for example because the user logged out.
+    Deleted(Id),
+    /// A session was removed by the expiry sweep.
+    Expired(Id),
+    /// The sessions folder was removed while the store was in use, for example by a cleanup of
+    /// temporary files, and was created again. The sessions in it are lost.
+    FolderRecreated,
+    /// Creating or saving a session failed because the disk is full, see
+    /// [`FileSessionStorage::set_degrade_on_disk_full`].
+    DiskFull,
+    /// Too many operations failed in a row and the circuit breaker opened, see
+    /// [`FileSessionStorage::set_circuit_breaker`].
+    CircuitOpened,
+    /// An operation succeeded after the circuit breaker opened, so it closed again.
+    CircuitClosed,
+    /// [`FileSessionStorage::continuously_verify`] found a session file that can't be parsed.
+    Corrupted(Id),
+}
+
+impl FileSessionStorage {
+    /// Subscribe to changes made to sessions by this store or any of its clones.
+    ///
+    /// Only changes made from within this process are reported. Subscribers that fall too far
+    /// behind will receive [`RecvError::Lagged`](broadcast::error::RecvError::Lagged).
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: SessionEvent) {
+        // Sending only fails if no one is subscribed
+        let _ = self.events.send(event);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{io, path::Path, time::Duration};
+
+use time::Date;
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    naming::encode_id,
+    FileSessionStorage,
+};
+
+/// Folder in the sessions folder expired sessions are archived in, see
+/// [`FileSessionStorage::set_archive_expired`].
+const EXPIRED_FOLDER: &str = ".expired";
+
+/// Options for [`FileSessionStorage::set_archive_expired`], also usable as a
+/// [`DeletionStrategy`](crate::DeletionStrategy).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveExpired {
+    /// How long archived sessions are kept, `None` to keep them until they are removed by hand.
+    pub retention: Option<Duration>,
+    /// Compress archived sessions with gzip, as `<id>.gz`. Needs the `archive` feature, ignored
+    /// without it.
+    pub compress: bool,
+}
+
+/// The name of the archive folder for sessions that expired on `date`, like `2024-01-31`.
+fn date_folder_name(date: Date) -> String {
+    format!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        u8::from(date.month()),
+        date.day()
+    )
+}
+
+impl FileSessionStorage {
+    /// Move expired sessions to a folder per day in the `.expired` folder in the sessions folder,
+    /// like `.expired/2024-01-31/<id>`, instead of deleting them.
+    ///
+    /// For analytics or compliance that need session data after it expired. Expiry sweeps delete
+    /// the folders of days longer than the retention ago. Blobs of expired sessions are still
+    /// deleted. Disabled by default.
+    ///
+    /// This is the same as [`set_deletion_strategy`](Self::set_deletion_strategy) with `options`.
+    pub fn set_archive_expired(self, options: ArchiveExpired) -> Self {
+        self.set_deletion_strategy(options)
+    }
+
+    /// Move the file of an expired session at `path` to the archive folder of today.
+    #[cfg_attr(not(feature = "archive"), allow(unused_variables))]
+    pub(crate) async fn archive_file(
+        &self,
+        session_id: &Id,
+        path: &Path,
+        options: ArchiveExpired,
+    ) -> io::Result<()> {
+        let folder = self
+            .folder()
+            .join(EXPIRED_FOLDER)
+            .join(date_folder_name(self.now_utc().date()));
+        self.fs.create_dir_all(&folder).await?;
+        let archived = folder.join(encode_id(session_id));
+        #[cfg(feature = "archive")]
+        if options.compress {
+            use std::io::Write;
+
+            let contents = self.fs.read(path).await?;
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&contents)?;
+            self.fs
+                .write(&archived.with_extension("gz"), &encoder.finish()?)
+                .await?;
+            return self.fs.remove_file(path).await;
+        }
+        self.fs.rename(path, &archived).await
+    }
+
+    /// Delete the archive folders of days longer than `retention` ago.
+    pub(crate) async fn purge_expired_archive(
+        &self,
+        retention: Duration,
+    ) -> session_store::Result<()> {
+        let Some(cutoff) = time::Duration::try_from(retention)
+            .ok()
+            .and_then(|retention| self.now_utc().checked_sub(retention))
+        else {
+            // The retention reaches back further than any date, so nothing is old enough
+            return Ok(());
+        };
+        let cutoff = date_folder_name(cutoff.date());
+        let archive = self.folder().join(EXPIRED_FOLDER);
+        let mut entries = match crate::fs::read_dir(&self.fs, &archive).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(FileError::new("list folder", &archive, e).into()),
+        };
+        while let Some(dir_entry) = entries
+            .next_entry()
+            .await
+            .context("list folder", &archive)?
+        {
+            let Some(name) = dir_entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            // Names sort like the dates, anything else isn't ours
+            if name.len() != cutoff.len() || name >= cutoff {
+                continue;
+            }
+            let path = dir_entry.path();
+            match self.fs.remove_dir_all(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(FileError::new("delete", &path, e).into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_sessions_core::{ExpiredDeletion, SessionStore};
+
+    use super::*;
+    use crate::{
+        tests::{record, store},
+        Fs,
+    };
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[tokio::test]
+    async fn expired_sessions_are_archived_and_purged() {
+        let (store, fs, clock) = store();
+        let store = store.set_archive_expired(ArchiveExpired {
+            retention: Some(2 * DAY),
+            compress: false,
+        });
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        clock.advance(Duration::from_secs(120));
+        store.delete_expired().await.unwrap();
+
+        let day_folder = Path::new("/sessions")
+            .join(EXPIRED_FOLDER)
+            .join(date_folder_name(store.now_utc().date()));
+        let archived = day_folder.join(encode_id(&session.id));
+        assert!(fs.try_exists(&archived).await.unwrap());
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+
+        clock.advance(DAY);
+        store.delete_expired().await.unwrap();
+        assert!(fs.try_exists(&archived).await.unwrap());
+        clock.advance(2 * DAY);
+        store.delete_expired().await.unwrap();
+        assert!(!fs.try_exists(&day_folder).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn endless_retention_keeps_everything() {
+        let (store, fs, clock) = store();
+        let store = store.set_archive_expired(ArchiveExpired {
+            retention: Some(Duration::MAX),
+            compress: false,
+        });
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        clock.advance(Duration::from_secs(120));
+        store.delete_expired().await.unwrap();
+        store.delete_expired().await.unwrap();
+
+        let archived = Path::new("/sessions")
+            .join(EXPIRED_FOLDER)
+            .join(date_folder_name(store.now_utc().date()))
+            .join(encode_id(&session.id));
+        assert!(fs.try_exists(&archived).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn compression_needs_the_feature() {
+        let (store, fs, clock) = store();
+        let store = store.set_archive_expired(ArchiveExpired {
+            retention: None,
+            compress: true,
+        });
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        clock.advance(Duration::from_secs(120));
+        store.delete_expired().await.unwrap();
+
+        let archived = Path::new("/sessions")
+            .join(EXPIRED_FOLDER)
+            .join(date_folder_name(store.now_utc().date()))
+            .join(encode_id(&session.id));
+        assert_eq!(
+            fs.try_exists(&archived.with_extension("gz")).await.unwrap(),
+            cfg!(feature = "archive")
+        );
+        assert_eq!(
+            fs.try_exists(&archived).await.unwrap(),
+            !cfg!(feature = "archive")
+        );
+    }
+}
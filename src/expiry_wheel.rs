@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{
+    future::{select, Either},
+    TryStreamExt,
+};
+use time::OffsetDateTime;
+use tokio::sync::broadcast::error::RecvError;
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{error::FileError, FileSessionStorage, SessionEvent};
+
+/// Bits of a deadline used for the slot on each level of the wheel.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// Levels of the wheel, the top one spans 64^5 seconds, about 34 years.
+const LEVELS: usize = 5;
+
+/// A hierarchical timer wheel of sessions by the second they expire in.
+///
+/// Each level has 64 slots, a slot on level `n` holds the deadlines of 64^n seconds. Deadlines
+/// move down a level when their slot comes up, so adding and removing are `O(1)` no matter how
+/// many sessions are scheduled.
+#[derive(Debug)]
+struct TimerWheel {
+    /// The last second that was processed.
+    now: u64,
+    levels: Vec<Vec<Vec<(u64, Id)>>>,
+    /// The current deadline of every scheduled session, entries in the slots that don't match
+    /// were rescheduled or removed.
+    deadlines: HashMap<Id, u64>,
+    /// Sessions that were scheduled for a second that already passed.
+    due: Vec<Id>,
+}
+
+impl TimerWheel {
+    fn new(now: u64) -> Self {
+        TimerWheel {
+            now,
+            levels: vec![vec![Vec::new(); SLOTS]; LEVELS],
+            deadlines: HashMap::new(),
+            due: Vec::new(),
+        }
+    }
+
+    /// Schedule a session, replacing its previous deadline.
+    fn insert(&mut self, session_id: Id, deadline: u64) {
+        self.deadlines.insert(session_id, deadline);
+        self.place(session_id, deadline);
+    }
+
+    fn remove(&mut self, session_id: &Id) {
+        self.deadlines.remove(session_id);
+    }
+
+    fn place(&mut self, session_id: Id, deadline: u64) {
+        if deadline <= self.now {
+            self.due.push(session_id);
+            return;
+        }
+        // The highest level where the deadline and now are in a different slot, so the deadline
+        // comes up in its slot exactly once
+        let differing = 63 - (deadline ^ self.now).leading_zeros();
+        let level = (differing / SLOT_BITS).min(LEVELS as u32 - 1);
+        let slot = (deadline >> (level * SLOT_BITS)) as usize % SLOTS;
+        self.levels[level as usize][slot].push((deadline, session_id));
+    }
+
+    /// Move the wheel forward to `now`, returning the sessions that are due.
+    fn advance(&mut self, now: u64) -> Vec<Id> {
+        while self.now < now {
+            self.now += 1;
+            for level in (1..LEVELS).rev() {
+                let shift = level as u32 * SLOT_BITS;
+                if self.now & ((1 << shift) - 1) != 0 {
+                    continue;
+                }
+                let slot = (self.now >> shift) as usize % SLOTS;
+                for (deadline, session_id) in std::mem::take(&mut self.levels[level][slot]) {
+                    self.place(session_id, deadline);
+                }
+            }
+            let slot = self.now as usize % SLOTS;
+            for (deadline, session_id) in std::mem::take(&mut self.levels[0][slot]) {
+                self.place(session_id, deadline);
+            }
+        }
+        // Removing the deadline also drops duplicates
+        std::mem::take(&mut self.due)
+            .into_iter()
+            .filter(|session_id| match self.deadlines.get(session_id) {
+                Some(deadline) if *deadline <= self.now => {
+                    self.deadlines.remove(session_id);
+                    true
+                }
+                _ => false,
+            })
+            .collect()
+    }
+}
+
+/// The first second on the wheel at which `time` passed.
+fn tick_after(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// How long until the wheel moves to the next second.
+fn until_next_tick(time: SystemTime) -> Duration {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Duration::from_secs(1) - Duration::from_nanos(elapsed.subsec_nanos().into())
+}
+
+/// The second on the wheel a session expiring at `expiry_date` is deleted in, the session counts
+/// as expired once its expiry date passed.
+fn expiry_tick(expiry_date: OffsetDateTime) -> u64 {
+    u64::try_from(expiry_date.unix_timestamp()).map_or(0, |secs| secs + 1)
+}
+
+impl FileSessionStorage {
+    /// Delete every session within a second of its expiry date, instead of waiting for the next
+    /// expiry sweep.
+    ///
+    /// Spawn it like
+    /// [`continuously_delete_expired`](tower_sessions_core::ExpiredDeletion::continuously_delete_expired)
+    /// instead of it. At startup the expiry date of every session is read into a timer wheel,
+    /// after that sessions are scheduled when they are created or saved through this store or its
+    /// clones. Sessions written by other processes are only seen at startup, so keep running
+    /// sweeps, with a long period, if several processes share the folder. Only sessions in the
+    /// [sweep partition](Self::set_sweep_partition) of this store are deleted, and like with
+    /// sweeps not before the [minimum expiry date](Self::set_minimum_expiry_date) passed.
+    pub async fn continuously_delete_on_expiry(self) -> session_store::Result<()> {
+        let mut events = self.subscribe();
+        let mut wheel = self.fill_wheel().await?;
+        loop {
+            let event = match select(
+                std::pin::pin!(crate::rt::sleep(until_next_tick(self.now()))),
+                std::pin::pin!(events.recv()),
+            )
+            .await
+            {
+                Either::Left(_) => None,
+                Either::Right((event, _)) => Some(event),
+            };
+            match event {
+                None
+                | Some(Ok(
+                    SessionEvent::DiskFull
+                    | SessionEvent::CircuitOpened
+                    | SessionEvent::CircuitClosed,
+                )) => {}
+                Some(Ok(SessionEvent::Created(session_id) | SessionEvent::Saved(session_id))) => {
+                    if self.sweep_partition.contains(&session_id) {
+                        match self.get_expiry(&session_id).await {
+                            Ok(Some(expiry_date)) => {
+                                wheel.insert(session_id, expiry_tick(expiry_date))
+                            }
+                            Ok(None) => wheel.remove(&session_id),
+                            Err(e) => self.record_error("delete_on_expiry", &e),
+                        }
+                    }
+                }
+                Some(Ok(
+                    SessionEvent::Deleted(session_id)
+                    | SessionEvent::Expired(session_id)
+                    | SessionEvent::Corrupted(session_id),
+                )) => wheel.remove(&session_id),
+                // Sessions may have been missed or lost, start over from what is on disk
+                Some(Ok(SessionEvent::FolderRecreated) | Err(RecvError::Lagged(_))) => {
+                    match self.fill_wheel().await {
+                        Ok(filled) => wheel = filled,
+                        Err(e) => self.record_error("delete_on_expiry", &e),
+                    }
+                }
+                // The store holds a sender itself, so this can't happen
+                Some(Err(RecvError::Closed)) => return Ok(()),
+            }
+
+            for session_id in wheel.advance(tick_after(self.now())) {
+                if let Err(e) = self.delete_if_expired(&session_id, &mut wheel).await {
+                    self.record_error("delete_on_expiry", &e);
+                }
+            }
+        }
+    }
+
+    /// A timer wheel with the expiry date of every session in the sweep partition.
+    async fn fill_wheel(&self) -> session_store::Result<TimerWheel> {
+        let mut wheel = TimerWheel::new(tick_after(self.now()));
+        let mut entries = std::pin::pin!(self.session_entries());
+        while let Some((session_id, _)) = entries.try_next().await? {
+            if !self.sweep_partition.contains(&session_id) {
+                continue;
+            }
+            match self.get_expiry(&session_id).await {
+                Ok(Some(expiry_date)) => wheel.insert(session_id, expiry_tick(expiry_date)),
+                Ok(None) => {}
+                // Left for the sweeps, like they skip unreadable files
+                Err(e) => self.record_error("delete_on_expiry", &e),
+            }
+        }
+        Ok(wheel)
+    }
+
+    /// Delete a session the wheel says is due, after checking it wasn't saved with a later expiry
+    /// date by another process.
+    ///
+    /// Sessions modified within the minimum expiry date are checked again once it passed. The
+    /// removal checks the expiry date again under the session lock, in case it is
+    /// saved in between.
+    async fn delete_if_expired(
+        &self,
+        session_id: &Id,
+        wheel: &mut TimerWheel,
+    ) -> session_store::Result<()> {
+        let path = self.session_path(session_id);
+        let modified = match self.fs.metadata(&path).await {
+            Ok(metadata) => metadata.modified().ok(),
+            // Deleted, or still under a legacy name that reading adopts
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(FileError::new("get metadata of", &path, e).into()),
+        };
+        if let Some(modified) = modified {
+            let recheck = modified + self.minimum_expiry_date();
+            if recheck > self.now() {
+                wheel.insert(*session_id, tick_after(recheck) + 1);
+                return Ok(());
+            }
+        }
+        let Some(record) = self.read_record(session_id).await? else {
+            return Ok(());
+        };
+        if self.is_expired(&record) {
+            self.expire_session(&record).await?;
+        } else {
+            wheel.insert(*session_id, expiry_tick(record.expiry_date));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[test]
+    fn sessions_are_due_at_their_deadline() {
+        let mut wheel = TimerWheel::new(1000);
+        let soon = Id::default();
+        let later = Id::default();
+        wheel.insert(soon, 1010);
+        wheel.insert(later, 1100);
+
+        assert_eq!(wheel.advance(1009), vec![]);
+        assert_eq!(wheel.advance(1010), vec![soon]);
+        assert_eq!(wheel.advance(1099), vec![]);
+        assert_eq!(wheel.advance(1100), vec![later]);
+    }
+
+    #[test]
+    fn far_deadlines_cascade_down() {
+        let mut wheel = TimerWheel::new(0);
+        let session_id = Id::default();
+        // Starts on the third level
+        wheel.insert(session_id, 64 * 64 * 3 + 5);
+
+        assert_eq!(wheel.advance(64 * 64 * 3 + 4), vec![]);
+        assert_eq!(wheel.advance(64 * 64 * 3 + 5), vec![session_id]);
+    }
+
+    #[test]
+    fn rescheduling_and_removing_replace_the_deadline() {
+        let mut wheel = TimerWheel::new(0);
+        let moved = Id::default();
+        let removed = Id::default();
+        wheel.insert(moved, 10);
+        wheel.insert(moved, 20);
+        wheel.insert(removed, 10);
+        wheel.remove(&removed);
+
+        assert_eq!(wheel.advance(15), vec![]);
+        assert_eq!(wheel.advance(20), vec![moved]);
+        assert_eq!(wheel.advance(100), vec![]);
+    }
+
+    #[test]
+    fn past_deadlines_are_due_right_away() {
+        let mut wheel = TimerWheel::new(100);
+        let session_id = Id::default();
+        wheel.insert(session_id, 50);
+
+        assert_eq!(wheel.advance(100), vec![session_id]);
+    }
+
+    #[tokio::test]
+    async fn recently_written_sessions_are_rescheduled() {
+        let (store, _, clock) = store();
+        let store = store.set_minimum_expiry_date(Duration::from_secs(600));
+        let mut session = record(&clock, Duration::from_secs(10));
+        store.create(&mut session).await.unwrap();
+        let mut wheel = TimerWheel::new(tick_after(store.now()));
+
+        clock.advance(Duration::from_secs(60));
+        store
+            .delete_if_expired(&session.id, &mut wheel)
+            .await
+            .unwrap();
+        assert_eq!(store.count_sessions().await.unwrap(), 1);
+        assert_eq!(
+            wheel.advance(tick_after(store.now()) + 600),
+            vec![session.id]
+        );
+
+        clock.advance(Duration::from_secs(600));
+        store
+            .delete_if_expired(&session.id, &mut wheel)
+            .await
+            .unwrap();
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, HashMap};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::FileSessionStorage;
+
+/// Explains the fields of an export to whoever receives it.
+const ABOUT: &str = "Everything stored for one session. `record` holds the session data and when \
+    the session expires, `metadata` when it was created, last saved and last used, `indexes` the \
+    values the session can be looked up by, `attachments` files stored with the session encoded \
+    as base64 and `versions` earlier versions of the record, newest first. Times are in UTC.";
+
+#[derive(Serialize)]
+struct SessionExport<'a> {
+    about: &'static str,
+    exported_at: String,
+    session_id: String,
+    record: ExportedRecord<'a>,
+    metadata: ExportedMetadata,
+    indexes: BTreeMap<&'a str, Vec<serde_json::Value>>,
+    attachments: Vec<ExportedAttachment>,
+    versions: Vec<ExportedVersion<'a>>,
+}
+
+#[derive(Serialize)]
+struct ExportedRecord<'a> {
+    data: &'a HashMap<String, serde_json::Value>,
+    expires_at: String,
+}
+
+impl<'a> From<&'a Record> for ExportedRecord<'a> {
+    fn from(record: &'a Record) -> Self {
+        ExportedRecord {
+            data: &record.data,
+            expires_at: format_time(record.expiry_date),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExportedMetadata {
+    size_bytes: u64,
+    created_at: Option<String>,
+    modified_at: String,
+    last_accessed_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExportedAttachment {
+    name: String,
+    size_bytes: usize,
+    base64: String,
+}
+
+#[derive(Serialize)]
+struct ExportedVersion<'a> {
+    version: u32,
+    record: ExportedRecord<'a>,
+}
+
+fn format_time(time: impl Into<OffsetDateTime>) -> String {
+    time.into().to_string()
+}
+
+impl FileSessionStorage {
+    /// Write everything stored for a session to `writer` as pretty printed JSON, to answer data
+    /// subject access requests. Returns `false` if the session doesn't exist.
+    ///
+    /// The export includes the record, its metadata, the keys it is indexed under, its blobs and
+    /// the older versions kept with [`set_keep_versions`](Self::set_keep_versions), and explains
+    /// its fields in `about`. The store keeps no audit log, entries written by
+    /// [hooks](crate::SessionStoreHooks) need to be exported separately. Exporting doesn't count
+    /// as using the session.
+    pub async fn export_session(
+        &self,
+        session_id: &Id,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> session_store::Result<bool> {
+        self.ensure_migrated(session_id).await?;
+        let Some(record) = self.read_record(session_id).await? else {
+            return Ok(false);
+        };
+        let Some(metadata) = self.session_metadata(session_id).await? else {
+            return Ok(false);
+        };
+
+        let indexes = self
+            .indexes
+            .iter()
+            .map(|index| {
+                let values = index
+                    .keys(&record)
+                    .iter()
+                    .filter_map(|key| key.to_value())
+                    .collect();
+                (index.name(), values)
+            })
+            .collect();
+        let mut attachments = Vec::new();
+        for name in self.list_blobs(session_id).await? {
+            if let Some(bytes) = self.get_blob(session_id, &name).await? {
+                attachments.push(ExportedAttachment {
+                    name,
+                    size_bytes: bytes.len(),
+                    base64: STANDARD.encode(bytes),
+                });
+            }
+        }
+        let mut versions = Vec::new();
+        for version in self.list_versions(session_id).await? {
+            if let Some(record) = self.load_version(session_id, version).await? {
+                versions.push((version, record));
+            }
+        }
+
+        let export = SessionExport {
+            about: ABOUT,
+            exported_at: format_time(self.now_utc()),
+            session_id: session_id.to_string(),
+            record: ExportedRecord::from(&record),
+            metadata: ExportedMetadata {
+                size_bytes: metadata.size,
+                created_at: metadata.created.map(format_time),
+                modified_at: format_time(metadata.modified),
+                last_accessed_at: metadata.last_accessed.map(format_time),
+            },
+            indexes,
+            attachments,
+            versions: versions
+                .iter()
+                .map(|(version, record)| ExportedVersion {
+                    version: *version,
+                    record: record.into(),
+                })
+                .collect(),
+        };
+        let json = serde_json::to_vec_pretty(&export)
+            .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        writer
+            .write_all(&json)
+            .await
+            .map_err(|e| session_store::Error::Backend(format!("Failed to write export: {e}")))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| session_store::Error::Backend(format!("Failed to write export: {e}")))?;
+        Ok(true)
+    }
+}
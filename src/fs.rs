@@ -0,0 +1,866 @@
+//! The file system the store keeps sessions on, see [`Fs`].
+
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use tower_sessions_core::session::Id;
+
+use crate::{rt::unblock, TEMP_FILE_SUFFIX};
+
+/// Suffix of the lock files [`RealFs::lock`] takes next to session files.
+pub(crate) const LOCK_FILE_SUFFIX: &str = ".lock";
+
+/// How many entries of a folder are read per blocking call.
+const READ_DIR_BATCH: usize = 32;
+
+/// The file system operations the store needs, so sessions can be kept somewhere other than the
+/// local disk or tests can simulate errors like a full disk with [`MemoryFs`](crate::MemoryFs).
+///
+/// Set it with [`FileSessionStorage::set_fs`](crate::FileSessionStorage::set_fs), the default is
+/// [`RealFs`]. Paths are the ones the store builds from its folder, they are never normalized.
+/// Errors should use the same [`io::ErrorKind`]s as `std::fs`, the store relies on `NotFound` and
+/// `AlreadyExists` in particular.
+#[async_trait]
+pub trait Fs: fmt::Debug + Send + Sync + 'static {
+    /// Create a folder and all its missing parents, like [`std::fs::create_dir_all`].
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Create a file with `contents`, failing with `AlreadyExists` if it already exists.
+    async fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Like [`create_new`](Self::create_new), but the file must never be visible partially
+    /// written, used when creating sessions.
+    ///
+    /// Calls `create_new` by default, for file systems where that is atomic already.
+    async fn create_new_atomic(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.create_new(path, contents).await
+    }
+
+    /// Create or replace a file with `contents`, like [`std::fs::write`].
+    async fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Read the contents of a file, like [`std::fs::read`].
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Move a file or folder, replacing the file at `to`, like [`std::fs::rename`].
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Remove a file, like [`std::fs::remove_file`].
+    async fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove an empty folder, like [`std::fs::remove_dir`].
+    async fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove a folder and everything in it, like [`std::fs::remove_dir_all`].
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// The names of the entries of a folder, in any order.
+    async fn read_dir(&self, path: &Path) -> io::Result<BoxStream<'static, io::Result<OsString>>>;
+
+    /// Information about a file or folder.
+    async fn metadata(&self, path: &Path) -> io::Result<FileMetadata>;
+
+    /// Change the modified date of a file.
+    async fn set_modified(&self, path: &Path, modified: SystemTime) -> io::Result<()>;
+
+    /// Copy a file, returning the number of bytes copied.
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let contents = self.read(from).await?;
+        self.write(to, &contents).await?;
+        Ok(contents.len() as u64)
+    }
+
+    /// Create a hard link at `to` to the file at `from`, unsupported by default.
+    async fn hard_link(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Whether a file or folder exists.
+    async fn try_exists(&self, path: &Path) -> io::Result<bool> {
+        match self.metadata(path).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Lock a file against other processes until the returned guard is dropped, used with
+    /// [`set_cross_process_locking`](crate::FileSessionStorage::set_cross_process_locking).
+    ///
+    /// Shared locks are taken for reading and fail with `NotFound` if `path` doesn't exist,
+    /// exclusive locks are taken before `path` is created or replaced, so it may not exist yet.
+    /// `path` is replaced by renaming a new file over it, so implementations shouldn't lock the
+    /// file itself. Does nothing by default, which is fine for file systems only one process
+    /// uses.
+    async fn lock(&self, _path: &Path, _exclusive: bool) -> io::Result<FileLock> {
+        Ok(FileLock::none())
+    }
+
+    /// What kind of file system a folder is on, returned by
+    /// [`FileSessionStorage::file_system_kind`](crate::FileSessionStorage::file_system_kind).
+    ///
+    /// Returns [`FileSystemKind::Unknown`] by default.
+    async fn file_system_kind(&self, _path: &Path) -> io::Result<FileSystemKind> {
+        Ok(FileSystemKind::Unknown)
+    }
+}
+
+/// The kind of file system the sessions folder is on, see
+/// [`FileSessionStorage::file_system_kind`](crate::FileSessionStorage::file_system_kind).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileSystemKind {
+    /// A disk of this machine.
+    Local,
+    /// NFS.
+    Nfs,
+    /// SMB or CIFS, like a Windows share.
+    Smb,
+    /// Couldn't be determined, for example on other operating systems.
+    Unknown,
+}
+
+impl FileSystemKind {
+    /// Whether the file system is on another machine, [`Nfs`](Self::Nfs) or [`Smb`](Self::Smb).
+    pub fn is_network(self) -> bool {
+        matches!(self, FileSystemKind::Nfs | FileSystemKind::Smb)
+    }
+}
+
+/// Information about a file or folder, returned by [`Fs::metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    is_dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+    created: Option<SystemTime>,
+}
+
+impl FileMetadata {
+    /// A file of `len` bytes.
+    pub fn file(len: u64, modified: SystemTime) -> Self {
+        FileMetadata {
+            is_dir: false,
+            len,
+            modified: Some(modified),
+            created: None,
+        }
+    }
+
+    /// A folder.
+    pub fn dir(modified: SystemTime) -> Self {
+        FileMetadata {
+            is_dir: true,
+            len: 0,
+            modified: Some(modified),
+            created: None,
+        }
+    }
+
+    /// Set when the file was created, unknown by default.
+    pub fn with_created(mut self, created: SystemTime) -> Self {
+        self.created = Some(created);
+        self
+    }
+
+    /// The size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether this is a folder.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Whether this is a file.
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+
+    /// When the file was last changed.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        self.modified
+            .ok_or_else(|| io::ErrorKind::Unsupported.into())
+    }
+
+    /// When the file was created.
+    pub fn created(&self) -> io::Result<SystemTime> {
+        self.created
+            .ok_or_else(|| io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl From<std::fs::Metadata> for FileMetadata {
+    fn from(metadata: std::fs::Metadata) -> Self {
+        FileMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            created: metadata.created().ok(),
+        }
+    }
+}
+
+/// A lock taken with [`Fs::lock`], released when dropped.
+pub struct FileLock(Option<Box<dyn Send + Sync>>);
+
+impl fmt::Debug for FileLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FileLock").field(&self.0.is_some()).finish()
+    }
+}
+
+impl FileLock {
+    /// A lock that is held until `guard` is dropped.
+    pub fn new(guard: impl Send + Sync + 'static) -> Self {
+        FileLock(Some(Box::new(guard)))
+    }
+
+    /// A lock that doesn't lock anything.
+    pub fn none() -> Self {
+        FileLock(None)
+    }
+}
+
+/// The local file system, using `std::fs` on the runtime's blocking threads.
+///
+/// Paths longer than `MAX_PATH` work on Windows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+#[async_trait]
+impl Fs for RealFs {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = os_path(path);
+        unblock(move || std::fs::create_dir_all(path)).await
+    }
+
+    async fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = os_path(path);
+        let contents = contents.to_vec();
+        unblock(move || create_new_in_place(&path, &contents)).await
+    }
+
+    /// On Linux the file is written without a name using `O_TMPFILE` and linked into place once
+    /// it is complete. Elsewhere, or where the file system doesn't support that, it is written to
+    /// a temporary file that is hard linked into place. If hard links aren't supported either, the
+    /// file is written in place.
+    async fn create_new_atomic(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = os_path(path);
+        let contents = contents.to_vec();
+        unblock(move || create_new_atomic(&path, &contents)).await
+    }
+
+    async fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = os_path(path);
+        let contents = contents.to_vec();
+        unblock(move || std::fs::write(path, contents)).await
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = os_path(path);
+        unblock(move || std::fs::read(path)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (os_path(from), os_path(to));
+        unblock(move || std::fs::rename(from, to)).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = os_path(path);
+        unblock(move || std::fs::remove_file(path)).await
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let path = os_path(path);
+        unblock(move || std::fs::remove_dir(path)).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = os_path(path);
+        unblock(move || std::fs::remove_dir_all(path)).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BoxStream<'static, io::Result<OsString>>> {
+        let path = os_path(path);
+        let inner = unblock(move || std::fs::read_dir(path)).await?;
+        let batches = futures::stream::unfold(Some(inner), |inner| async move {
+            let mut inner = inner?;
+            let (inner, batch) = unblock(move || {
+                let batch: VecDeque<_> = inner
+                    .by_ref()
+                    .take(READ_DIR_BATCH)
+                    .map(|entry| entry.map(|entry| entry.file_name()))
+                    .collect();
+                let done = batch.len() < READ_DIR_BATCH;
+                ((!done).then_some(inner), batch)
+            })
+            .await;
+            Some((futures::stream::iter(batch), inner))
+        });
+        Ok(batches.flatten().boxed())
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let path = os_path(path);
+        unblock(move || std::fs::metadata(path).map(FileMetadata::from)).await
+    }
+
+    async fn set_modified(&self, path: &Path, modified: SystemTime) -> io::Result<()> {
+        let path = os_path(path);
+        unblock(move || {
+            OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_modified(modified)
+        })
+        .await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let (from, to) = (os_path(from), os_path(to));
+        unblock(move || std::fs::copy(from, to)).await
+    }
+
+    async fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (os_path(from), os_path(to));
+        unblock(move || std::fs::hard_link(from, to)).await
+    }
+
+    async fn try_exists(&self, path: &Path) -> io::Result<bool> {
+        let path = os_path(path);
+        unblock(move || path.try_exists()).await
+    }
+
+    /// Takes `flock` on a `.<name>.lock` file next to `path`, which unlike `path` is never
+    /// replaced, so every process locks the same file. On network file systems
+    /// `flock` may only lock against processes on the same machine, so both kinds of locks create
+    /// a temporary lock file next to `path` instead, failing if it exists.
+    async fn lock(&self, path: &Path, exclusive: bool) -> io::Result<FileLock> {
+        let path = os_path(path);
+        unblock(move || {
+            // So reading a session that doesn't exist leaves no lock file behind
+            if !exclusive {
+                std::fs::metadata(&path)?;
+            }
+            if cached_file_system(&path).is_ok_and(FileSystemKind::is_network) {
+                return lock_with_file(&path);
+            }
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(lock_file_path(&path)?)?;
+            if exclusive {
+                file.lock()?;
+            } else {
+                file.lock_shared()?;
+            }
+            Ok(FileLock::new(file))
+        })
+        .await
+    }
+
+    /// Detected on Linux and macOS.
+    async fn file_system_kind(&self, path: &Path) -> io::Result<FileSystemKind> {
+        let path = os_path(path);
+        unblock(move || detect_file_system(&path)).await
+    }
+}
+
+/// [`detect_file_system`] for the folder `path` is in, remembered so locking doesn't need a
+/// `statfs` call every time.
+fn cached_file_system(path: &Path) -> io::Result<FileSystemKind> {
+    /// Forget everything once this many folders are known, sharded folders can be many.
+    const CAPACITY: usize = 4096;
+    static KINDS: std::sync::OnceLock<
+        std::sync::Mutex<std::collections::HashMap<PathBuf, FileSystemKind>>,
+    > = std::sync::OnceLock::new();
+
+    let folder = path.parent().unwrap_or(path);
+    let kinds = KINDS.get_or_init(Default::default);
+    if let Some(kind) = kinds.lock().unwrap().get(folder) {
+        return Ok(*kind);
+    }
+    let kind = detect_file_system(path)?;
+    let mut kinds = kinds.lock().unwrap();
+    if kinds.len() >= CAPACITY {
+        kinds.clear();
+    }
+    kinds.insert(folder.to_path_buf(), kind);
+    Ok(kind)
+}
+
+#[cfg(target_os = "linux")]
+fn detect_file_system(path: &Path) -> io::Result<FileSystemKind> {
+    // From `statfs(2)`
+    const NFS_SUPER_MAGIC: u32 = 0x6969;
+    const SMB_SUPER_MAGIC: u32 = 0x517b;
+    const CIFS_MAGIC_NUMBER: u32 = 0xff534d42;
+    const SMB2_MAGIC_NUMBER: u32 = 0xfe534d42;
+
+    // The type of `f_type` differs between architectures
+    Ok(match rustix::fs::statfs(path)?.f_type as u32 {
+        NFS_SUPER_MAGIC => FileSystemKind::Nfs,
+        SMB_SUPER_MAGIC | CIFS_MAGIC_NUMBER | SMB2_MAGIC_NUMBER => FileSystemKind::Smb,
+        _ => FileSystemKind::Local,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn detect_file_system(path: &Path) -> io::Result<FileSystemKind> {
+    let name = rustix::fs::statfs(path)?.f_fstypename.map(|c| c as u8);
+    let name = std::ffi::CStr::from_bytes_until_nul(&name).unwrap_or_default();
+    Ok(match name.to_bytes() {
+        b"nfs" => FileSystemKind::Nfs,
+        b"smbfs" => FileSystemKind::Smb,
+        _ => FileSystemKind::Local,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn detect_file_system(_path: &Path) -> io::Result<FileSystemKind> {
+    Ok(FileSystemKind::Unknown)
+}
+
+/// How old a lock file from [`lock_with_file`] may get before it is assumed to be left behind by
+/// a process that crashed. Held locks are touched well within this, see [`LOCK_FILE_REFRESH`].
+const STALE_LOCK_FILE_AGE: Duration = Duration::from_secs(30);
+
+/// How often the modification time of a held lock file is updated, so it isn't taken for stale.
+const LOCK_FILE_REFRESH: Duration = Duration::from_secs(10);
+
+/// How long [`lock_with_file`] waits for a lock file before giving up.
+const LOCK_FILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The file [`RealFs::lock`] locks for `path`, `.<name>.lock` next to it.
+///
+/// Starts with a dot so it is never mistaken for a session.
+pub(crate) fn lock_file_path(path: &Path) -> io::Result<PathBuf> {
+    let Some(name) = path.file_name() else {
+        return Err(io::ErrorKind::InvalidInput.into());
+    };
+    let mut lock_name = OsString::from(".");
+    lock_name.push(name);
+    lock_name.push(LOCK_FILE_SUFFIX);
+    Ok(path.with_file_name(lock_name))
+}
+
+/// Lock `path` by creating `.<name>.lock.tmp` next to it, waiting while it exists.
+///
+/// Creating a file that must not exist yet is atomic even on old NFS versions. The file holds a
+/// token unique to the lock, so a stale lock file is only removed if it is still the one that was
+/// found stale, and a lock only removes its own file.
+fn lock_with_file(path: &Path) -> io::Result<FileLock> {
+    // Scans report it once it's stale
+    let mut lock_path = lock_file_path(path)?.into_os_string();
+    lock_path.push(TEMP_FILE_SUFFIX);
+    let lock_path = PathBuf::from(lock_path);
+    let token = lock_token();
+    let started = std::time::Instant::now();
+    let mut delay = Duration::from_millis(1);
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(mut file) => {
+                let written = file.write_all(token.as_bytes());
+                let lock = LockFile::hold(lock_path, token);
+                written?;
+                return Ok(FileLock::new(lock));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                if break_stale_lock_file(&lock_path) {
+                    continue;
+                }
+                if started.elapsed() > LOCK_FILE_TIMEOUT {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("timed out waiting for lock file {}", lock_path.display()),
+                    ));
+                }
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A token no other lock file has, from this or another process.
+fn lock_token() -> String {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{}-{count}-{nanos}", std::process::id())
+}
+
+/// Remove the lock file at `lock_path` if it is stale, returns `true` if it was removed.
+///
+/// Checking the age and removing the file can't be one step, another process may break the same
+/// stale lock and take a new one in between. So the file is renamed to a name of our own first,
+/// which only one process can do, and removed only if it still holds the token that was found
+/// stale. Otherwise it is a fresh lock and is put back.
+fn break_stale_lock_file(lock_path: &Path) -> bool {
+    let stale = std::fs::metadata(lock_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| {
+            modified
+                .elapsed()
+                .is_ok_and(|age| age > STALE_LOCK_FILE_AGE)
+        });
+    if !stale {
+        return false;
+    }
+    let Ok(stale_token) = std::fs::read(lock_path) else {
+        return false;
+    };
+    let mut taken_name = lock_path.as_os_str().to_owned();
+    taken_name.push(format!(".{}{TEMP_FILE_SUFFIX}", lock_token()));
+    let taken = PathBuf::from(taken_name);
+    if std::fs::rename(lock_path, &taken).is_err() {
+        // Someone else broke it first
+        return false;
+    }
+    if std::fs::read(&taken).is_ok_and(|token| token == stale_token) {
+        let _ = std::fs::remove_file(&taken);
+        return true;
+    }
+    // A fresh lock replaced the stale one in between, give it back unless another took its place
+    let _ = std::fs::hard_link(&taken, lock_path);
+    let _ = std::fs::remove_file(&taken);
+    false
+}
+
+/// A lock file from [`lock_with_file`], touched while it is held and removed when dropped.
+struct LockFile {
+    path: PathBuf,
+    token: String,
+    /// Dropping this stops the thread touching the file.
+    _stop_refresh: std::sync::mpsc::Sender<()>,
+}
+
+impl LockFile {
+    fn hold(path: PathBuf, token: String) -> Self {
+        let (stop_refresh, stopped) = std::sync::mpsc::channel::<()>();
+        let refresh_path = path.clone();
+        let _ = std::thread::Builder::new()
+            .name("session-lock-refresh".to_owned())
+            .spawn(move || {
+                while let Err(std::sync::mpsc::RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(LOCK_FILE_REFRESH)
+                {
+                    let touched = OpenOptions::new()
+                        .write(true)
+                        .open(&refresh_path)
+                        .and_then(|file| file.set_modified(SystemTime::now()));
+                    if touched.is_err() {
+                        return;
+                    }
+                }
+            });
+        LockFile {
+            path,
+            token,
+            _stop_refresh: stop_refresh,
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Only remove the file if it is still ours
+        if std::fs::read(&self.path).is_ok_and(|token| token == self.token.as_bytes()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// `path` as passed to the OS by [`RealFs`].
+///
+/// On Windows paths are made absolute and get the `\\?\` prefix, so folders nested deeper than
+/// `MAX_PATH` allows work instead of failing with confusing errors.
+#[cfg(windows)]
+pub(crate) fn os_path(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+
+    // Prefixed paths aren't normalized by Windows, so `.`, `..` and `/` are resolved first
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let mut components = absolute.components();
+    let mut verbatim = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut verbatim = OsString::from(r"\\?\");
+                verbatim.push(prefix.as_os_str());
+                PathBuf::from(verbatim)
+            }
+            Prefix::UNC(server, share) => {
+                let mut verbatim = OsString::from(r"\\?\UNC\");
+                verbatim.push(server);
+                verbatim.push(r"\");
+                verbatim.push(share);
+                PathBuf::from(verbatim)
+            }
+            // Already prefixed, or a device
+            _ => return absolute,
+        },
+        _ => return absolute,
+    };
+    verbatim.extend(components);
+    verbatim
+}
+
+/// `path` as passed to the OS by [`RealFs`].
+#[cfg(not(windows))]
+pub(crate) fn os_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+fn create_new_in_place(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create_new(true).write(true).open(path)?;
+    file.write_all(contents)
+}
+
+/// See [`RealFs::create_new_atomic`].
+fn create_new_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if let Some(result) = create_new_unnamed(path, contents) {
+        return result;
+    }
+    create_new_linked(path, contents)
+}
+
+/// Write the file without a name and link it into place, `None` if this isn't supported here.
+#[cfg(target_os = "linux")]
+fn create_new_unnamed(path: &Path, contents: &[u8]) -> Option<io::Result<()>> {
+    use std::os::fd::AsRawFd;
+
+    use rustix::{
+        fs::{linkat, open, AtFlags, Mode, OFlags, CWD},
+        io::Errno,
+    };
+
+    let folder = match path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    let file = match open(
+        folder,
+        OFlags::TMPFILE | OFlags::WRONLY | OFlags::CLOEXEC,
+        Mode::from_raw_mode(0o666),
+    ) {
+        Ok(file) => file,
+        // Old kernels take it for `O_DIRECTORY`, some file systems don't support it
+        Err(Errno::ISDIR | Errno::OPNOTSUPP | Errno::INVAL) => return None,
+        Err(e) => return Some(Err(e.into())),
+    };
+    let mut file = std::fs::File::from(file);
+    if let Err(e) = file.write_all(contents) {
+        return Some(Err(e));
+    }
+    // Linking the descriptor itself needs `CAP_DAC_READ_SEARCH`, its `/proc` entry doesn't
+    let proc_path = format!("/proc/self/fd/{}", file.as_raw_fd());
+    match linkat(CWD, &proc_path, CWD, path, AtFlags::SYMLINK_FOLLOW) {
+        Ok(()) => Some(Ok(())),
+        // No `/proc` mounted
+        Err(Errno::NOENT) if !Path::new("/proc/self/fd").exists() => None,
+        Err(e) => Some(Err(e.into())),
+    }
+}
+
+/// Write the file to a temporary file next to it and hard link that into place, which fails if
+/// the file already exists unlike a rename.
+fn create_new_linked(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let Some(name) = path.file_name() else {
+        return create_new_in_place(path, contents);
+    };
+    // Starts with a dot so it is never mistaken for a session
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(
+        ".{}{TEMP_FILE_SUFFIX}",
+        crate::naming::encode_id(&Id::default())
+    ));
+    let temp_path = path.with_file_name(temp_name);
+    create_new_in_place(&temp_path, contents)?;
+    let result = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        // Probably a file system without hard links
+        Err(_) => create_new_in_place(path, contents),
+    }
+}
+
+/// List a folder of `fs`, yielding entries that remember their path.
+pub(crate) async fn read_dir(fs: &Arc<dyn Fs>, path: &Path) -> io::Result<ReadDir> {
+    Ok(ReadDir {
+        folder: path.to_path_buf(),
+        fs: fs.clone(),
+        names: fs.read_dir(path).await?,
+    })
+}
+
+/// The entries of a folder, returned by [`read_dir`].
+pub(crate) struct ReadDir {
+    folder: PathBuf,
+    fs: Arc<dyn Fs>,
+    names: BoxStream<'static, io::Result<OsString>>,
+}
+
+impl ReadDir {
+    pub(crate) async fn next_entry(&mut self) -> io::Result<Option<DirEntry>> {
+        match self.names.next().await.transpose()? {
+            Some(file_name) => Ok(Some(DirEntry {
+                path: self.folder.join(&file_name),
+                file_name,
+                fs: self.fs.clone(),
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An entry of a folder, returned by [`ReadDir::next_entry`].
+#[derive(Debug, Clone)]
+pub(crate) struct DirEntry {
+    path: PathBuf,
+    file_name: OsString,
+    fs: Arc<dyn Fs>,
+}
+
+impl DirEntry {
+    pub(crate) fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub(crate) fn file_name(&self) -> OsString {
+        self.file_name.clone()
+    }
+
+    pub(crate) async fn metadata(&self) -> io::Result<FileMetadata> {
+        self.fs.metadata(&self.path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder of its own for each test, with a session file in it to lock.
+    fn session_file(test: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("fs-test-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("session");
+        std::fs::write(&path, b"{}").unwrap();
+        path
+    }
+
+    fn lock_path(path: &Path) -> PathBuf {
+        path.with_file_name(format!(".session.lock{TEMP_FILE_SUFFIX}"))
+    }
+
+    #[test]
+    fn lock_file_is_removed_when_dropped() {
+        let path = session_file("drop");
+        let lock = lock_with_file(&path).unwrap();
+        assert!(lock_path(&path).exists());
+        drop(lock);
+        assert!(!lock_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_shared_locks_need_the_file() {
+        let path = session_file("missing");
+        let missing = path.with_file_name("other");
+        let error = RealFs.lock(&missing, false).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(!lock_file_path(&missing).unwrap().exists());
+
+        let lock = RealFs.lock(&missing, true).await.unwrap();
+        assert!(!missing.exists());
+        drop(lock);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn lock_holds_while_the_file_is_replaced() {
+        let path = session_file("replaced");
+        let lock = RealFs.lock(&path, true).await.unwrap();
+        let replacement = path.with_file_name("replacement");
+        std::fs::write(&replacement, b"{}").unwrap();
+        std::fs::rename(&replacement, &path).unwrap();
+
+        // What another process locking the path now would get
+        let other = std::fs::File::open(lock_file_path(&path).unwrap()).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn stale_lock_file_is_broken() {
+        let path = session_file("stale");
+        let stale = std::fs::File::create(lock_path(&path)).unwrap();
+        stale
+            .set_modified(SystemTime::now() - STALE_LOCK_FILE_AGE * 2)
+            .unwrap();
+        drop(stale);
+
+        let lock = lock_with_file(&path).unwrap();
+        drop(lock);
+        assert!(!lock_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn fresh_lock_file_is_kept() {
+        let path = session_file("fresh");
+        std::fs::write(lock_path(&path), b"other").unwrap();
+
+        assert!(!break_stale_lock_file(&lock_path(&path)));
+        assert_eq!(std::fs::read(lock_path(&path)).unwrap(), b"other");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn lock_only_removes_its_own_file() {
+        let path = session_file("own");
+        let lock = lock_with_file(&path).unwrap();
+        // Broken as stale and taken by another process in the meantime
+        std::fs::write(lock_path(&path), b"other").unwrap();
+        drop(lock);
+
+        assert_eq!(std::fs::read(lock_path(&path)).unwrap(), b"other");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::{io, path::Path};
+
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{error::IoResultExt, FileSessionStorage, FileSystemKind};
+
+impl FileSessionStorage {
+    /// Only report the store as healthy if at least this many bytes are free on the disk holding
+    /// the sessions folder.
+    ///
+    /// Defaults to 0, which disables the check.
+    pub fn set_minimum_free_space(mut self, bytes: u64) -> Self {
+        self.minimum_free_space = bytes;
+        self
+    }
+
+    /// Create the sessions folder and check that sessions can be stored, so problems are
+    /// reported at startup instead of on the first request.
+    ///
+    /// Runs the same checks as [`health_check`](Self::health_check) once the folder exists.
+    pub async fn init(&self) -> session_store::Result<()> {
+        let folder = self.folder();
+        self.fs
+            .create_dir_all(&folder)
+            .await
+            .context("create sessions folder", &folder)?;
+        #[cfg(feature = "tracing")]
+        if let Ok(kind) = self.file_system_kind().await {
+            if kind.is_network() {
+                tracing::warn!(
+                    folder = %folder.display(),
+                    ?kind,
+                    "sessions folder is on a network file system, modification times may be \
+                     coarse or from another clock"
+                );
+            }
+        }
+        self.health_check().await
+    }
+
+    /// What kind of file system the sessions folder is on, for logging at startup.
+    ///
+    /// On network file systems cross process locks use lock files instead of `flock`, which may
+    /// only lock within one machine there. Modification times can also be coarse or come from the
+    /// server's clock, which affects the age used by expiry sweeps and
+    /// [`load_if_modified_since`](Self::load_if_modified_since). [`init`](Self::init) logs a
+    /// warning with the `tracing` feature.
+    pub async fn file_system_kind(&self) -> session_store::Result<FileSystemKind> {
+        let folder = self.folder();
+        self.fs
+            .file_system_kind(&folder)
+            .await
+            .context("detect file system of", &folder)
+    }
+
+    /// Check that sessions can be stored, for use in readiness probes.
+    ///
+    /// Verifies that the folder exists, that there is enough free space (see
+    /// [`set_minimum_free_space`](Self::set_minimum_free_space)), and that a test file can be
+    /// written, read back and deleted. Also fails if the folder is on a case insensitive file
+    /// system but the [`FileNaming`](crate::FileNaming) needs a case sensitive one.
+    pub async fn health_check(&self) -> session_store::Result<()> {
+        let folder = self.folder();
+        let metadata = self
+            .fs
+            .metadata(&folder)
+            .await
+            .context("open sessions folder", &folder)?;
+        if !metadata.is_dir() {
+            return Err(session_store::Error::Backend(
+                "Sessions folder is not a directory".to_string(),
+            ));
+        }
+
+        if self.minimum_free_space > 0 {
+            let available = available_space(&folder).context("get free space of", &folder)?;
+            if available < self.minimum_free_space {
+                return Err(session_store::Error::Backend(format!(
+                    "Only {available} bytes free, need at least {}",
+                    self.minimum_free_space
+                )));
+            }
+        }
+
+        // Not a valid session ID, so sweeps and listings will never pick it up
+        let sentinel = folder.join(format!(".health-check-{}", Id::default()));
+        let contents = sentinel.to_string_lossy().into_owned();
+        self.fs
+            .write(&sentinel, contents.as_bytes())
+            .await
+            .context("write test file", &sentinel)?;
+        let read_back = self.fs.read(&sentinel).await;
+        let case_insensitive = if self.naming.needs_case_sensitive_fs() {
+            let name = sentinel.file_name().unwrap_or_default().to_string_lossy();
+            let swapped = folder.join(name.to_uppercase());
+            self.fs.try_exists(&swapped).await.unwrap_or(false)
+        } else {
+            false
+        };
+        let removed = self.fs.remove_file(&sentinel).await;
+        if read_back.ok().as_deref() != Some(contents.as_bytes()) {
+            return Err(session_store::Error::Backend(
+                "Failed to read back test file".to_string(),
+            ));
+        }
+        removed.context("delete test file", &sentinel)?;
+        if case_insensitive {
+            return Err(session_store::Error::Backend(format!(
+                "Sessions folder is on a case insensitive file system, where sessions named by \
+                 {:?} can overwrite each other",
+                self.naming
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn available_space(path: &Path) -> io::Result<u64> {
+    fs4::available_space(crate::fs::os_path(path))
+}
+
+#[cfg(target_os = "wasi")]
+fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
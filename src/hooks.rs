@@ -0,0 +1,177 @@
+use std::{fmt, future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{FileSessionStorage, SlowOperation};
+
+/// Callbacks run by the store after a session changed, for auditing, invalidating caches or
+/// replicating sessions without wrapping the store.
+///
+/// Register them with [`FileSessionStorage::add_hooks`] or
+/// [`FileSessionStorageBuilder::hooks`](crate::FileSessionStorageBuilder::hooks). Every method
+/// does nothing by default. Hooks are awaited before the operation returns, so slow hooks slow
+/// down every request.
+///
+/// Only [`saved`](Self::saved) runs while the session is still locked, for saves through
+/// `SessionStore::save`, so those reach it in order. The other hooks run after the lock is released, so when the same
+/// session is changed concurrently, for example deleted while it is saved, they can see the
+/// changes in a different order than they happened.
+#[async_trait]
+pub trait SessionStoreHooks: Send + Sync + 'static {
+    /// A new session was created.
+    async fn created(&self, _record: &Record) {}
+
+    /// An existing session was saved.
+    async fn saved(&self, _record: &Record) {}
+
+    /// A session was loaded.
+    async fn loaded(&self, _record: &Record) {}
+
+    /// A session was deleted.
+    async fn deleted(&self, _session_id: &Id) {}
+
+    /// An expired session is about to be removed, `record` is its last saved version.
+    ///
+    /// For example to send a "session ended" analytics event or keep a summary elsewhere. What
+    /// happens when it fails is set with [`FileSessionStorage::set_before_expire_failure`].
+    async fn before_expire(&self, _record: &Record) -> session_store::Result<()> {
+        Ok(())
+    }
+
+    /// A session was removed by the expiry sweep, `record` is its last saved version.
+    async fn expired(&self, _record: &Record) {}
+
+    /// An operation took longer than the threshold set with
+    /// [`FileSessionStorage::set_slow_operation_threshold`].
+    async fn slow_operation(&self, _operation: &SlowOperation) {}
+}
+
+/// What to do with an expired session when [`SessionStoreHooks::before_expire`] fails, set with
+/// [`FileSessionStorage::set_before_expire_failure`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HookFailure {
+    /// Remove the session anyway.
+    #[default]
+    Skip,
+    /// Keep the session, so the next sweep runs the hooks again.
+    Abort,
+}
+
+/// Runs the closure passed to [`FileSessionStorage::on_expired`].
+pub(crate) struct ExpiredCallback<F>(pub(crate) F);
+
+#[async_trait]
+impl<F, Fut> SessionStoreHooks for ExpiredCallback<F>
+where
+    F: Fn(Record) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    async fn expired(&self, record: &Record) {
+        (self.0)(record.clone()).await;
+    }
+}
+
+/// The hooks registered on a store, shared between clones.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks(Arc<Vec<Arc<dyn SessionStoreHooks>>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("count", &self.0.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub(crate) fn push(&mut self, hooks: impl SessionStoreHooks) {
+        let mut all = (*self.0).clone();
+        all.push(Arc::new(hooks));
+        self.0 = Arc::new(all);
+    }
+
+    pub(crate) async fn created(&self, record: &Record) {
+        for hooks in self.0.iter() {
+            hooks.created(record).await;
+        }
+    }
+
+    pub(crate) async fn saved(&self, record: &Record) {
+        for hooks in self.0.iter() {
+            hooks.saved(record).await;
+        }
+    }
+
+    pub(crate) async fn loaded(&self, record: &Record) {
+        for hooks in self.0.iter() {
+            hooks.loaded(record).await;
+        }
+    }
+
+    pub(crate) async fn deleted(&self, session_id: &Id) {
+        for hooks in self.0.iter() {
+            hooks.deleted(session_id).await;
+        }
+    }
+
+    /// Run every `before_expire` hook, returns the errors of those that failed.
+    pub(crate) async fn before_expire(&self, record: &Record) -> Vec<session_store::Error> {
+        let mut errors = Vec::new();
+        for hooks in self.0.iter() {
+            if let Err(e) = hooks.before_expire(record).await {
+                errors.push(e);
+            }
+        }
+        errors
+    }
+
+    pub(crate) async fn expired(&self, record: &Record) {
+        for hooks in self.0.iter() {
+            hooks.expired(record).await;
+        }
+    }
+
+    pub(crate) async fn slow_operation(&self, operation: &SlowOperation) {
+        for hooks in self.0.iter() {
+            hooks.slow_operation(operation).await;
+        }
+    }
+}
+
+impl FileSessionStorage {
+    /// Run `hooks` whenever a session is created, saved, loaded, deleted or expired.
+    ///
+    /// Hooks added earlier run first.
+    pub fn add_hooks(mut self, hooks: impl SessionStoreHooks) -> Self {
+        self.hooks.push(hooks);
+        self
+    }
+
+    /// Run `callback` with the last saved version of every session removed because it expired,
+    /// for example to revoke refresh tokens that belong to it or tell the user they were logged
+    /// out.
+    ///
+    /// It runs as an [`expired`](SessionStoreHooks::expired) hook, so the sweep waits for it.
+    /// Spawn a task from it for slow work like calling a webhook.
+    pub fn on_expired<F, Fut>(self, callback: F) -> Self
+    where
+        F: Fn(Record) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.add_hooks(ExpiredCallback(callback))
+    }
+
+    /// Whether an expired session is still removed when one of its
+    /// [`before_expire`](SessionStoreHooks::before_expire) hooks fails.
+    ///
+    /// Failures are recorded for [`dump_report`](Self::dump_report) either way. Defaults to
+    /// [`HookFailure::Skip`].
+    pub fn set_before_expire_failure(mut self, on_failure: HookFailure) -> Self {
+        self.before_expire_failure = on_failure;
+        self
+    }
+}
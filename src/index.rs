@@ -0,0 +1,440 @@
+use std::{borrow::Cow, fmt, path::PathBuf, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    is_valid_folder_name,
+    naming::encode_id,
+    session_id_from_file_name, FileSessionStorage,
+};
+
+/// Name of the folder inside the sessions folder that holds the indexes.
+pub(crate) const INDEX_FOLDER: &str = ".index";
+
+/// Name of the index maintained by [`FileSessionStorage::set_user_index`].
+pub(crate) const USER_INDEX: &str = "user";
+
+/// Longest file name used for an index key, most file systems allow up to 255 bytes.
+const MAX_KEY_FILE_NAME: usize = 200;
+
+type Extractor = dyn Fn(&Record) -> Vec<IndexKey> + Send + Sync;
+
+/// A value sessions can be looked up by in an index, see [`FileSessionStorage::add_index`].
+///
+/// Keys are compared by their JSON representation, so `IndexKey::new(42)` and
+/// `IndexKey::new("42")` are different keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IndexKey(String);
+
+impl IndexKey {
+    /// Create a key from any JSON value.
+    pub fn new(value: impl Into<serde_json::Value>) -> Self {
+        IndexKey(value.into().to_string())
+    }
+
+    /// The key as a file name, its JSON in lowercase hex.
+    ///
+    /// Hex can't differ only in case, so keys don't share a folder on case insensitive file
+    /// systems. Long keys are cut short and end in a hash of the whole key instead, entries are
+    /// checked against the record so keys sharing a folder are still told apart.
+    fn file_name(&self) -> String {
+        let hex: String = self.0.bytes().map(|b| format!("{b:02x}")).collect();
+        if hex.len() <= MAX_KEY_FILE_NAME {
+            return hex;
+        }
+        // FNV-1a, which unlike the hasher of the standard library is the same in every version
+        let hash = self.0.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+        format!("{}-{hash:016x}", &hex[..MAX_KEY_FILE_NAME - 17])
+    }
+
+    /// The base64 file name older versions used, which can clash on case insensitive file
+    /// systems.
+    fn legacy_file_name(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.0)
+    }
+
+    /// The JSON value the key was created from.
+    pub(crate) fn to_value(&self) -> Option<serde_json::Value> {
+        serde_json::from_str(&self.0).ok()
+    }
+}
+
+/// A secondary index, mapping keys extracted from records to the sessions they were found in.
+///
+/// Each entry is an empty file at `.index/<index name>/<key>/<session id>`. Entries can be stale
+/// if a process crashed halfway through a write, so they are always checked against the record
+/// before being used.
+#[derive(Clone)]
+pub(crate) struct SessionIndex {
+    name: Cow<'static, str>,
+    extractor: Arc<Extractor>,
+}
+
+impl fmt::Debug for SessionIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionIndex")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionIndex {
+    pub(crate) fn new(
+        name: Cow<'static, str>,
+        extractor: impl Fn(&Record) -> Vec<IndexKey> + Send + Sync + 'static,
+    ) -> Self {
+        SessionIndex {
+            name,
+            extractor: Arc::new(extractor),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The keys `record` is found under in this index.
+    pub(crate) fn keys(&self, record: &Record) -> Vec<IndexKey> {
+        (self.extractor)(record)
+    }
+
+    /// Index sessions by the value of `data_key` in the session data.
+    pub(crate) fn user(data_key: String) -> Self {
+        SessionIndex {
+            name: Cow::Borrowed(USER_INDEX),
+            extractor: Arc::new(move |record: &Record| {
+                record
+                    .data
+                    .get(&data_key)
+                    .filter(|value| !value.is_null())
+                    .map(|value| IndexKey::new(value.clone()))
+                    .into_iter()
+                    .collect()
+            }),
+        }
+    }
+}
+
+impl FileSessionStorage {
+    /// Keep an index of sessions by the user they belong to, so all sessions of a user can be
+    /// found without loading every session.
+    ///
+    /// `data_key` is the key in the session data that holds the user identifier, the value must be
+    /// the same JSON value when looking it up, so `42` and `"42"` are different users.
+    pub fn set_user_index(mut self, data_key: impl Into<String>) -> Self {
+        self.insert_index(SessionIndex::user(data_key.into()));
+        self
+    }
+
+    /// Keep an index of sessions by the keys `extractor` returns for them, so sessions can be
+    /// found by tenant, device, role etc. without loading every session.
+    ///
+    /// The index is kept on disk and maintained by `create`, `save` and `delete`, it only covers
+    /// sessions written since it was added. Adding an index with the same name replaces it.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is empty or contains characters other than ASCII letters, digits, `-` and `_`.
+    pub fn add_index(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        extractor: impl Fn(&Record) -> Vec<IndexKey> + Send + Sync + 'static,
+    ) -> Self {
+        let index = SessionIndex::new(name.into(), extractor);
+        assert!(
+            is_valid_folder_name(index.name()),
+            "invalid index name {:?}",
+            index.name()
+        );
+        self.insert_index(index);
+        self
+    }
+
+    /// Add an index, replacing any existing one with the same name.
+    pub(crate) fn insert_index(&mut self, index: SessionIndex) {
+        let mut indexes = (*self.indexes).clone();
+        indexes.retain(|existing| existing.name != index.name);
+        indexes.push(index);
+        self.indexes = Arc::new(indexes);
+    }
+
+    fn find_index(&self, name: &str) -> session_store::Result<&SessionIndex> {
+        self.indexes
+            .iter()
+            .find(|index| index.name == name)
+            .ok_or_else(|| session_store::Error::Backend(format!("No index named {name}")))
+    }
+
+    fn index_key_folder(&self, index: &SessionIndex, key: &IndexKey) -> PathBuf {
+        self.folder()
+            .join(INDEX_FOLDER)
+            .join(index.name.as_ref())
+            .join(key.file_name())
+    }
+
+    /// The folders entries for `key` can be in, the current one and the one older versions
+    /// wrote, if its name wasn't too long to be written.
+    fn index_key_folders(&self, index: &SessionIndex, key: &IndexKey) -> Vec<PathBuf> {
+        let index_folder = self.folder().join(INDEX_FOLDER).join(index.name.as_ref());
+        let legacy_file_name = key.legacy_file_name();
+        let mut folders = vec![index_folder.join(key.file_name())];
+        // Longer names couldn't be created by older versions either
+        if legacy_file_name.len() <= 255 {
+            folders.push(index_folder.join(legacy_file_name));
+        }
+        folders
+    }
+
+    /// Add index entries for a record that was just written.
+    pub(crate) async fn add_to_indexes(&self, record: &Record) -> session_store::Result<()> {
+        for index in self.indexes.iter() {
+            for key in (index.extractor)(record) {
+                let folder = self.index_key_folder(index, &key);
+                self.fs
+                    .create_dir_all(&folder)
+                    .await
+                    .context("create index folder", &folder)?;
+                let path = folder.join(encode_id(&record.id));
+                self.fs
+                    .write(&path, &[])
+                    .await
+                    .context("write index entry", &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the index entries of `old` that no longer apply to `new`.
+    ///
+    /// Pass `None` for `new` if the session was deleted.
+    pub(crate) async fn remove_from_indexes(
+        &self,
+        old: &Record,
+        new: Option<&Record>,
+    ) -> session_store::Result<()> {
+        for index in self.indexes.iter() {
+            let new_keys = new.map(|new| (index.extractor)(new)).unwrap_or_default();
+            for key in (index.extractor)(old) {
+                if new_keys.contains(&key) {
+                    continue;
+                }
+                for folder in self.index_key_folders(index, &key) {
+                    let path = folder.join(encode_id(&old.id));
+                    match self.fs.remove_file(&path).await {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(FileError::new("delete index entry", &path, e).into()),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the sessions that `index` lists under `key`, dropping entries that turn out to be
+    /// stale.
+    async fn indexed_records(
+        &self,
+        index: &SessionIndex,
+        key: &IndexKey,
+    ) -> session_store::Result<Vec<Record>> {
+        let mut records: Vec<Record> = Vec::new();
+        for folder in self.index_key_folders(index, key) {
+            let mut entries = match crate::fs::read_dir(&self.fs, &folder).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(FileError::new("list index", &folder, e).into()),
+            };
+            while let Some(dir_entry) = entries.next_entry().await.context("list index", &folder)? {
+                let Some(session_id) = session_id_from_file_name(&dir_entry.file_name()) else {
+                    continue;
+                };
+                if records.iter().any(|record| record.id == session_id) {
+                    continue;
+                }
+                // Hold the lock so a concurrent save can't add the entry back in between
+                let _guard = self.locks.lock(session_id).await;
+                let Some(record) = self.read_record(&session_id).await? else {
+                    let _ = self.fs.remove_file(&dir_entry.path()).await;
+                    continue;
+                };
+                let keys = (index.extractor)(&record);
+                if keys.contains(key) {
+                    records.push(record);
+                } else if !keys
+                    .iter()
+                    .any(|other| self.index_key_folders(index, other).contains(&folder))
+                {
+                    // Only stale if no key of the session uses this folder, keys that differ in
+                    // case share a legacy folder on case insensitive file systems
+                    let _ = self.fs.remove_file(&dir_entry.path()).await;
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Find the IDs of all sessions the index `name` lists under `key`.
+    pub async fn lookup_index(&self, name: &str, key: &IndexKey) -> session_store::Result<Vec<Id>> {
+        let index = self.find_index(name)?;
+        Ok(self
+            .indexed_records(index, key)
+            .await?
+            .into_iter()
+            .map(|record| record.id)
+            .collect())
+    }
+
+    /// Delete all sessions the index `name` lists under `key`.
+    ///
+    /// Returns the number of sessions deleted.
+    pub async fn delete_by_index_key(
+        &self,
+        name: &str,
+        key: &IndexKey,
+    ) -> session_store::Result<usize> {
+        let index = self.find_index(name)?;
+        let mut deleted = 0;
+        for record in self.indexed_records(index, key).await? {
+            if self.delete_session(&record.id).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Delete all sessions of a user, for example after they changed their password.
+    ///
+    /// Requires [`set_user_index`](Self::set_user_index), without it no sessions are deleted.
+    /// Returns the number of sessions deleted.
+    pub async fn delete_all_for_user(
+        &self,
+        user: impl Into<serde_json::Value>,
+    ) -> session_store::Result<usize> {
+        if self.find_index(USER_INDEX).is_err() {
+            return Ok(0);
+        }
+        self.delete_by_index_key(USER_INDEX, &IndexKey::new(user))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::{
+        tests::{record, store},
+        Fs,
+    };
+
+    #[tokio::test]
+    async fn lookup_follows_saves_and_deletes() {
+        let (store, _, clock) = store();
+        let store = store.set_user_index("user");
+        let mut session = record(&clock, Duration::from_secs(3600));
+        session.data.insert("user".to_string(), 42.into());
+        store.create(&mut session).await.unwrap();
+        let mut other = record(&clock, Duration::from_secs(3600));
+        other.data.insert("user".to_string(), "42".into());
+        store.create(&mut other).await.unwrap();
+
+        let user = IndexKey::new(42);
+        assert_eq!(
+            store.lookup_index(USER_INDEX, &user).await.unwrap(),
+            vec![session.id]
+        );
+
+        session.data.insert("user".to_string(), 7.into());
+        store.save(&session).await.unwrap();
+        assert_eq!(store.lookup_index(USER_INDEX, &user).await.unwrap(), vec![]);
+        assert_eq!(
+            store
+                .lookup_index(USER_INDEX, &IndexKey::new(7))
+                .await
+                .unwrap(),
+            vec![session.id]
+        );
+
+        store.delete(&session.id).await.unwrap();
+        assert_eq!(
+            store
+                .lookup_index(USER_INDEX, &IndexKey::new(7))
+                .await
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_entries_are_removed() {
+        let (store, fs, clock) = store();
+        let store = store.set_user_index("user");
+        let session = record(&clock, Duration::from_secs(3600));
+        let key = IndexKey::new(42);
+        // An entry left behind by a crash, for a session that doesn't exist
+        let folder = store.index_key_folder(store.find_index(USER_INDEX).unwrap(), &key);
+        fs.create_dir_all(&folder).await.unwrap();
+        let entry = folder.join(encode_id(&session.id));
+        fs.write(&entry, &[]).await.unwrap();
+
+        assert_eq!(store.lookup_index(USER_INDEX, &key).await.unwrap(), vec![]);
+        assert!(fs.metadata(&entry).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn delete_all_for_user_deletes_only_their_sessions() {
+        let (store, _, clock) = store();
+        let store = store.set_user_index("user");
+        let mut ids = Vec::new();
+        for user in [1, 1, 2] {
+            let mut session = record(&clock, Duration::from_secs(3600));
+            session.data.insert("user".to_string(), user.into());
+            store.create(&mut session).await.unwrap();
+            ids.push(session.id);
+        }
+
+        assert_eq!(store.delete_all_for_user(1).await.unwrap(), 2);
+        assert_eq!(store.list_session_ids().await.unwrap(), vec![ids[2]]);
+    }
+
+    #[tokio::test]
+    async fn legacy_entries_are_found() {
+        let (store, fs, clock) = store();
+        let store = store.set_user_index("user");
+        let mut session = record(&clock, Duration::from_secs(3600));
+        session.data.insert("user".to_string(), 42.into());
+        store.create(&mut session).await.unwrap();
+        let index = store.find_index(USER_INDEX).unwrap();
+        let key = IndexKey::new(42);
+        let [folder, legacy_folder] = store.index_key_folders(index, &key).try_into().unwrap();
+        fs.rename(&folder, &legacy_folder).await.unwrap();
+
+        assert_eq!(
+            store.lookup_index(USER_INDEX, &key).await.unwrap(),
+            vec![session.id]
+        );
+    }
+
+    #[test]
+    fn long_keys_fit_in_a_file_name() {
+        let short = IndexKey::new("user");
+        assert_eq!(short.file_name(), "227573657222");
+
+        let long = IndexKey::new("u".repeat(300));
+        let other = IndexKey::new(format!("{}v", "u".repeat(299)));
+        assert_eq!(long.file_name().len(), MAX_KEY_FILE_NAME);
+        assert_ne!(long.file_name(), other.file_name());
+        assert_eq!(long.file_name(), IndexKey::new("u".repeat(300)).file_name());
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    collections::BinaryHeap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{
+    error::{decode_json, FileError, IoResultExt},
+    fs::{DirEntry, ReadDir},
+    FileSessionStorage,
+};
+
+/// Number of sessions in the store, returned by [`FileSessionStorage::count_sessions_by_expiry`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionCounts {
+    /// Sessions that haven't expired yet.
+    pub active: usize,
+    /// Sessions that expired but haven't been deleted yet.
+    pub expired: usize,
+}
+
+/// Information about a session file, returned by [`FileSessionStorage::session_metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SessionMetadata {
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// When the session was created.
+    ///
+    /// Sessions stored by older versions don't record it, for them this is the birth time of the
+    /// file if the file system records it, which is when the session was last saved.
+    pub created: Option<SystemTime>,
+    /// When the session was last saved.
+    pub modified: SystemTime,
+    /// When the session was last loaded, only recorded with
+    /// [`set_track_last_access`](FileSessionStorage::set_track_last_access).
+    pub last_accessed: Option<SystemTime>,
+}
+
+/// One page of session IDs, returned by [`FileSessionStorage::list_sessions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionPage {
+    /// The IDs on this page, ordered by session ID.
+    pub session_ids: Vec<Id>,
+    /// Pass this to `list_sessions` to get the next page, `None` if this is the last page.
+    pub next_cursor: Option<Id>,
+}
+
+/// Only the expiry date of a record, all other fields are skipped while parsing.
+#[derive(Deserialize)]
+struct ExpiryOnly {
+    expiry_date: OffsetDateTime,
+}
+
+/// A folder being listed by [`FileSessionStorage::entries_in`], with its path relative to the
+/// sessions folder.
+struct Listing {
+    entries: ReadDir,
+    relative: PathBuf,
+}
+
+enum EntriesState {
+    Start,
+    Reading(Vec<Listing>),
+    Done,
+}
+
+impl FileSessionStorage {
+    /// Lazily walk the folder, yielding every file that is named like a session.
+    ///
+    /// A missing folder is treated as an empty store.
+    pub(crate) fn session_entries(
+        &self,
+    ) -> impl Stream<Item = session_store::Result<(Id, DirEntry)>> + Send + 'static {
+        self.entries_in(self.folder())
+    }
+
+    /// Lazily walk `folder` as laid out by the file naming, yielding every session file.
+    pub(crate) fn entries_in(
+        &self,
+        folder_name: Arc<Path>,
+    ) -> impl Stream<Item = session_store::Result<(Id, DirEntry)>> + Send + 'static {
+        let fs = self.fs.clone();
+        let naming = self.naming.clone();
+        stream::unfold(EntriesState::Start, move |state| {
+            let folder_name = folder_name.clone();
+            let fs = fs.clone();
+            let naming = naming.clone();
+            async move {
+                let mut listings = match state {
+                    EntriesState::Start => match crate::fs::read_dir(&fs, &folder_name).await {
+                        Ok(entries) => vec![Listing {
+                            entries,
+                            relative: PathBuf::new(),
+                        }],
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+                        Err(e) => {
+                            return Some((
+                                Err(FileError::new("list folder", &folder_name, e).into()),
+                                EntriesState::Done,
+                            ))
+                        }
+                    },
+                    EntriesState::Reading(listings) => listings,
+                    EntriesState::Done => return None,
+                };
+                loop {
+                    let depth = listings.len().checked_sub(1)?;
+                    let listing = listings.last_mut()?;
+                    let dir_entry = match listing.entries.next_entry().await {
+                        Ok(Some(dir_entry)) => dir_entry,
+                        Ok(None) => {
+                            listings.pop();
+                            continue;
+                        }
+                        Err(e) => {
+                            return Some((
+                                Err(FileError::new("list folder", &folder_name, e).into()),
+                                EntriesState::Done,
+                            ))
+                        }
+                    };
+                    let relative = listing.relative.join(dir_entry.file_name());
+                    if depth == naming.depth() {
+                        if let Some(session_id) = naming.session_id(&relative) {
+                            return Some((
+                                Ok((session_id, dir_entry)),
+                                EntriesState::Reading(listings),
+                            ));
+                        }
+                        continue;
+                    }
+                    // Skip the folders of the store itself, like `.index` and blob folders
+                    let name = dir_entry.file_name();
+                    let name = name.to_string_lossy();
+                    if name.starts_with('.') || name.ends_with(".blobs") {
+                        continue;
+                    }
+                    match crate::fs::read_dir(&fs, &dir_entry.path()).await {
+                        Ok(entries) => listings.push(Listing { entries, relative }),
+                        // A file, or deleted since we listed the folder
+                        Err(_) => continue,
+                    }
+                }
+            }
+        })
+    }
+
+    /// List the IDs of all sessions currently in the store, including expired ones that haven't
+    /// been deleted yet.
+    ///
+    /// Files in the folder that aren't sessions are ignored.
+    pub async fn list_session_ids(&self) -> session_store::Result<Vec<Id>> {
+        self.session_entries()
+            .map_ok(|(session_id, _)| session_id)
+            .try_collect()
+            .await
+    }
+
+    /// List one page of session IDs, ordered by session ID.
+    ///
+    /// Start with `None` as the cursor, then pass the `next_cursor` of the previous page. Only
+    /// `limit` IDs are kept in memory at a time, but the whole folder still needs to be listed for
+    /// every page. Sessions created while paging might be skipped if they sort before the cursor.
+    /// A `limit` of 0 is treated as 1, so every page but the last has a cursor to continue from.
+    pub async fn list_sessions(
+        &self,
+        cursor: Option<&Id>,
+        limit: usize,
+    ) -> session_store::Result<SessionPage> {
+        let limit = limit.max(1);
+        let cursor = cursor.map(|id| id.to_string());
+        // Max-heap of the `limit + 1` smallest IDs after the cursor, the extra one tells us
+        // whether there is a next page
+        let page = self
+            .session_entries()
+            .try_fold(BinaryHeap::new(), |mut page, (session_id, _)| {
+                let name = session_id.to_string();
+                if cursor.as_ref().is_none_or(|cursor| &name > cursor) {
+                    page.push((name, session_id.0));
+                    if page.len() > limit + 1 {
+                        page.pop();
+                    }
+                }
+                async move { Ok(page) }
+            })
+            .await?;
+
+        let mut session_ids: Vec<Id> = page
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(_, session_id)| Id(session_id))
+            .collect();
+        let next_cursor = if session_ids.len() > limit {
+            session_ids.truncate(limit);
+            session_ids.last().copied()
+        } else {
+            None
+        };
+        Ok(SessionPage {
+            session_ids,
+            next_cursor,
+        })
+    }
+
+    /// Lazily load every session in the store, including expired ones that haven't been deleted
+    /// yet.
+    ///
+    /// Only one record is kept in memory at a time, so this is suitable for building reports over
+    /// large stores. Sessions deleted while iterating are skipped.
+    pub fn iter_sessions(
+        &self,
+    ) -> impl Stream<Item = session_store::Result<Record>> + Send + 'static {
+        let store = self.clone();
+        self.session_entries()
+            .then(move |entry| {
+                let store = store.clone();
+                async move {
+                    let (session_id, _) = entry?;
+                    store.read_record(&session_id).await
+                }
+            })
+            .filter_map(|record| async move { record.transpose() })
+    }
+
+    /// Find the IDs of all sessions where `key` in the session data is equal to `value`, for
+    /// example all sessions of a single user.
+    ///
+    /// This needs to load every session in the store, results are streamed as they are found.
+    pub fn find_sessions(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> impl Stream<Item = session_store::Result<Id>> + Send + 'static {
+        let key = key.into();
+        let value = value.into();
+        self.iter_sessions().try_filter_map(move |record| {
+            let matches = record.data.get(&key) == Some(&value);
+            async move { Ok(matches.then_some(record.id)) }
+        })
+    }
+
+    /// Count the sessions in the store, including expired ones that haven't been deleted yet.
+    ///
+    /// This only lists the folder and doesn't open any files.
+    pub async fn count_sessions(&self) -> session_store::Result<usize> {
+        self.session_entries()
+            .try_fold(0, |count, _| async move { Ok(count + 1) })
+            .await
+    }
+
+    /// Count the sessions in the store, split into active and expired ones.
+    ///
+    /// Unlike [`count_sessions`](Self::count_sessions) this needs to load every session.
+    pub async fn count_sessions_by_expiry(&self) -> session_store::Result<SessionCounts> {
+        let now = self.now_utc();
+        self.iter_sessions()
+            .try_fold(SessionCounts::default(), |mut counts, record| async move {
+                if record.expiry_date < now {
+                    counts.expired += 1;
+                } else {
+                    counts.active += 1;
+                }
+                Ok(counts)
+            })
+            .await
+    }
+
+    /// The combined size in bytes of all session files.
+    pub async fn total_disk_usage(&self) -> session_store::Result<u64> {
+        self.session_entries()
+            .try_fold(0, |total, (_, dir_entry)| async move {
+                match dir_entry.metadata().await {
+                    Ok(metadata) => Ok(total + metadata.len()),
+                    // Deleted since we listed the folder
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(total),
+                    Err(e) => Err(FileError::new("get metadata of", dir_entry.path(), e).into()),
+                }
+            })
+            .await
+    }
+
+    /// Check whether a session exists without loading it.
+    ///
+    /// Expired sessions that haven't been deleted yet still exist.
+    pub async fn exists(&self, session_id: &Id) -> session_store::Result<bool> {
+        let path = self.session_path(session_id);
+        let exists = self
+            .fs
+            .try_exists(&path)
+            .await
+            .context("get metadata of", &path)?;
+        Ok(exists || self.adopt_legacy_name(session_id).await?)
+    }
+
+    /// Get just the expiry date of a session, `None` if it doesn't exist.
+    ///
+    /// Cheaper than a full load since the session data is skipped instead of deserialized.
+    pub async fn get_expiry(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<OffsetDateTime>> {
+        let path = self.session_path(session_id);
+        let _lock = match self.lock_file(&path, false).await {
+            Ok(lock) => lock,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return self.get_expiry_legacy(session_id).await
+            }
+            Err(e) => return Err(FileError::new("lock", &path, e).into()),
+        };
+        let contents = match self.fs.read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return self.get_expiry_legacy(session_id).await
+            }
+            Err(e) => return Err(FileError::new("read", &path, e).into()),
+        };
+        let ExpiryOnly { expiry_date } = decode_json(&contents)?;
+        Ok(Some(expiry_date))
+    }
+
+    /// [`get_expiry`](Self::get_expiry) for a session that wasn't found, in case it is still
+    /// named by an older version.
+    async fn get_expiry_legacy(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<OffsetDateTime>> {
+        if !self.adopt_legacy_name(session_id).await? {
+            return Ok(None);
+        }
+        Box::pin(self.get_expiry(session_id)).await
+    }
+
+    /// Get information about a session without loading it, `None` if it doesn't exist.
+    pub async fn session_metadata(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<SessionMetadata>> {
+        let path = self.session_path(session_id);
+        let metadata = match self.fs.metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if !self.adopt_legacy_name(session_id).await? {
+                    return Ok(None);
+                }
+                self.fs
+                    .metadata(&path)
+                    .await
+                    .context("get metadata of", &path)?
+            }
+            Err(e) => return Err(FileError::new("get metadata of", &path, e).into()),
+        };
+        let modified = metadata.modified().context("get modified date of", &path)?;
+        Ok(Some(SessionMetadata {
+            size: metadata.len(),
+            created: self.created_at_of(&path).await?.map(SystemTime::from),
+            modified,
+            last_accessed: self.last_access(session_id).await?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn pages_cover_every_session_in_order() {
+        let (store, _, clock) = store();
+        let mut created = Vec::new();
+        for _ in 0..5 {
+            let mut session = record(&clock, Duration::from_secs(60));
+            store.create(&mut session).await.unwrap();
+            created.push(session.id);
+        }
+        created.sort_by_key(|id| id.to_string());
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = store.list_sessions(cursor.as_ref(), 2).await.unwrap();
+            assert!(page.session_ids.len() <= 2);
+            listed.extend(page.session_ids);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(listed, created);
+    }
+}
@@ -5,6 +5,20 @@
 //! Useful when you want something more persistant than a in memory store but don't want to setup an entire database, especially
 //! during local development. Should work fine in production environments though.
 //!
+//! # Configuration
+//!
+//! Simple options can be set using the `set_*` methods, or use a builder to have the whole configuration checked at once:
+//!
+//! ```rs
+//! let session_store = FileSessionStorage::builder()
+//!     .folder(Path::new("/var/lib/my-app/sessions"))
+//!     .minimum_expiry_date(Duration::from_secs(60 * 60))
+//!     .build()?;
+//! ```
+//!
+//! `FileSessionStorage::from_env` reads the same options from `SESSION_STORE_*` environment variables instead, and
+//! `FileSessionStorageConfig` can be deserialized from the configuration file of your application.
+//!
 //! # Expiry
 //!
 //! You can enable automatically deleting expired sessions like this:
@@ -19,31 +33,278 @@
 //!
 //! By default, it will only load sessions to check their expirty if the last modified date of the file is at least 60 seconds. You can adjust this with
 //! `set_minimum_expiry_date`. Ideally the expiry date would be the same as the duration of your sessions.
+//!
+//! Expired sessions are never loaded, even before a sweep reaches them. Loading one deletes it instead.
+//!
+//! To delete sessions right when they expire instead, spawn `continuously_delete_on_expiry`, which keeps a timer wheel
+//! of the expiry dates.
+//!
+//! If several instances of your application share the same folder, use `set_sweep_partition` to give each of them a
+//! share of the sessions to check.
+//!
+//! For code without an async runtime, [`blocking::FileSessionStorageSync`] offers the same operations as blocking
+//! calls on the same on-disk format.
+//!
+//! # Features
+//!
+//! - `admin`: `admin_router`, an axum router to list, inspect and delete sessions.
+//! - `archive`: `snapshot_to` and `restore_from` to backup the sessions folder as a `.tar.gz` archive.
+//! - `tracing`: a `tracing` span for every store operation, with the operation, a hash of the session ID, the size of
+//!   the record, the duration and the result.
+//! - `opentelemetry`: enables `tracing` and adds the OpenTelemetry semantic convention attributes for database calls
+//!   (`db.system = "file"`, `db.operation.name`, `otel.kind = "client"`) to the spans, so APM tools group session I/O
+//!   with other database calls when exported through tracing-opentelemetry.
+//! - `metrics`: counters of store operations by operation and outcome, histograms of their latency and of record
+//!   sizes, and a gauge of the sessions on disk, using the `metrics` facade so any installed exporter picks them up.
+//!   `continuously_report_usage` keeps the session count and disk usage gauges up to date.
+//! - `tower-sessions-012`, `tower-sessions-014` and `tower-sessions-015`: implement `SessionStore` and
+//!   `ExpiredDeletion` of those versions of tower-sessions-core as well, for apps that aren't on 0.13 yet.
+//! - `opendal`: `OpendalFs`, to keep sessions in object storage like S3, GCS or Azure Blob Storage through an opendal
+//!   `Operator`, with the same one object per session layout.
+//! - `redb`: `RedbSessionStorage`, the same zero setup store but with every session in a single redb database file,
+//!   which is faster than a file per session for large numbers of sessions.
+//! - `sqlite`: `SqliteFileSessionStorage`, with every session in a single SQLite database file, for deployments
+//!   running out of inodes. SQLite is bundled.
+//! - `chaos`: `ChaosStore`, a wrapper around any store that injects failures, latency and partial writes, for testing
+//!   how an application copes with session store outages. Only meant for tests.
+//! - `cli`: the `sessions-file-tool` binary to list, inspect, delete and purge expired sessions in a folder, for
+//!   debugging on a server.
+//! - `tokio` (default), `async-std` and `smol`: the runtime blocking file system calls and timers run on. With
+//!   `default-features = false` and one of the others the store never needs a tokio runtime, though `transfer` and
+//!   `dump_report` still take tokio's `AsyncBufRead` and `AsyncWrite` traits.
+//!
+//! # WASI
+//!
+//! The store builds for `wasm32-wasip1` with the default `tokio` feature, so edge runtimes can keep sessions in a
+//! mounted directory. WASI has no threads, so file system calls run inline instead of on a blocking pool. Cross
+//! process locking and `set_minimum_free_space` aren't supported there and return an error when used.
+
+mod access;
+#[cfg(feature = "admin")]
+mod admin;
+mod anonymize;
+#[cfg(feature = "archive")]
+mod archive;
+mod blobs;
+pub mod blocking;
+mod builder;
+mod cache;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod circuit;
+mod clock;
+#[cfg(any(
+    feature = "tower-sessions-012",
+    feature = "tower-sessions-014",
+    feature = "tower-sessions-015"
+))]
+mod compat;
+mod conditional;
+mod config;
+mod created;
+mod deletion;
+mod disk_full;
+mod env;
+mod error;
+mod events;
+mod expired_archive;
+mod expiry_wheel;
+mod export;
+mod fs;
+mod health;
+mod hooks;
+mod index;
+mod inspect;
+mod lock;
+mod memory_fs;
+mod migrating;
+mod mirror;
+mod namespace;
+mod naming;
+#[cfg(feature = "opendal")]
+mod opendal_fs;
+mod platform;
+mod rate_limit;
+#[cfg(feature = "redb")]
+mod redb_store;
+mod relocate;
+mod report;
+mod rt;
+mod scan;
+mod settings;
+mod shard;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
+mod stats;
+mod telemetry;
+mod temporary;
+mod tiered;
+mod transfer;
+mod trash;
+mod ttl;
+mod verify;
+mod versions;
 
 use std::{
     borrow::Cow,
-    fs::OpenOptions,
-    path::Path,
-    str::FromStr,
-    time::{Duration, SystemTime},
+    collections::VecDeque,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
 };
 
 use async_trait::async_trait;
+use deletion::Deletion;
+use error::{decode_json, FileError, IoResultExt};
+use futures::TryStreamExt;
+use hooks::Hooks;
+use index::SessionIndex;
+use lock::SessionLocks;
+use report::RecentError;
+use settings::RuntimeSettings;
+use telemetry::Operation;
+use temporary::TempFolder;
 use time::OffsetDateTime;
-use tokio::fs::remove_file;
+use tokio::sync::broadcast;
 use tower_sessions_core::{
     session::{Id, Record},
     session_store, ExpiredDeletion, SessionStore,
 };
+use trash::Discard;
+use ttl::TtlPolicies;
+
+pub use builder::{BuildError, FileSessionStorageBuilder};
+pub use cache::SessionCache;
+#[cfg(feature = "chaos")]
+pub use chaos::ChaosStore;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use conditional::{ConditionalLoad, ModificationToken};
+pub use config::{ConfigError, FileSessionStorageConfig, SweepPartitionConfig};
+pub use deletion::{Anonymize, DeletionStrategy, HardDelete};
+pub use error::StoreErrorKind;
+pub use events::SessionEvent;
+pub use expired_archive::ArchiveExpired;
+pub use fs::{FileLock, FileMetadata, FileSystemKind, Fs, RealFs};
+pub use hooks::{HookFailure, SessionStoreHooks};
+pub use index::IndexKey;
+pub use inspect::{SessionCounts, SessionMetadata, SessionPage};
+pub use memory_fs::MemoryFs;
+pub use migrating::MigratingStore;
+pub use mirror::{MirrorReport, ReadFallback};
+pub use namespace::StoreFactory;
+pub use naming::{
+    Base64IdEncoding, FileNaming, FlatNaming, HexIdEncoding, IdEncoding, ShardedNaming,
+};
+#[cfg(feature = "opendal")]
+pub use opendal_fs::OpendalFs;
+#[cfg(feature = "redb")]
+pub use redb_store::RedbSessionStorage;
+pub use scan::{GarbageFile, GarbageKind, ScanReport};
+pub use shard::ShardedStore;
+pub use snapshot::{RestoreConflict, RestoreOptions, RestoreSummary};
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::SqliteFileSessionStorage;
+pub use stats::{DurationBuckets, SizeHistogram, StoreStats, SweepReport};
+pub use telemetry::SlowOperation;
+pub use tiered::TieredStore;
+pub use transfer::{
+    ImportConflict, ImportOptions, ImportSummary, MigrateOptions, MigrationProgress,
+};
+pub use ttl::TtlPolicy;
+pub use versions::MAX_KEEP_VERSIONS;
+
+/// Suffix of the temporary files sessions are written to before replacing the real file.
+pub(crate) const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+/// How many times `create` will generate a new ID when the file for the current one already exists.
+const MAX_CREATE_ATTEMPTS: usize = 8;
 
 /// A Session storage that stores each session, JSON encoded, on the local disk.
 ///
-/// In production, you may want to put this behind a [`MemoryStore`](https://docs.rs/tower-sessions/latest/tower_sessions/struct.MemoryStore.html)
-/// for performance.
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// In production, you may want to put this behind a cache for performance,
+/// [`with_memory_cache`](Self::with_memory_cache) sets one up, or use [`TieredStore`] with any
+/// other store.
+///
+/// Concurrent writes to the same session from within one process are serialized, clones of the
+/// store share the same locks.
+#[derive(Debug, Clone)]
 pub struct FileSessionStorage {
-    folder_name: Cow<'static, Path>,
-    minimum_expiry_date: Duration,
+    folders: Arc<RwLock<Folders>>,
+    settings: Arc<RwLock<RuntimeSettings>>,
+    locks: SessionLocks,
+    events: broadcast::Sender<SessionEvent>,
+    sweep_partition: SweepPartition,
+    cross_process_locking: bool,
+    indexes: Arc<Vec<SessionIndex>>,
+    minimum_free_space: u64,
+    last_sweep: Arc<Mutex<Option<SweepReport>>>,
+    cached_stats: Arc<Mutex<Option<StoreStats>>>,
+    recent_errors: Arc<Mutex<VecDeque<RecentError>>>,
+    hooks: Hooks,
+    before_expire_failure: HookFailure,
+    track_last_access: bool,
+    temp_folder: Option<Arc<TempFolder>>,
+    clock: Arc<dyn Clock>,
+    fs: Arc<dyn Fs>,
+    naming: Arc<dyn FileNaming>,
+    reject_expired_writes: bool,
+    degrade_on_disk_full: bool,
+    /// When the disk was found to be full, `None` unless degraded.
+    disk_full_since: Arc<Mutex<Option<SystemTime>>>,
+    create_rate_limit: Option<Arc<Mutex<rate_limit::CreateRateLimit>>>,
+    circuit_breaker: Option<Arc<Mutex<circuit::CircuitBreaker>>>,
+    quarantine_corrupt: bool,
+    /// How long deleted sessions are kept in the trash, `None` to delete them right away.
+    soft_delete: Option<Duration>,
+    /// How many older versions of each session are kept.
+    keep_versions: u32,
+    /// What happens to expired sessions.
+    deletion: Deletion,
+    ttl_policies: TtlPolicies,
+    mirror: Option<Arc<Path>>,
+    read_fallback: ReadFallback,
+}
+
+/// Where sessions are stored, shared between clones so the store can be moved while in use.
+#[derive(Debug)]
+struct Folders {
+    primary: Arc<Path>,
+    /// Folder we are moving sessions away from, sessions found here are moved to `primary` before
+    /// they are used.
+    legacy: Option<Arc<Path>>,
+    /// Whether `primary` was seen to exist, so it disappearing can be told apart from it not having
+    /// been created yet.
+    primary_seen: bool,
+    /// Whether [`relocate`](FileSessionStorage::relocate) is running.
+    relocating: bool,
+}
+
+/// Parse the name of a file in the sessions folder, `None` if it's not a session.
+pub(crate) fn session_id_from_file_name(file_name: &OsStr) -> Option<Id> {
+    file_name.to_str().and_then(naming::decode_id)
+}
+
+/// Whether `name` can be used as the name of a folder created by the store.
+pub(crate) fn is_valid_folder_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Which share of the sessions an instance checks during expiry sweeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SweepPartition {
+    index: u32,
+    count: u32,
+}
+
+impl SweepPartition {
+    fn contains(&self, session_id: &Id) -> bool {
+        (session_id.0 as u128 % self.count as u128) == self.index as u128
+    }
 }
 
 impl Default for FileSessionStorage {
@@ -61,121 +322,772 @@ impl FileSessionStorage {
     /// Create a new `FileSessionStore` with sessions placed in the given folder.
     pub fn new_in_folder(folder: impl Into<Cow<'static, Path>>) -> Self {
         FileSessionStorage {
-            folder_name: folder.into(),
-            minimum_expiry_date: Duration::from_secs(60),
+            folders: Arc::new(RwLock::new(Folders {
+                primary: Arc::from(folder.into().into_owned()),
+                legacy: None,
+                primary_seen: false,
+                relocating: false,
+            })),
+            settings: Arc::default(),
+            locks: SessionLocks::default(),
+            events: broadcast::Sender::new(events::EVENT_CHANNEL_CAPACITY),
+            sweep_partition: SweepPartition { index: 0, count: 1 },
+            cross_process_locking: false,
+            indexes: Arc::default(),
+            minimum_free_space: 0,
+            last_sweep: Arc::default(),
+            cached_stats: Arc::default(),
+            recent_errors: Arc::default(),
+            hooks: Hooks::default(),
+            before_expire_failure: HookFailure::Skip,
+            track_last_access: false,
+            temp_folder: None,
+            clock: Arc::new(SystemClock),
+            fs: Arc::new(RealFs),
+            naming: Arc::new(FlatNaming::default()),
+            reject_expired_writes: false,
+            degrade_on_disk_full: false,
+            disk_full_since: Arc::default(),
+            create_rate_limit: None,
+            circuit_breaker: None,
+            quarantine_corrupt: false,
+            soft_delete: None,
+            keep_versions: 0,
+            deletion: Deletion::default(),
+            ttl_policies: TtlPolicies::default(),
+            mirror: None,
+            read_fallback: ReadFallback::FailFast,
         }
     }
 
+    /// Keep sessions on `fs` instead of the local disk, for example a [`MemoryFs`] in tests.
+    ///
+    /// `snapshot_to` and `restore_from` of the `archive` feature and the free space check of
+    /// [`health_check`](Self::health_check) still use the local disk.
+    pub fn set_fs(mut self, fs: impl Fs) -> Self {
+        self.fs = Arc::new(fs);
+        self
+    }
+
+    /// Configure a new store with a [`FileSessionStorageBuilder`].
+    pub fn builder() -> FileSessionStorageBuilder {
+        FileSessionStorageBuilder::default()
+    }
+
     /// We need to open every session file to determine if it expired.
     /// The minimum expiry time sets the minimum age of a file before attempting to open it.
-    pub fn set_minimum_expiry_date(mut self, duration: Duration) -> Self {
-        self.minimum_expiry_date = duration;
+    pub fn set_minimum_expiry_date(self, duration: Duration) -> Self {
+        self.with_settings(|settings| settings.minimum_expiry_date = duration)
+    }
+
+    /// When several instances share the same folder, only sweep the sessions assigned to this
+    /// instance, so the work of deleting expired sessions is divided between them.
+    ///
+    /// Sessions are assigned by their ID, `index` must be unique for every instance and be less
+    /// than `count`, the total number of instances.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not less than `count`.
+    pub fn set_sweep_partition(mut self, index: u32, count: u32) -> Self {
+        assert!(index < count, "partition index must be less than the count");
+        self.sweep_partition = SweepPartition { index, count };
         self
     }
-}
 
-#[async_trait]
-impl SessionStore for FileSessionStorage {
-    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
-        tokio::fs::create_dir_all(&self.folder_name)
-            .await
-            .map_err(|_| session_store::Error::Backend("Failed to create folder".to_string()))?;
+    /// Take a lock on session files while reading or writing them, so multiple processes sharing
+    /// the same folder never see a partially written session.
+    ///
+    /// Uses `flock` on Unix and `LockFileEx` on Windows, on a `.<name>.lock` file next to each
+    /// session file since saving replaces the session file. The lock file is deleted with the
+    /// session. Note that these locks are advisory on Unix and may not work on some network file
+    /// systems.
+    pub fn set_cross_process_locking(mut self, enabled: bool) -> Self {
+        self.cross_process_locking = enabled;
+        self
+    }
 
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(self.folder_name.join(record.id.to_string()))
-            .map_err(|_| session_store::Error::Backend("Failed to open file".to_string()))?;
-        serde_json::to_writer(file, &record)
-            .map_err(|_| session_store::Error::Backend("Failed to serialize/decode".to_string()))?;
+    /// Make `create` and `save` fail for records whose expiry date already passed, instead of
+    /// writing a file the next sweep deletes.
+    ///
+    /// Usually means the session expiry is misconfigured, off by default.
+    pub fn set_reject_expired_writes(mut self, enabled: bool) -> Self {
+        self.reject_expired_writes = enabled;
+        self
+    }
 
+    /// Fail if `record` already expired and [`set_reject_expired_writes`] is enabled.
+    ///
+    /// [`set_reject_expired_writes`]: Self::set_reject_expired_writes
+    fn check_not_expired(&self, record: &Record) -> session_store::Result<()> {
+        if self.reject_expired_writes && record.expiry_date < self.now_utc() {
+            return Err(session_store::Error::Backend(format!(
+                "{}{} that expired at {}",
+                error::EXPIRED_WRITE_PREFIX,
+                record.id,
+                record.expiry_date
+            )));
+        }
         Ok(())
     }
 
-    async fn save(&self, record: &Record) -> session_store::Result<()> {
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(self.folder_name.join(record.id.to_string()))
-            .map_err(|_| session_store::Error::Backend("Failed to open file".to_string()))?;
-        serde_json::to_writer(file, &record)
-            .map_err(|_| session_store::Error::Backend("Failed to serialize/decode".to_string()))?;
+    /// The folder sessions are currently stored in.
+    pub(crate) fn folder(&self) -> Arc<Path> {
+        self.folders.read().unwrap().primary.clone()
+    }
+
+    /// The path of the file the given session is stored in.
+    pub(crate) fn session_path(&self, session_id: &Id) -> PathBuf {
+        self.folder().join(self.naming.path(session_id))
+    }
+
+    /// Create the folder the file of a session goes in.
+    ///
+    /// If the sessions folder was removed while the store was in use, for example by a cleanup of
+    /// temporary files, it is created again and [`SessionEvent::FolderRecreated`] is sent.
+    pub(crate) async fn create_session_folder(&self, path: &Path) -> session_store::Result<()> {
+        let folder = path.parent().unwrap_or(path);
+        if self.fs.metadata(folder).await.is_ok_and(|m| m.is_dir()) {
+            self.mark_folder_seen(folder);
+            return Ok(());
+        }
+        let (primary, seen) = {
+            let folders = self.folders.read().unwrap();
+            (folders.primary.clone(), folders.primary_seen)
+        };
+        let recreated = seen
+            && folder.starts_with(&primary)
+            && !self.fs.try_exists(&primary).await.unwrap_or(true);
+        self.fs
+            .create_dir_all(folder)
+            .await
+            .context("create folder", folder)?;
+        self.mark_folder_seen(folder);
+        if recreated {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                folder = %primary.display(),
+                "sessions folder disappeared and was created again"
+            );
+            self.emit(SessionEvent::FolderRecreated);
+        }
         Ok(())
     }
 
-    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
-        let path = self.folder_name.join(session_id.to_string());
-        if !path.is_file() {
-            return Ok(None);
+    fn mark_folder_seen(&self, folder: &Path) {
+        if self.folders.read().unwrap().primary_seen {
+            return;
+        }
+        let mut folders = self.folders.write().unwrap();
+        if folder.starts_with(&folders.primary) {
+            folders.primary_seen = true;
         }
-        let file = OpenOptions::new()
-            .read(true)
-            .open(path)
-            .map_err(|_| session_store::Error::Backend("Failed to open file".to_string()))?;
-        let out = serde_json::from_reader(file)
-            .map_err(|_| session_store::Error::Backend("Failed to serialize/decode".to_string()))?;
+    }
+
+    /// Delete every session in the store, for example to log out all users.
+    ///
+    /// Other files in the folder are left alone. Sessions created while this runs may survive.
+    /// Returns the number of sessions deleted.
+    pub async fn clear_all(&self) -> session_store::Result<usize> {
+        let mut deleted = 0;
+        for session_id in self.list_session_ids().await? {
+            if self.delete_session(&session_id).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Read a session from disk, `None` if it doesn't exist.
+    pub(crate) async fn read_record(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<Record>> {
+        let path = self.session_path(session_id);
+        let mut record = self.read_record_file(&path).await;
+        if matches!(record, Ok(None)) && self.adopt_legacy_name(session_id).await? {
+            record = self.read_record_file(&path).await;
+        }
+        match self.fallback_mirror() {
+            Some(mirror) if !matches!(record, Ok(Some(_))) => {
+                self.read_from_mirror(mirror, session_id, record).await
+            }
+            _ => record,
+        }
+    }
+
+    /// Read a session file, `None` if it doesn't exist.
+    pub(crate) async fn read_record_file(
+        &self,
+        path: &Path,
+    ) -> session_store::Result<Option<Record>> {
+        let _lock = match self.lock_file(path, false).await {
+            Ok(lock) => lock,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileError::new("lock", path, e).into()),
+        };
+        let contents = match self.fs.read(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileError::new("read", path, e).into()),
+        };
+        telemetry::record_bytes(contents.len() as u64);
+        let out = decode_json(&contents)?;
 
         Ok(out)
     }
 
-    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
-        let res = remove_file(self.folder_name.join(session_id.to_string())).await;
-        match res {
+    /// Lock a file against other processes if cross process locking is enabled.
+    pub(crate) async fn lock_file(
+        &self,
+        path: &Path,
+        exclusive: bool,
+    ) -> std::io::Result<Option<FileLock>> {
+        if !self.cross_process_locking {
+            return Ok(None);
+        }
+        self.fs.lock(path, exclusive).await.map(Some)
+    }
+
+    /// Replace the file of a session by writing to a temporary file and renaming it, so readers
+    /// and hard links to the old file never see a partially written session.
+    ///
+    /// Without `created_at` the creation time of the current file is kept, or set to now if there
+    /// is none.
+    pub(crate) async fn replace_file(
+        &self,
+        session_id: &Id,
+        record: &Record,
+        created_at: Option<OffsetDateTime>,
+    ) -> session_store::Result<()> {
+        let created_at = match created_at {
+            Some(created_at) => created_at,
+            None => self
+                .created_at_of(&self.session_path(session_id))
+                .await?
+                .unwrap_or_else(|| self.now_utc()),
+        };
+        let contents =
+            created::encode_record(&self.apply_ttl_policy(record, created_at), created_at)?;
+        // Starts with a dot so it is never mistaken for a session
+        let temp_path = self.folder().join(format!(
+            ".{}.{}{TEMP_FILE_SUFFIX}",
+            naming::encode_id(session_id),
+            naming::encode_id(&Id::default())
+        ));
+        let result = async {
+            self.fs
+                .create_new(&temp_path, &contents)
+                .await
+                .context("write", &temp_path)?;
+            telemetry::record_bytes(contents.len() as u64);
+            let path = self.session_path(session_id);
+            self.fs
+                .rename(&temp_path, &path)
+                .await
+                .context("replace", &path)
+        }
+        .await;
+        if result.is_err() {
+            let _ = self.fs.remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    /// Remove the file for a session, or move it to the trash or archive, returns `false` if it
+    /// didn't exist.
+    pub(crate) async fn remove_session(
+        &self,
+        session_id: &Id,
+        discard: Discard,
+    ) -> session_store::Result<bool> {
+        self.remove_session_if(session_id, discard, false).await
+    }
+
+    /// Like [`remove_session`](Self::remove_session), but only if the session is still expired
+    /// once it is locked, returns `false` if it was saved with a later expiry date in between.
+    pub(crate) async fn remove_expired_session(
+        &self,
+        session_id: &Id,
+        discard: Discard,
+    ) -> session_store::Result<bool> {
+        self.remove_session_if(session_id, discard, true).await
+    }
+
+    async fn remove_session_if(
+        &self,
+        session_id: &Id,
+        discard: Discard,
+        only_expired: bool,
+    ) -> session_store::Result<bool> {
+        let _guard = self.locks.lock(*session_id).await;
+        self.migrate_session(session_id).await?;
+        let old = if self.indexes.is_empty() && !only_expired {
+            None
+        } else {
+            self.read_record(session_id).await?
+        };
+        if only_expired && !old.as_ref().is_some_and(|old| self.is_expired(old)) {
+            return Ok(false);
+        }
+        let path = self.session_path(session_id);
+        let mut result = self.discard_file(session_id, &path, discard).await;
+        if result
+            .as_ref()
+            .is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+            && self.adopt_legacy_name(session_id).await?
+        {
+            result = self.discard_file(session_id, &path, discard).await;
+        }
+        match result {
             Ok(_) => {}
-            Err(e) => {
-                if e.kind() != std::io::ErrorKind::NotFound {
-                    return Err(session_store::Error::Backend(
-                        "Failed to Delete".to_string(),
-                    ));
-                }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(FileError::new("delete", &path, e).into()),
+        }
+        if self.cross_process_locking {
+            // Left behind by `RealFs`, other file systems have none
+            if let Ok(lock_path) = fs::lock_file_path(&path) {
+                let _ = self.fs.remove_file(&lock_path).await;
             }
         }
-        Ok(())
+        if let Some(old) = old {
+            self.remove_from_indexes(&old, None).await?;
+        }
+        if discard == Discard::Trash {
+            self.trash_blobs(session_id).await?;
+        } else {
+            self.remove_blobs(session_id).await?;
+        }
+        self.remove_versions(session_id).await?;
+        self.remove_last_access(session_id).await?;
+        self.unmirror_deleted(session_id).await?;
+        Ok(true)
+    }
+
+    /// Whether the expiry date of a session passed.
+    pub(crate) fn is_expired(&self, record: &Record) -> bool {
+        self.now_utc() > record.expiry_date
+    }
+
+    /// Delete or archive an expired session, returns `false` if it didn't exist anymore or a
+    /// failed hook kept it.
+    pub(crate) async fn expire_session(&self, record: &Record) -> session_store::Result<bool> {
+        let mut aborted = false;
+        for error in self.hooks.before_expire(record).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(session_id = %record.id, %error, "before_expire hook failed");
+            self.record_error("before expire", &error);
+            aborted |= self.before_expire_failure == HookFailure::Abort;
+        }
+        if aborted {
+            return Ok(false);
+        }
+        let deleted = self.deletion.0.remove(self, record).await?;
+        if deleted {
+            self.emit(SessionEvent::Expired(record.id));
+            self.hooks.expired(record).await;
+        }
+        Ok(deleted)
+    }
+
+    /// Check one session file during an expiry sweep, returns `None` if it was too recently
+    /// modified to be checked or disappeared, otherwise whether it expired and was deleted.
+    async fn sweep_session(
+        &self,
+        session_id: &Id,
+        dir_entry: &fs::DirEntry,
+        minimum_expiry_date: Duration,
+    ) -> session_store::Result<Option<bool>> {
+        let path = dir_entry.path();
+        let metadata = match dir_entry.metadata().await {
+            Ok(metadata) => metadata,
+            // Deleted since we listed the folder
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileError::new("get metadata of", &path, e).into()),
+        };
+        let modified_date = metadata.modified().context("get modified date of", &path)?;
+        // Modified in the future when the clock went back, which is certainly recent
+        let age = self.now().duration_since(modified_date).unwrap_or_default();
+        if age < minimum_expiry_date {
+            return Ok(None);
+        }
+
+        let Some(session) = self.read_record(session_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.is_expired(&session) && self.expire_session(&session).await?,
+        ))
+    }
+
+    /// Delete a session on request of the user, returns `false` if it didn't exist.
+    pub(crate) async fn delete_session(&self, session_id: &Id) -> session_store::Result<bool> {
+        let discard = match self.soft_delete {
+            Some(_) => Discard::Trash,
+            None => Discard::Delete,
+        };
+        let deleted = self.remove_session(session_id, discard).await?;
+        if deleted {
+            self.emit(SessionEvent::Deleted(*session_id));
+            self.hooks.deleted(session_id).await;
+        }
+        Ok(deleted)
+    }
+
+    /// Create the file of a new session. On an ID collision this tries again with a fresh ID if
+    /// `new_id` is set, otherwise nothing is written. Returns the ID the session was created with.
+    async fn create_session(
+        &self,
+        record: &mut Record,
+        new_id: bool,
+    ) -> session_store::Result<Option<Id>> {
+        let session_id = record.id;
+        let create = async {
+            self.check_create_rate()?;
+            self.check_not_expired(record)?;
+
+            let created_at = self.now_utc();
+            let mut attempts = 0;
+            let contents = loop {
+                // So a session that is still in the legacy folder counts as a collision
+                self.ensure_migrated(&record.id).await?;
+                // The ID is part of the contents, so they change with it
+                let contents =
+                    created::encode_record(&self.apply_ttl_policy(record, created_at), created_at)?;
+                let path = self.session_path(&record.id);
+                self.create_session_folder(&path).await?;
+                // Readers never see the file partially written, so it doesn't need to be locked
+                match self.fs.create_new_atomic(&path, &contents).await {
+                    Ok(()) => break contents,
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && !new_id => {
+                        return Ok(None)
+                    }
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::AlreadyExists
+                            && attempts < MAX_CREATE_ATTEMPTS =>
+                    {
+                        // Session ID collision, try again with a fresh ID
+                        attempts += 1;
+                        record.id = Id::default();
+                    }
+                    Err(e) => return Err(FileError::new("create", &path, e).into()),
+                }
+            };
+            telemetry::record_bytes(contents.len() as u64);
+            if let Err(e) = self.add_to_indexes(record).await {
+                // A session missing from its indexes would be skipped by lookups, like deleting
+                // all sessions of a user
+                let path = self.session_path(&record.id);
+                let _ = self.remove_from_indexes(record, None).await;
+                let _ = self.fs.remove_file(&path).await;
+                return Err(e);
+            }
+            self.emit(SessionEvent::Created(record.id));
+            self.hooks.created(record).await;
+
+            Ok(Some(record.id))
+        };
+        self.observe_as(
+            Operation::Create,
+            Some(session_id),
+            self.guard_disk_full(create),
+            |session_id| *session_id,
+        )
+        .await
+    }
+
+    /// Create a session keeping its ID, returns `false` without writing anything if the ID is
+    /// already in use.
+    pub(crate) async fn create_with_id(&self, record: &Record) -> session_store::Result<bool> {
+        let mut record = record.clone();
+        Ok(self.create_session(&mut record, false).await?.is_some())
     }
 }
 
 #[async_trait]
-impl ExpiredDeletion for FileSessionStorage {
-    async fn delete_expired(&self) -> session_store::Result<()> {
-        let mut folders = tokio::fs::read_dir(&self.folder_name)
-            .await
-            .map_err(|_| session_store::Error::Backend("Failed to list folder".to_string()))?;
-        while let Some(dir_entry) = folders
-            .next_entry()
-            .await
-            .map_err(|_| session_store::Error::Backend("Failed to load next file".to_string()))?
-        {
-            let Some(session_id) = dir_entry
-                .file_name()
-                .to_str()
-                .and_then(|k| Id::from_str(k).ok())
-            else {
-                continue;
+impl SessionStore for FileSessionStorage {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.create_session(record, true).await.map(|_| ())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let save = async {
+            self.check_not_expired(record)?;
+            let _guard = self.locks.lock(record.id).await;
+            self.migrate_session(&record.id).await?;
+            let old = if self.indexes.is_empty() {
+                None
+            } else {
+                self.read_record(&record.id).await?
             };
-            let metadata = dir_entry
-                .metadata()
-                .await
-                .map_err(|_| session_store::Error::Backend("Failed to get metadata".to_string()))?;
-            let modified_date = metadata.modified().map_err(|_| {
-                session_store::Error::Backend("Failed to get modified date".to_string())
-            })?;
-            let age = SystemTime::now()
-                .duration_since(modified_date)
-                .map_err(|_| {
-                    session_store::Error::Backend("Failed to subtract dates".to_string())
-                })?;
-            if age < self.minimum_expiry_date {
-                continue;
+            let path = self.session_path(&record.id);
+            // The file may have been cleaned up since the session was loaded, then it is created
+            // again under the same ID
+            if !self.fs.try_exists(&path).await.context("open", &path)?
+                && !self.adopt_legacy_name(&record.id).await?
+            {
+                self.create_session_folder(&path).await?;
+            }
+            // Keeps other processes from replacing the file at the same time
+            let lock = self.lock_file(&path, true).await.context("lock", &path)?;
+            self.keep_version(&record.id).await?;
+            self.replace_file(&record.id, record, None).await?;
+            drop(lock);
+            if let Some(old) = old {
+                self.remove_from_indexes(&old, Some(record)).await?;
             }
+            self.add_to_indexes(record).await?;
+            self.emit(SessionEvent::Saved(record.id));
+            self.hooks.saved(record).await;
+            Ok(())
+        };
+        self.observe(Operation::Save, Some(record.id), self.guard_disk_full(save))
+            .await
+    }
 
-            let Some(session) = self.load(&session_id).await? else {
-                continue;
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        self.observe(Operation::Load, Some(*session_id), async {
+            self.ensure_migrated(session_id).await?;
+            let record = match self.read_record(session_id).await? {
+                // So stale cookies can't bring back sessions the sweep hasn't reached yet
+                Some(record) if self.is_expired(&record) => {
+                    // Still expired if it couldn't be removed, the sweep tries again
+                    if let Err(e) = self.expire_session(&record).await {
+                        self.record_error("expire", &e);
+                    }
+                    None
+                }
+                record => record,
             };
-            if OffsetDateTime::now_utc() > session.expiry_date {
-                self.delete(&session_id).await?;
+            if let Some(record) = &record {
+                self.touch_last_access(session_id).await?;
+                self.hooks.loaded(record).await;
+            }
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.observe(Operation::Delete, Some(*session_id), async {
+            self.delete_session(session_id).await?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for FileSessionStorage {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        self.observe(Operation::DeleteExpired, None, async {
+            let started = Instant::now();
+            let mut on_disk = 0;
+            let mut checked = 0;
+            let mut deleted = 0;
+            let mut failed = 0;
+            let minimum_expiry_date = self.minimum_expiry_date();
+            let mut entries = std::pin::pin!(self.session_entries());
+            while let Some((session_id, dir_entry)) = entries.try_next().await? {
+                on_disk += 1;
+                if !self.sweep_partition.contains(&session_id) {
+                    continue;
+                }
+                match self
+                    .sweep_session(&session_id, &dir_entry, minimum_expiry_date)
+                    .await
+                {
+                    Ok(None) => {}
+                    Ok(Some(expired)) => {
+                        checked += 1;
+                        deleted += usize::from(expired);
+                        // Read each time, so updating the limit applies to a running sweep
+                        if let Some(per_second) = self.sweep_rate_limit().filter(|_| expired) {
+                            crate::rt::sleep(Duration::from_secs(1) / per_second).await;
+                        }
+                    }
+                    // One bad file shouldn't keep the other sessions from expiring
+                    Err(e) => {
+                        failed += 1;
+                        self.record_error(
+                            Operation::DeleteExpired.name(),
+                            &session_store::Error::Backend(format!(
+                                "Skipped {}: {e}",
+                                dir_entry.path().display()
+                            )),
+                        );
+                    }
+                }
+            }
+
+            if let Some(retention) = self.soft_delete {
+                if let Err(e) = self.purge_trash(retention).await {
+                    failed += 1;
+                    self.record_error(Operation::DeleteExpired.name(), &e);
+                }
+            }
+            if let Err(e) = self.deletion.0.after_sweep(self).await {
+                failed += 1;
+                self.record_error(Operation::DeleteExpired.name(), &e);
             }
+
+            telemetry::record_sessions_on_disk(on_disk - deleted);
+            self.record_sweep(SweepReport {
+                finished_at: self.now(),
+                duration: started.elapsed(),
+                checked,
+                deleted,
+                failed,
+            });
+            Ok(())
+        })
+        .await
+    }
+
+    /// Like the default, but sleeps on the runtime picked by the crate features instead of
+    /// needing a tokio timer, and keeps going when a sweep fails, for example while the disk is
+    /// unavailable. Failures are kept for [`dump_report`](FileSessionStorage::dump_report).
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    /// A store on a [`MemoryFs`], with a clock that only moves when the test advances it.
+    pub(crate) fn store() -> (FileSessionStorage, MemoryFs, ManualClock) {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let fs = MemoryFs::new().with_clock(clock.clone());
+        let store = FileSessionStorage::new_in_folder(Path::new("/sessions"))
+            .set_fs(fs.clone())
+            .set_clock(clock.clone());
+        (store, fs, clock)
+    }
+
+    /// A new record expiring `ttl` after the time on `clock`.
+    pub(crate) fn record(clock: &ManualClock, ttl: Duration) -> Record {
+        Record {
+            id: Id::default(),
+            data: Default::default(),
+            expiry_date: OffsetDateTime::from(clock.now() + ttl),
         }
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn create_picks_new_id_on_collision() {
+        let (store, _, clock) = store();
+        let mut first = record(&clock, Duration::from_secs(3600));
+        store.create(&mut first).await.unwrap();
+
+        let mut second = record(&clock, Duration::from_secs(3600));
+        second.id = first.id;
+        second.data.insert("second".to_string(), true.into());
+        store.create(&mut second).await.unwrap();
+
+        assert_ne!(second.id, first.id);
+        assert_eq!(store.load(&first.id).await.unwrap(), Some(first));
+        assert_eq!(store.load(&second.id).await.unwrap(), Some(second));
+    }
+
+    #[tokio::test]
+    async fn create_removes_file_when_indexing_fails() {
+        let (store, fs, clock) = store();
+        let store = store.set_user_index("user");
+        let mut session = record(&clock, Duration::from_secs(3600));
+        session.data.insert("user".to_string(), 42.into());
+        // The index folder can't be created where a file is in the way
+        fs.create_dir_all(Path::new("/sessions/.index"))
+            .await
+            .unwrap();
+        fs.write(Path::new("/sessions/.index/user"), b"")
+            .await
+            .unwrap();
+
+        assert!(store.create(&mut session).await.is_err());
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn load_drops_expired_session() {
+        let (store, _, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn sweep_deletes_only_expired_sessions() {
+        let (store, _, clock) = store();
+        let mut short = record(&clock, Duration::from_secs(60));
+        let mut long = record(&clock, Duration::from_secs(3600));
+        store.create(&mut short).await.unwrap();
+        store.create(&mut long).await.unwrap();
+
+        clock.advance(Duration::from_secs(120));
+        store.delete_expired().await.unwrap();
+
+        assert_eq!(store.list_session_ids().await.unwrap(), vec![long.id]);
+    }
+
+    #[tokio::test]
+    async fn sweep_keeps_recently_written_files() {
+        let (store, _, clock) = store();
+        let store = store.set_minimum_expiry_date(Duration::from_secs(600));
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+
+        clock.advance(Duration::from_secs(120));
+        store.delete_expired().await.unwrap();
+        assert_eq!(store.count_sessions().await.unwrap(), 1);
+
+        clock.advance(Duration::from_secs(600));
+        store.delete_expired().await.unwrap();
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn partitions_divide_the_sweep() {
+        let (store, _, clock) = store();
+        let partitions = [
+            store.clone().set_sweep_partition(0, 2),
+            store.clone().set_sweep_partition(1, 2),
+        ];
+        for _ in 0..16 {
+            store
+                .create(&mut record(&clock, Duration::from_secs(60)))
+                .await
+                .unwrap();
+        }
+        clock.advance(Duration::from_secs(120));
+
+        partitions[0].delete_expired().await.unwrap();
+        let left = store.list_session_ids().await.unwrap();
+        assert!(left
+            .iter()
+            .all(|session_id| partitions[1].sweep_partition.contains(session_id)));
+        partitions[1].delete_expired().await.unwrap();
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn create_with_id_reports_collision() {
+        let (store, _, clock) = store();
+        let mut first = record(&clock, Duration::from_secs(3600));
+        store.create(&mut first).await.unwrap();
+
+        let mut second = record(&clock, Duration::from_secs(3600));
+        second.id = first.id;
+        assert!(!store.create_with_id(&second).await.unwrap());
+        assert_eq!(store.count_sessions().await.unwrap(), 1);
     }
 }
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tower_sessions_core::session::Id;
+
+/// Keyed locks used to serialize writes to the same session within one process.
+///
+/// Entries are removed again once nobody holds or waits for them, so the map only
+/// grows with the number of sessions being written concurrently.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SessionLocks {
+    locks: Arc<DashMap<Id, Arc<Mutex<()>>>>,
+}
+
+impl SessionLocks {
+    /// Wait until no other task in this process is writing the given session.
+    pub(crate) async fn lock(&self, id: Id) -> SessionLockGuard {
+        let mutex = self.locks.entry(id).or_default().clone();
+        SessionLockGuard {
+            guard: Some(mutex.lock_owned().await),
+            id,
+            locks: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct SessionLockGuard {
+    guard: Option<OwnedMutexGuard<()>>,
+    id: Id,
+    locks: SessionLocks,
+}
+
+impl Drop for SessionLockGuard {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        // Holding the shard lock while checking the count means no one can clone the
+        // mutex out of the map in between.
+        self.locks
+            .locks
+            .remove_if(&self.id, |_, mutex| Arc::strong_count(mutex) == 1);
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+
+use crate::{Clock, FileMetadata, Fs, SystemClock};
+
+/// A file system that only exists in memory, for tests.
+///
+/// Pass it to [`FileSessionStorage::set_fs`](crate::FileSessionStorage::set_fs) and keep a clone
+/// to simulate failures with [`fail_reads`](Self::fail_reads) and
+/// [`fail_writes`](Self::fail_writes). Clones share the same files. Modified dates come from the
+/// clock passed to [`with_clock`](Self::with_clock), so they line up with a
+/// [`ManualClock`](crate::ManualClock) given to the store.
+#[derive(Clone)]
+pub struct MemoryFs {
+    state: Arc<Mutex<MemoryState>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    nodes: BTreeMap<PathBuf, Node>,
+    read_error: Option<io::ErrorKind>,
+    write_error: Option<io::ErrorKind>,
+}
+
+enum Node {
+    File {
+        contents: Arc<[u8]>,
+        modified: SystemTime,
+        created: SystemTime,
+    },
+    Dir {
+        modified: SystemTime,
+    },
+}
+
+impl fmt::Debug for MemoryFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MemoryFs")
+            .field("entries", &state.nodes.len())
+            .field("read_error", &state.read_error)
+            .field("write_error", &state.write_error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        MemoryFs::new()
+    }
+}
+
+impl MemoryFs {
+    /// Create an empty file system.
+    pub fn new() -> Self {
+        MemoryFs {
+            state: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Take modified dates from `clock` instead of the system time.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Make every read, listing and metadata call fail with `kind`, until called with `None`.
+    pub fn fail_reads(&self, kind: Option<io::ErrorKind>) {
+        self.state.lock().unwrap().read_error = kind;
+    }
+
+    /// Make every call that changes the file system fail with `kind`, until called with `None`.
+    ///
+    /// Use [`io::ErrorKind::StorageFull`] to simulate a full disk or
+    /// [`io::ErrorKind::PermissionDenied`] for a read only folder.
+    pub fn fail_writes(&self, kind: Option<io::ErrorKind>) {
+        self.state.lock().unwrap().write_error = kind;
+    }
+
+    /// Run `f` on the state if reads aren't failing.
+    fn read<T>(&self, f: impl FnOnce(&MemoryState) -> io::Result<T>) -> io::Result<T> {
+        let state = self.state.lock().unwrap();
+        match state.read_error {
+            Some(kind) => Err(kind.into()),
+            None => f(&state),
+        }
+    }
+
+    /// Run `f` on the state if writes aren't failing.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut MemoryState, SystemTime) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.write_error {
+            Some(kind) => Err(kind.into()),
+            None => f(&mut state, self.clock.now()),
+        }
+    }
+}
+
+impl MemoryState {
+    /// Fail unless the folder `path` would be created in exists.
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && parent != Path::new("/") => {
+                match self.nodes.get(parent) {
+                    Some(Node::Dir { .. }) => Ok(()),
+                    Some(Node::File { .. }) => Err(io::ErrorKind::NotADirectory.into()),
+                    None => Err(io::ErrorKind::NotFound.into()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn children<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a PathBuf> + 'a {
+        self.nodes
+            .range(path.to_path_buf()..)
+            .map(|(child, _)| child)
+            .skip_while(move |child| *child == path)
+            .take_while(move |child| child.starts_with(path))
+    }
+
+    fn put_file(&mut self, path: &Path, contents: &[u8], now: SystemTime) -> io::Result<()> {
+        self.check_parent(path)?;
+        let created = match self.nodes.get(path) {
+            Some(Node::Dir { .. }) => return Err(io::ErrorKind::IsADirectory.into()),
+            Some(Node::File { created, .. }) => *created,
+            None => now,
+        };
+        self.nodes.insert(
+            path.to_path_buf(),
+            Node::File {
+                contents: contents.into(),
+                modified: now,
+                created,
+            },
+        );
+        Ok(())
+    }
+
+    fn get_file(&self, path: &Path) -> io::Result<&Arc<[u8]>> {
+        match self.nodes.get(path) {
+            Some(Node::File { contents, .. }) => Ok(contents),
+            Some(Node::Dir { .. }) => Err(io::ErrorKind::IsADirectory.into()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Fs for MemoryFs {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.write(|state, now| {
+            let mut ancestors: Vec<_> = path
+                .ancestors()
+                .filter(|p| !p.as_os_str().is_empty() && *p != Path::new("/"))
+                .collect();
+            ancestors.reverse();
+            for ancestor in ancestors {
+                match state.nodes.get(ancestor) {
+                    Some(Node::Dir { .. }) => {}
+                    Some(Node::File { .. }) => return Err(io::ErrorKind::NotADirectory.into()),
+                    None => {
+                        state
+                            .nodes
+                            .insert(ancestor.to_path_buf(), Node::Dir { modified: now });
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    async fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.write(|state, now| {
+            if state.nodes.contains_key(path) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            state.put_file(path, contents, now)
+        })
+    }
+
+    async fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.write(|state, now| state.put_file(path, contents, now))
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read(|state| state.get_file(path).map(|contents| contents.to_vec()))
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.write(|state, _| {
+            let node = state.nodes.get(from).ok_or(io::ErrorKind::NotFound)?;
+            state.check_parent(to)?;
+            match (node, state.nodes.get(to)) {
+                (Node::File { .. }, Some(Node::Dir { .. })) => {
+                    return Err(io::ErrorKind::IsADirectory.into())
+                }
+                (Node::Dir { .. }, Some(Node::File { .. })) => {
+                    return Err(io::ErrorKind::NotADirectory.into())
+                }
+                (Node::Dir { .. }, Some(Node::Dir { .. }))
+                    if state.children(to).next().is_some() =>
+                {
+                    return Err(io::ErrorKind::DirectoryNotEmpty.into())
+                }
+                _ => {}
+            }
+            if to.starts_with(from) && to != from {
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+            let moved: Vec<PathBuf> = std::iter::once(from.to_path_buf())
+                .chain(state.children(from).cloned())
+                .collect();
+            for old in moved {
+                let node = state.nodes.remove(&old).unwrap();
+                let new = to.join(old.strip_prefix(from).unwrap());
+                state.nodes.insert(new, node);
+            }
+            Ok(())
+        })
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.write(|state, _| {
+            state.get_file(path)?;
+            state.nodes.remove(path);
+            Ok(())
+        })
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.write(|state, _| {
+            match state.nodes.get(path) {
+                Some(Node::Dir { .. }) => {}
+                Some(Node::File { .. }) => return Err(io::ErrorKind::NotADirectory.into()),
+                None => return Err(io::ErrorKind::NotFound.into()),
+            }
+            if state.children(path).next().is_some() {
+                return Err(io::ErrorKind::DirectoryNotEmpty.into());
+            }
+            state.nodes.remove(path);
+            Ok(())
+        })
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.write(|state, _| {
+            match state.nodes.get(path) {
+                Some(Node::Dir { .. }) => {}
+                Some(Node::File { .. }) => return Err(io::ErrorKind::NotADirectory.into()),
+                None => return Err(io::ErrorKind::NotFound.into()),
+            }
+            let removed: Vec<PathBuf> = state.children(path).cloned().collect();
+            for child in removed {
+                state.nodes.remove(&child);
+            }
+            state.nodes.remove(path);
+            Ok(())
+        })
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BoxStream<'static, io::Result<OsString>>> {
+        let names: Vec<io::Result<OsString>> = self.read(|state| {
+            match state.nodes.get(path) {
+                Some(Node::Dir { .. }) => {}
+                Some(Node::File { .. }) => return Err(io::ErrorKind::NotADirectory.into()),
+                None => return Err(io::ErrorKind::NotFound.into()),
+            }
+            Ok(state
+                .children(path)
+                .filter(|child| child.parent() == Some(path))
+                .filter_map(|child| child.file_name().map(|name| Ok(name.to_owned())))
+                .collect())
+        })?;
+        Ok(futures::stream::iter(names).boxed())
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        self.read(|state| match state.nodes.get(path) {
+            Some(Node::File {
+                contents,
+                modified,
+                created,
+            }) => Ok(FileMetadata::file(contents.len() as u64, *modified).with_created(*created)),
+            Some(Node::Dir { modified }) => Ok(FileMetadata::dir(*modified)),
+            None => Err(io::ErrorKind::NotFound.into()),
+        })
+    }
+
+    async fn set_modified(&self, path: &Path, new_modified: SystemTime) -> io::Result<()> {
+        self.write(|state, _| match state.nodes.get_mut(path) {
+            Some(Node::File { modified, .. } | Node::Dir { modified }) => {
+                *modified = new_modified;
+                Ok(())
+            }
+            None => Err(io::ErrorKind::NotFound.into()),
+        })
+    }
+
+    async fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        // Files are only ever replaced as a whole, so a copy behaves the same as a link
+        self.write(|state, now| {
+            let contents = state.get_file(from)?.clone();
+            if state.nodes.contains_key(to) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            state.check_parent(to)?;
+            let modified = match state.nodes.get(from) {
+                Some(Node::File { modified, .. }) => *modified,
+                _ => now,
+            };
+            state.nodes.insert(
+                to.to_path_buf(),
+                Node::File {
+                    contents,
+                    modified,
+                    created: now,
+                },
+            );
+            Ok(())
+        })
+    }
+}
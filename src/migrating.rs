@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+/// Moves sessions from an `Old` store to a `New` one as they are used, so switching folders,
+/// layouts or backends doesn't log anybody out.
+///
+/// Loads try `New` first and fall back to `Old`. A session found only in `Old` is saved to `New`
+/// and then deleted from `Old`. Everything else only goes to `New`, except deletes which go to
+/// both. `New::save` has to create sessions that don't exist yet, as the [`SessionStore`]
+/// contract requires. Sessions that are never used again stay in `Old` until they expire there,
+/// so keep its expiry task running, or use
+/// [`migrate_to`](crate::FileSessionStorage::migrate_to) to move the rest at once.
+#[derive(Debug, Clone)]
+pub struct MigratingStore<Old, New> {
+    old: Old,
+    new: New,
+}
+
+impl<Old: SessionStore, New: SessionStore> MigratingStore<Old, New> {
+    /// Move sessions from `old` to `new`.
+    pub fn new(old: Old, new: New) -> Self {
+        MigratingStore { old, new }
+    }
+
+    /// The store sessions are moved away from.
+    pub fn old(&self) -> &Old {
+        &self.old
+    }
+
+    /// The store sessions are moved to.
+    pub fn new_store(&self) -> &New {
+        &self.new
+    }
+}
+
+#[async_trait]
+impl<Old: SessionStore, New: SessionStore> SessionStore for MigratingStore<Old, New> {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.new.create(record).await
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.new.save(record).await
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        if let Some(record) = self.new.load(session_id).await? {
+            return Ok(Some(record));
+        }
+        let Some(record) = self.old.load(session_id).await? else {
+            return Ok(None);
+        };
+        self.new.save(&record).await?;
+        self.old.delete(session_id).await?;
+        Ok(Some(record))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.new.delete(session_id).await?;
+        self.old.delete(session_id).await
+    }
+}
+
+#[async_trait]
+impl<Old, New> ExpiredDeletion for MigratingStore<Old, New>
+where
+    Old: SessionStore + ExpiredDeletion,
+    New: SessionStore + ExpiredDeletion,
+{
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        // Sweep both even if one fails
+        let new = self.new.delete_expired().await;
+        let old = self.old.delete_expired().await;
+        new.and(old)
+    }
+
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        tests::{record, store},
+        FileSessionStorage,
+    };
+
+    fn migrating() -> (
+        MigratingStore<FileSessionStorage, FileSessionStorage>,
+        crate::ManualClock,
+    ) {
+        let (old, _, clock) = store();
+        let (new, _, _) = store();
+        (
+            MigratingStore::new(old, new.set_clock(clock.clone())),
+            clock,
+        )
+    }
+
+    #[tokio::test]
+    async fn loading_moves_sessions_to_the_new_store() {
+        let (migrating, clock) = migrating();
+        let mut session = record(&clock, Duration::from_secs(60));
+        migrating.old().create(&mut session).await.unwrap();
+
+        assert_eq!(
+            migrating.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert_eq!(migrating.old().load(&session.id).await.unwrap(), None);
+        assert_eq!(
+            migrating.new_store().load(&session.id).await.unwrap(),
+            Some(session)
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_go_to_the_new_store_and_deletes_to_both() {
+        let (migrating, clock) = migrating();
+        let mut session = record(&clock, Duration::from_secs(60));
+        migrating.old().create(&mut session).await.unwrap();
+        let mut saved = session.clone();
+        saved.data.insert("saved".to_string(), true.into());
+        migrating.save(&saved).await.unwrap();
+
+        assert_eq!(migrating.load(&session.id).await.unwrap(), Some(saved));
+        assert_eq!(
+            migrating.old().load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        migrating.delete(&session.id).await.unwrap();
+        assert_eq!(migrating.load(&session.id).await.unwrap(), None);
+        assert_eq!(migrating.old().load(&session.id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::TryStreamExt;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{
+    created::encode_record,
+    error::{FileError, IoResultExt},
+    naming::encode_id,
+    FileSessionStorage, SessionEvent, TEMP_FILE_SUFFIX,
+};
+
+/// What [`FileSessionStorage::reconcile_mirror`] changed in the mirror folder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MirrorReport {
+    /// Sessions that were missing or outdated in the mirror and were copied to it.
+    pub copied: usize,
+    /// Sessions that only existed in the mirror and were removed from it, because they expired
+    /// or pruning was asked for.
+    pub removed: usize,
+    /// Sessions that only existed in the mirror and were restored from it, with
+    /// [`ReadFallback::FailOver`].
+    pub restored: usize,
+    /// Sessions that only existed in the mirror and were left there.
+    pub kept: usize,
+}
+
+/// What to do when a session file is missing or unreadable, set with
+/// [`FileSessionStorage::set_read_fallback`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadFallback {
+    /// Report the error, or a missing session, right away.
+    #[default]
+    FailFast,
+    /// Try the copy in the mirror folder and restore the session file from it.
+    FailOver,
+}
+
+impl FileSessionStorage {
+    /// Keep a copy of every session in `mirror_folder`, for example on another disk, so a single
+    /// disk failure doesn't log out every user.
+    ///
+    /// Sessions are copied in the background by [`continuously_mirror`](Self::continuously_mirror),
+    /// which needs to be spawned like the expiry task. Only session files are mirrored, not the
+    /// indexes or blobs.
+    pub fn set_mirror_folder(mut self, mirror_folder: impl Into<PathBuf>) -> Self {
+        self.mirror = Some(Arc::from(mirror_folder.into()));
+        self
+    }
+
+    /// Read sessions from the mirror folder when their file in the sessions folder is missing or
+    /// unreadable, see [`ReadFallback`]. Does nothing without a mirror folder.
+    ///
+    /// With [`ReadFallback::FailOver`] deleted sessions are also removed from the mirror right
+    /// away instead of in the background, so they can't come back from there.
+    pub fn set_read_fallback(mut self, read_fallback: ReadFallback) -> Self {
+        self.read_fallback = read_fallback;
+        self
+    }
+
+    /// The mirror folder, if reads should fall back to it.
+    pub(crate) fn fallback_mirror(&self) -> Option<&Arc<Path>> {
+        match self.read_fallback {
+            ReadFallback::FailFast => None,
+            ReadFallback::FailOver => self.mirror.as_ref(),
+        }
+    }
+
+    /// Read a session from the mirror after reading it from the sessions folder gave `primary`,
+    /// restoring the file in the sessions folder if the mirror has it.
+    pub(crate) async fn read_from_mirror(
+        &self,
+        mirror: &Path,
+        session_id: &Id,
+        primary: session_store::Result<Option<Record>>,
+    ) -> session_store::Result<Option<Record>> {
+        let mirror_path = mirror.join(self.naming.path(session_id));
+        let Ok(Some(record)) = self.read_record_file(&mirror_path).await else {
+            return primary;
+        };
+        let path = self.session_path(session_id);
+        let repaired = async {
+            self.create_session_folder(&path).await?;
+            let created_at = self.created_at_of(&mirror_path).await?;
+            self.replace_file(session_id, &record, created_at).await
+        }
+        .await;
+        if let Err(e) = repaired {
+            self.record_error("repair", &e);
+        }
+        Ok(Some(record))
+    }
+
+    /// Remove a deleted session from the mirror right away if reads fall back to it.
+    pub(crate) async fn unmirror_deleted(&self, session_id: &Id) -> session_store::Result<()> {
+        match self.fallback_mirror() {
+            Some(mirror) => self.unmirror_session(mirror, session_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// The mirror folder set with [`set_mirror_folder`](Self::set_mirror_folder).
+    pub(crate) fn mirror_folder(&self) -> session_store::Result<Arc<Path>> {
+        self.mirror
+            .clone()
+            .ok_or_else(|| session_store::Error::Backend("No mirror folder set".to_string()))
+    }
+
+    /// Copy the current file of a session to the mirror, or remove it from the mirror if the
+    /// session no longer exists.
+    ///
+    /// Files that can't be read or decoded are left alone, so a damaged file never replaces a
+    /// good copy.
+    async fn mirror_session(&self, mirror: &Path, session_id: &Id) -> session_store::Result<()> {
+        let path = self.session_path(session_id);
+        let Some(record) = self.read_record_file(&path).await? else {
+            return self.unmirror_session(mirror, session_id).await;
+        };
+        let created_at = self.created_at_of(&path).await?;
+        let contents = encode_record(&record, created_at.unwrap_or_else(|| self.now_utc()))?;
+
+        let mirror_path = mirror.join(self.naming.path(session_id));
+        self.create_session_folder(&mirror_path).await?;
+        let temp_path = mirror.join(format!(
+            ".{}.{}{TEMP_FILE_SUFFIX}",
+            encode_id(session_id),
+            encode_id(&Id::default())
+        ));
+        let result = async {
+            self.fs
+                .create_new(&temp_path, &contents)
+                .await
+                .context("write", &temp_path)?;
+            self.fs
+                .rename(&temp_path, &mirror_path)
+                .await
+                .context("replace", &mirror_path)
+        }
+        .await;
+        if result.is_err() {
+            let _ = self.fs.remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    /// Remove the copy of a session from the mirror.
+    async fn unmirror_session(&self, mirror: &Path, session_id: &Id) -> session_store::Result<()> {
+        let mirror_path = mirror.join(self.naming.path(session_id));
+        match self.fs.remove_file(&mirror_path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::new("delete", &mirror_path, e).into()),
+        }
+    }
+
+    /// Bring the mirror folder up to date with the sessions folder, copying sessions that are
+    /// missing or older in the mirror.
+    ///
+    /// Sessions only in the mirror may be all that is left of them after a disk failure. With
+    /// [`ReadFallback::FailOver`] they are restored to the sessions folder, since deleting a
+    /// session removes its copy right away. Otherwise they are kept in the mirror, or removed
+    /// from it with `prune` if they are known to have been deleted, since their copies are only
+    /// removed in the background. Expired copies are always removed.
+    ///
+    /// Run this once after adding a mirror or replacing a failed disk, [`continuously_mirror`]
+    /// also runs it without pruning whenever it may have missed changes.
+    ///
+    /// [`continuously_mirror`]: Self::continuously_mirror
+    pub async fn reconcile_mirror(&self, prune: bool) -> session_store::Result<MirrorReport> {
+        let mirror = self.mirror_folder()?;
+        self.fs
+            .create_dir_all(&mirror)
+            .await
+            .context("create folder", &mirror)?;
+        let mut report = MirrorReport::default();
+
+        let mut sessions = HashSet::new();
+        let mut entries = std::pin::pin!(self.session_entries());
+        while let Some((session_id, dir_entry)) = entries.try_next().await? {
+            sessions.insert(session_id);
+            let modified = match dir_entry.metadata().await {
+                Ok(metadata) => metadata.modified().ok(),
+                // Deleted since we listed the folder
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(FileError::new("get metadata of", dir_entry.path(), e).into()),
+            };
+            if dir_entry.path() != self.session_path(&session_id) {
+                // Named by an older version, renaming keeps the modified date
+                self.adopt_legacy_name(&session_id).await?;
+            }
+            let mirror_path = mirror.join(self.naming.path(&session_id));
+            let mirror_modified = match self.fs.metadata(&mirror_path).await {
+                Ok(metadata) => metadata.modified().ok(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(FileError::new("get metadata of", &mirror_path, e).into()),
+            };
+            let up_to_date = matches!(
+                (modified, mirror_modified),
+                (Some(modified), Some(mirror_modified)) if mirror_modified >= modified
+            );
+            if !up_to_date {
+                self.mirror_session(&mirror, &session_id).await?;
+                report.copied += 1;
+            }
+        }
+
+        let mut entries = std::pin::pin!(self.entries_in(mirror.clone()));
+        while let Some((session_id, dir_entry)) = entries.try_next().await? {
+            let mirror_path = mirror.join(self.naming.path(&session_id));
+            let path = dir_entry.path();
+            if path != mirror_path {
+                // A copy named by an older version
+                if sessions.contains(&session_id) {
+                    // Copied again under its current name above
+                    let _ = self.fs.remove_file(&path).await;
+                    continue;
+                }
+                self.create_session_folder(&mirror_path).await?;
+                self.fs
+                    .rename(&path, &mirror_path)
+                    .await
+                    .context("rename", &path)?;
+            }
+            if sessions.contains(&session_id) || self.exists(&session_id).await? {
+                continue;
+            }
+            let expired = match self.read_record_file(&mirror_path).await {
+                Ok(Some(record)) => self.is_expired(&record),
+                // Unreadable copies are left alone, like a damaged session file
+                Ok(None) | Err(_) => false,
+            };
+            if expired {
+                self.unmirror_session(&mirror, &session_id).await?;
+                report.removed += 1;
+            } else if self.fallback_mirror().is_some() {
+                // Deletions already removed the copy, so the session file was lost
+                self.read_from_mirror(&mirror, &session_id, Ok(None))
+                    .await?;
+                report.restored += 1;
+            } else if prune {
+                self.unmirror_session(&mirror, &session_id).await?;
+                report.removed += 1;
+            } else {
+                report.kept += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Copy every change made through this store or its clones to the mirror folder, never
+    /// returns unless no mirror folder is set.
+    ///
+    /// Starts with [`reconcile_mirror`](Self::reconcile_mirror), without pruning. If copying a session fails, for
+    /// example because the mirror disk is unavailable, the error is kept for
+    /// [`dump_report`](Self::dump_report) and the mirror is reconciled again after the next
+    /// change. Changes made by other processes are only picked up by reconciling.
+    pub async fn continuously_mirror(self) -> session_store::Result<()> {
+        let mirror = self.mirror_folder()?;
+        let mut events = self.subscribe();
+        let mut in_sync = false;
+        loop {
+            if !in_sync {
+                match self.reconcile_mirror(false).await {
+                    Ok(_) => in_sync = true,
+                    Err(e) => self.record_error("mirror", &e),
+                }
+            }
+            let result = match events.recv().await {
+                Ok(SessionEvent::Created(session_id) | SessionEvent::Saved(session_id)) => {
+                    self.mirror_session(&mirror, &session_id).await
+                }
+                Ok(SessionEvent::Deleted(session_id) | SessionEvent::Expired(session_id)) => {
+                    self.unmirror_session(&mirror, &session_id).await
+                }
+                Ok(SessionEvent::FolderRecreated) => {
+                    // Reconciling restores the lost sessions with fail over, and keeps their
+                    // copies without it
+                    in_sync = false;
+                    Ok(())
+                }
+                Ok(
+                    SessionEvent::DiskFull
+                    | SessionEvent::CircuitOpened
+                    | SessionEvent::CircuitClosed
+                    | SessionEvent::Corrupted(_),
+                ) => Ok(()),
+                Err(RecvError::Lagged(_)) => {
+                    in_sync = false;
+                    Ok(())
+                }
+                // The store holds a sender itself, so this can't happen
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            if let Err(e) = result {
+                self.record_error("mirror", &e);
+                in_sync = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::{
+        tests::{record, store},
+        Fs,
+    };
+
+    /// A store mirrored to `/mirror`, with a session that only the mirror has left.
+    async fn lost_session(
+        read_fallback: ReadFallback,
+    ) -> (FileSessionStorage, crate::ManualClock, Record) {
+        let (store, fs, clock) = store();
+        let store = store
+            .set_mirror_folder("/mirror")
+            .set_read_fallback(read_fallback);
+        let mut session = record(&clock, Duration::from_secs(3600));
+        store.create(&mut session).await.unwrap();
+        assert_eq!(store.reconcile_mirror(false).await.unwrap().copied, 1);
+        // The disk failed and was replaced
+        fs.remove_dir_all(Path::new("/sessions")).await.unwrap();
+        (store, clock, session)
+    }
+
+    #[tokio::test]
+    async fn reconcile_keeps_copies_by_default() {
+        let (store, _, session) = lost_session(ReadFallback::FailFast).await;
+
+        let report = store.reconcile_mirror(false).await.unwrap();
+        assert_eq!((report.kept, report.removed), (1, 0));
+        let report = store.reconcile_mirror(false).await.unwrap();
+        assert_eq!(report.kept, 1);
+        assert!(store
+            .fs
+            .try_exists(&Path::new("/mirror").join(encode_id(&session.id)))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn reconcile_restores_copies_with_fail_over() {
+        let (store, _, session) = lost_session(ReadFallback::FailOver).await;
+
+        let report = store.reconcile_mirror(true).await.unwrap();
+        assert_eq!((report.restored, report.removed), (1, 0));
+        let fail_fast = store.clone().set_read_fallback(ReadFallback::FailFast);
+        assert_eq!(fail_fast.load(&session.id).await.unwrap(), Some(session));
+    }
+
+    #[tokio::test]
+    async fn reconcile_prunes_only_when_asked() {
+        let (store, _, session) = lost_session(ReadFallback::FailFast).await;
+
+        let report = store.reconcile_mirror(true).await.unwrap();
+        assert_eq!((report.kept, report.removed), (0, 1));
+        let fail_over = store.clone().set_read_fallback(ReadFallback::FailOver);
+        assert_eq!(fail_over.load(&session.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reconcile_removes_expired_copies() {
+        let (store, clock, _) = lost_session(ReadFallback::FailOver).await;
+        clock.advance(Duration::from_secs(7200));
+
+        let report = store.reconcile_mirror(false).await.unwrap();
+        assert_eq!((report.removed, report.restored, report.kept), (1, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn fail_over_reads_and_repairs_from_the_mirror() {
+        let (store, _, session) = lost_session(ReadFallback::FailOver).await;
+
+        assert_eq!(
+            store.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        let fail_fast = store.clone().set_read_fallback(ReadFallback::FailFast);
+        assert_eq!(fail_fast.load(&session.id).await.unwrap(), Some(session));
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use dashmap::DashMap;
+use tokio::sync::broadcast;
+use tower_sessions_core::{session_store, ExpiredDeletion};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    events, is_valid_folder_name,
+    lock::SessionLocks,
+    naming::decode_id,
+    FileSessionStorage, Folders,
+};
+
+fn is_valid_namespace(namespace: &str) -> bool {
+    is_valid_folder_name(namespace) && decode_id(namespace).is_none()
+}
+
+impl FileSessionStorage {
+    /// A store with the same configuration, but with sessions placed in the subfolder `namespace`,
+    /// for example to keep the sessions of different tenants separate.
+    ///
+    /// All operations of the returned store, including expiry sweeps, only touch that subfolder.
+    /// Operations on this store ignore the subfolder, so they don't affect the namespaced sessions
+    /// either.
+    ///
+    /// # Panics
+    ///
+    /// If `namespace` is empty, contains characters other than ASCII letters, digits, `-` and `_`,
+    /// or could be mistaken for a session ID.
+    pub fn with_namespace(&self, namespace: &str) -> Self {
+        assert!(
+            is_valid_namespace(namespace),
+            "invalid namespace {namespace:?}"
+        );
+        let folders = self.folders.read().unwrap();
+        FileSessionStorage {
+            folders: Arc::new(RwLock::new(Folders {
+                primary: Arc::from(folders.primary.join(namespace)),
+                legacy: folders
+                    .legacy
+                    .as_ref()
+                    .map(|legacy| Arc::from(legacy.join(namespace))),
+                primary_seen: false,
+                relocating: false,
+            })),
+            mirror: self
+                .mirror
+                .as_ref()
+                .map(|mirror| Arc::from(mirror.join(namespace))),
+            locks: SessionLocks::default(),
+            events: broadcast::Sender::new(events::EVENT_CHANNEL_CAPACITY),
+            last_sweep: Arc::new(Mutex::new(None)),
+            cached_stats: Arc::new(Mutex::new(None)),
+            recent_errors: Arc::default(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Creates and caches a namespaced store per tenant, all sharing the configuration of one base
+/// store.
+///
+/// Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct StoreFactory {
+    base: FileSessionStorage,
+    stores: Arc<DashMap<String, FileSessionStorage>>,
+}
+
+impl StoreFactory {
+    /// Create a factory for stores in subfolders of `base`, see
+    /// [`FileSessionStorage::with_namespace`].
+    pub fn new(base: FileSessionStorage) -> Self {
+        StoreFactory {
+            base,
+            stores: Arc::default(),
+        }
+    }
+
+    /// The store for `tenant`, created the first time it is requested.
+    ///
+    /// Returns `None` if `tenant` can't be used as a namespace, see
+    /// [`FileSessionStorage::with_namespace`].
+    pub fn get(&self, tenant: &str) -> Option<FileSessionStorage> {
+        if let Some(store) = self.stores.get(tenant) {
+            return Some(store.clone());
+        }
+        if !is_valid_namespace(tenant) {
+            return None;
+        }
+        Some(
+            self.stores
+                .entry(tenant.to_string())
+                .or_insert_with(|| self.base.with_namespace(tenant))
+                .clone(),
+        )
+    }
+
+    /// Delete expired sessions of every tenant, including tenants that weren't requested from this
+    /// factory yet but have a folder.
+    pub async fn delete_expired(&self) -> session_store::Result<()> {
+        let folder = self.base.folder();
+        let mut folders = match crate::fs::read_dir(&self.base.fs, &folder).await {
+            Ok(folders) => folders,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(FileError::new("list folder", &folder, e).into()),
+        };
+        // Sweep every tenant even if one fails, returning the first failure
+        let mut result = Ok(());
+        while let Some(dir_entry) = folders.next_entry().await.context("list folder", &folder)? {
+            let is_dir = dir_entry.metadata().await.is_ok_and(|m| m.is_dir());
+            let Some(tenant) = dir_entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if let Some(store) = is_dir.then(|| self.get(&tenant)).flatten() {
+                result = result.and(store.delete_expired().await);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn namespaces_keep_sessions_apart() {
+        let (store, _, clock) = store();
+        let tenant = store.with_namespace("tenant-a");
+        let mut session = record(&clock, Duration::from_secs(60));
+        tenant.create(&mut session).await.unwrap();
+
+        assert!(tenant.folder().ends_with("tenant-a"));
+        assert_eq!(
+            tenant.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+        assert_eq!(
+            store
+                .with_namespace("tenant-b")
+                .load(&session.id)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(store.list_session_ids().await.unwrap().is_empty());
+
+        clock.advance(Duration::from_secs(120));
+        store.delete_expired().await.unwrap();
+        assert!(tenant
+            .fs
+            .try_exists(&tenant.session_path(&session.id))
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid namespace")]
+    fn namespaces_must_be_folder_names() {
+        store().0.with_namespace("../escape");
+    }
+
+    #[test]
+    fn factory_reuses_stores_per_tenant() {
+        let (store, _, _) = store();
+        let factory = StoreFactory::new(store);
+        let first = factory.get("tenant-a").unwrap();
+        // Namespaced stores each get their own event channel
+        let again = factory.get("tenant-a").unwrap();
+        assert!(first.events.same_channel(&again.events));
+        let other = factory.get("tenant-b").unwrap();
+        assert!(!first.events.same_channel(&other.events));
+        assert!(factory.get("not/a/tenant").is_none());
+    }
+
+    #[tokio::test]
+    async fn factory_sweeps_tenants_it_never_handed_out() {
+        let (store, _, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(60));
+        let tenant = StoreFactory::new(store.clone()).get("tenant-a").unwrap();
+        tenant.create(&mut session).await.unwrap();
+
+        clock.advance(Duration::from_secs(120));
+        StoreFactory::new(store).delete_expired().await.unwrap();
+        assert!(!tenant
+            .fs
+            .try_exists(&tenant.session_path(&session.id))
+            .await
+            .unwrap());
+    }
+}
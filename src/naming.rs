@@ -0,0 +1,304 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use tower_sessions_core::session::Id;
+
+use crate::{BuildError, FileSessionStorage};
+
+/// Length of an [`Id`] encoded as a file name.
+const ID_LENGTH: usize = 32;
+
+/// The name of the files of a session, its ID in lowercase hex.
+///
+/// The base64 form of IDs can differ only in case, which clashes on case insensitive file systems
+/// like the defaults on Windows and macOS.
+pub(crate) fn encode_id(session_id: &Id) -> String {
+    format!("{:032x}", session_id.0 as u128)
+}
+
+/// Parse a name from [`encode_id`], or the base64 form of the ID that older versions used.
+pub(crate) fn decode_id(name: &str) -> Option<Id> {
+    if name.len() != ID_LENGTH {
+        return Id::from_str(name).ok();
+    }
+    // `from_str_radix` would also take a sign or uppercase digits
+    if !name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    u128::from_str_radix(name, 16).ok().map(|id| Id(id as i128))
+}
+
+/// Decides where in the sessions folder each session is stored.
+///
+/// Set it with [`FileSessionStorage::set_file_naming`] or
+/// [`FileSessionStorageBuilder::file_naming`](crate::FileSessionStorageBuilder::file_naming), the
+/// default is [`FlatNaming`]. Paths are relative to the sessions folder. Names starting with a
+/// dot and ending in `.blobs` are used by the store itself and must not be returned.
+pub trait FileNaming: fmt::Debug + Send + Sync + 'static {
+    /// The path of the file a session is stored in.
+    fn path(&self, session_id: &Id) -> PathBuf;
+
+    /// The session stored at `path`, `None` if the file isn't a session.
+    ///
+    /// Should also recognize the paths returned by [`legacy_path`](Self::legacy_path).
+    fn session_id(&self, path: &Path) -> Option<Id>;
+
+    /// Where an older version of the naming stored a session, if that differs from
+    /// [`path`](Self::path). Sessions found there are moved to their current path when they are
+    /// used.
+    fn legacy_path(&self, _session_id: &Id) -> Option<PathBuf> {
+        None
+    }
+
+    /// How many folders deep session files are, listings only look this deep.
+    fn depth(&self) -> usize {
+        0
+    }
+
+    /// Whether paths of two sessions can differ only in case, so sessions would overwrite each
+    /// other on a case insensitive file system. Then [`health_check`] fails on such a file system.
+    ///
+    /// [`health_check`]: FileSessionStorage::health_check
+    fn needs_case_sensitive_fs(&self) -> bool {
+        true
+    }
+}
+
+/// How the ID of a session is turned into the name of its file and back, for
+/// [`FlatNaming::with_id_encoding`] and [`ShardedNaming::with_id_encoding`].
+///
+/// Lets the store use a sessions folder shared with other tools that name files their own way,
+/// without writing a whole [`FileNaming`].
+pub trait IdEncoding: fmt::Debug + Send + Sync + 'static {
+    /// The name of the file for a session. Must be a valid file name and different for every ID,
+    /// also on case insensitive file systems if the folder may be on one.
+    fn encode(&self, session_id: &Id) -> String;
+
+    /// The session a file name belongs to, `None` if it isn't the name of a session.
+    ///
+    /// Should also recognize the names returned by [`encode_legacy`](Self::encode_legacy).
+    fn decode(&self, name: &str) -> Option<Id>;
+
+    /// The name an older version of the encoding used for a session, if that differs from
+    /// [`encode`](Self::encode). Sessions found under it are renamed when they are used.
+    fn encode_legacy(&self, _session_id: &Id) -> Option<String> {
+        None
+    }
+
+    /// Whether names of two sessions can differ only in case, see
+    /// [`FileNaming::needs_case_sensitive_fs`].
+    fn needs_case_sensitive_fs(&self) -> bool {
+        true
+    }
+}
+
+/// IDs in lowercase hex, the default.
+///
+/// Safe on every file system. Also decodes names from [`Base64IdEncoding`], which older versions
+/// used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HexIdEncoding;
+
+impl IdEncoding for HexIdEncoding {
+    fn encode(&self, session_id: &Id) -> String {
+        encode_id(session_id)
+    }
+
+    fn decode(&self, name: &str) -> Option<Id> {
+        decode_id(name)
+    }
+
+    fn encode_legacy(&self, session_id: &Id) -> Option<String> {
+        Some(session_id.to_string())
+    }
+
+    fn needs_case_sensitive_fs(&self) -> bool {
+        false
+    }
+}
+
+/// IDs in the base64 form used by tower-sessions, which is also the value of the session cookie.
+///
+/// Two IDs can differ only in case, so don't use it on case insensitive file systems like the
+/// defaults on Windows and macOS. [`health_check`](FileSessionStorage::health_check) fails there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Base64IdEncoding;
+
+impl IdEncoding for Base64IdEncoding {
+    fn encode(&self, session_id: &Id) -> String {
+        session_id.to_string()
+    }
+
+    fn decode(&self, name: &str) -> Option<Id> {
+        Id::from_str(name).ok()
+    }
+}
+
+/// Every session directly in the sessions folder, named after its ID in hex by default.
+///
+/// Files named after the base64 form of the ID by older versions are still found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatNaming<E = HexIdEncoding> {
+    extension: Option<Cow<'static, str>>,
+    encoding: E,
+}
+
+impl Default for FlatNaming {
+    fn default() -> Self {
+        FlatNaming {
+            extension: None,
+            encoding: HexIdEncoding,
+        }
+    }
+}
+
+impl FlatNaming {
+    /// Name files `<id>.<extension>` instead of just `<id>`, for example `json`.
+    pub fn with_extension(extension: impl Into<Cow<'static, str>>) -> Self {
+        FlatNaming {
+            extension: Some(extension.into()),
+            encoding: HexIdEncoding,
+        }
+    }
+}
+
+impl<E: IdEncoding> FlatNaming<E> {
+    /// Turn IDs into file names with `encoding` instead of hex.
+    pub fn with_id_encoding<F: IdEncoding>(self, encoding: F) -> FlatNaming<F> {
+        FlatNaming {
+            extension: self.extension,
+            encoding,
+        }
+    }
+
+    fn file_name(&self, name: String) -> PathBuf {
+        match &self.extension {
+            Some(extension) => PathBuf::from(format!("{name}.{extension}")),
+            None => PathBuf::from(name),
+        }
+    }
+}
+
+impl<E: IdEncoding> FileNaming for FlatNaming<E> {
+    fn path(&self, session_id: &Id) -> PathBuf {
+        self.file_name(self.encoding.encode(session_id))
+    }
+
+    fn session_id(&self, path: &Path) -> Option<Id> {
+        let name = path.to_str()?;
+        let name = match &self.extension {
+            Some(extension) => name.strip_suffix(extension.as_ref())?.strip_suffix('.')?,
+            None => name,
+        };
+        self.encoding.decode(name)
+    }
+
+    fn legacy_path(&self, session_id: &Id) -> Option<PathBuf> {
+        Some(self.file_name(self.encoding.encode_legacy(session_id)?))
+    }
+
+    fn needs_case_sensitive_fs(&self) -> bool {
+        self.encoding.needs_case_sensitive_fs()
+    }
+}
+
+/// Sessions spread over nested folders named after the start of their ID, so no single folder
+/// gets too large for the file system, for example `ab/cd/abcd...` with a depth of 2 and a width
+/// of 2.
+///
+/// IDs are random, so the sessions are spread evenly. Sessions stored by older versions, with the
+/// folders and file named after the base64 form of the ID, are still found. Don't combine this with a
+/// [`StoreFactory`](crate::StoreFactory) on the same folder, tenant folders would be mistaken for
+/// shards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardedNaming<E = HexIdEncoding> {
+    depth: usize,
+    width: usize,
+    encoding: E,
+}
+
+impl ShardedNaming {
+    /// Nest sessions `depth` folders deep, each named after the next `width` characters of the ID.
+    ///
+    /// Fails with [`BuildError::InvalidShardedNaming`] if `width` is 0 or the folders would need
+    /// more characters than an ID has.
+    pub fn new(depth: usize, width: usize) -> Result<Self, BuildError> {
+        if width == 0 || depth.saturating_mul(width) > ID_LENGTH {
+            return Err(BuildError::InvalidShardedNaming { depth, width });
+        }
+        Ok(ShardedNaming {
+            depth,
+            width,
+            encoding: HexIdEncoding,
+        })
+    }
+}
+
+impl<E: IdEncoding> ShardedNaming<E> {
+    /// Turn IDs into file and folder names with `encoding` instead of hex.
+    ///
+    /// Folders are padded with `_` for names shorter than the folders need.
+    pub fn with_id_encoding<F: IdEncoding>(self, encoding: F) -> ShardedNaming<F> {
+        ShardedNaming {
+            depth: self.depth,
+            width: self.width,
+            encoding,
+        }
+    }
+
+    fn sharded(&self, name: String) -> PathBuf {
+        let mut chars = name.chars().chain(std::iter::repeat('_'));
+        let mut path: PathBuf = (0..self.depth)
+            .map(|_| chars.by_ref().take(self.width).collect::<String>())
+            .collect();
+        path.push(name);
+        path
+    }
+}
+
+impl<E: IdEncoding> FileNaming for ShardedNaming<E> {
+    fn path(&self, session_id: &Id) -> PathBuf {
+        self.sharded(self.encoding.encode(session_id))
+    }
+
+    fn session_id(&self, path: &Path) -> Option<Id> {
+        let session_id = self.encoding.decode(path.file_name()?.to_str()?)?;
+        let matches = |expected: PathBuf| {
+            path.components()
+                .filter(|component| *component != Component::CurDir)
+                .eq(expected.components())
+        };
+        let matches =
+            matches(self.path(&session_id)) || self.legacy_path(&session_id).is_some_and(matches);
+        matches.then_some(session_id)
+    }
+
+    fn legacy_path(&self, session_id: &Id) -> Option<PathBuf> {
+        Some(self.sharded(self.encoding.encode_legacy(session_id)?))
+    }
+
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn needs_case_sensitive_fs(&self) -> bool {
+        self.encoding.needs_case_sensitive_fs()
+    }
+}
+
+impl FileSessionStorage {
+    /// Decide where each session is stored with `naming` instead of naming files after the ID in
+    /// the sessions folder.
+    ///
+    /// Existing sessions aren't moved and the legacy folder is expected to use the same naming, so
+    /// only change this for a new folder.
+    pub fn set_file_naming(mut self, naming: impl FileNaming) -> Self {
+        self.naming = Arc::new(naming);
+        self
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Component, Path},
+    time::SystemTime,
+};
+
+use ::opendal::{EntryMode, ErrorKind, Operator};
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+
+use crate::{FileMetadata, Fs};
+
+/// Keeps sessions in any storage [opendal](::opendal) supports, like S3, GCS or Azure Blob
+/// Storage, with the same layout of one object per session as on disk.
+///
+/// Pass it to [`FileSessionStorage::set_fs`](crate::FileSessionStorage::set_fs), the folder of the
+/// store becomes a prefix in the bucket. Enable the opendal feature of the service you need, like
+/// `opendal/services-s3`.
+///
+/// Object stores have no file locks, so cross process locking isn't supported. Creating a session
+/// only detects collisions atomically on services with conditional writes. Renames fall back to a
+/// copy and a delete where the service has no rename.
+#[derive(Debug, Clone)]
+pub struct OpendalFs {
+    operator: Operator,
+}
+
+impl OpendalFs {
+    /// Keep sessions in the storage of `operator`.
+    pub fn new(operator: Operator) -> Self {
+        OpendalFs { operator }
+    }
+}
+
+impl From<Operator> for OpendalFs {
+    fn from(operator: Operator) -> Self {
+        OpendalFs::new(operator)
+    }
+}
+
+/// The key of the object for a file.
+fn file_key(path: &Path) -> String {
+    let parts: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
+
+/// The key of a folder, which opendal marks with a trailing slash.
+fn dir_key(path: &Path) -> String {
+    let mut key = file_key(path);
+    key.push('/');
+    key
+}
+
+fn io_error(e: ::opendal::Error) -> io::Error {
+    let kind = match e.kind() {
+        ErrorKind::NotFound => io::ErrorKind::NotFound,
+        ErrorKind::PermissionDenied => io::ErrorKind::PermissionDenied,
+        ErrorKind::AlreadyExists | ErrorKind::ConditionNotMatch => io::ErrorKind::AlreadyExists,
+        ErrorKind::IsADirectory => io::ErrorKind::IsADirectory,
+        ErrorKind::NotADirectory => io::ErrorKind::NotADirectory,
+        ErrorKind::Unsupported => io::ErrorKind::Unsupported,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+#[async_trait]
+impl Fs for OpendalFs {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        // Folders are implied by the keys of their objects on most services
+        if !self.operator.info().capability().create_dir {
+            return Ok(());
+        }
+        self.operator
+            .create_dir(&dir_key(path))
+            .await
+            .map_err(io_error)
+    }
+
+    async fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let key = file_key(path);
+        if self.operator.info().capability().write_with_if_not_exists {
+            self.operator
+                .write_with(&key, contents.to_vec())
+                .if_not_exists(true)
+                .await
+                .map_err(io_error)?;
+            return Ok(());
+        }
+        if self.operator.exists(&key).await.map_err(io_error)? {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.write(path, contents).await
+    }
+
+    async fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.operator
+            .write(&file_key(path), contents.to_vec())
+            .await
+            .map_err(io_error)?;
+        Ok(())
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let contents = self
+            .operator
+            .read(&file_key(path))
+            .await
+            .map_err(io_error)?;
+        Ok(contents.to_vec())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (file_key(from), file_key(to));
+        if self.operator.info().capability().rename {
+            return self.operator.rename(&from, &to).await.map_err(io_error);
+        }
+        if self.operator.info().capability().copy {
+            self.operator.copy(&from, &to).await.map_err(io_error)?;
+        } else {
+            let contents = self.operator.read(&from).await.map_err(io_error)?;
+            self.operator.write(&to, contents).await.map_err(io_error)?;
+        }
+        self.operator.delete(&from).await.map_err(io_error)
+    }
+
+    async fn remove_file(&self, path: &Path) -> io::Result<()> {
+        // Deletes succeed for missing objects, but the store needs to know
+        let key = file_key(path);
+        self.operator.stat(&key).await.map_err(io_error)?;
+        self.operator.delete(&key).await.map_err(io_error)
+    }
+
+    async fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        if self.read_dir(path).await?.next().await.is_some() {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        self.operator.delete(&dir_key(path)).await.map_err(io_error)
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.operator
+            .delete_with(&dir_key(path))
+            .recursive(true)
+            .await
+            .map_err(io_error)
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BoxStream<'static, io::Result<OsString>>> {
+        let key = dir_key(path);
+        let lister = self.operator.lister(&key).await.map_err(io_error)?;
+        Ok(lister
+            .map_err(io_error)
+            .try_filter_map(move |entry| {
+                // Some services list the folder itself as well
+                let name = (entry.path() != key)
+                    .then(|| OsString::from(entry.name().trim_end_matches('/')));
+                async move { Ok(name) }
+            })
+            .boxed())
+    }
+
+    async fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = match self.operator.stat(&file_key(path)).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.operator.stat(&dir_key(path)).await.map_err(io_error)?
+            }
+            Err(e) => return Err(io_error(e)),
+        };
+        let modified = metadata
+            .last_modified()
+            .map(SystemTime::from)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        Ok(match metadata.mode() {
+            EntryMode::DIR => FileMetadata::dir(modified),
+            _ => FileMetadata::file(metadata.content_length(), modified),
+        })
+    }
+
+    /// Objects can't be changed in place, so this writes the object again and its modified date
+    /// becomes the time of the write instead of `modified`.
+    async fn set_modified(&self, path: &Path, _modified: SystemTime) -> io::Result<()> {
+        let contents = self.read(path).await?;
+        self.write(path, &contents).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        if !self.operator.info().capability().copy {
+            let contents = self.read(from).await?;
+            self.write(to, &contents).await?;
+            return Ok(contents.len() as u64);
+        }
+        let metadata = self
+            .operator
+            .copy(&file_key(from), &file_key(to))
+            .await
+            .map_err(io_error)?;
+        Ok(metadata.content_length())
+    }
+}
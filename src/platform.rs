@@ -0,0 +1,83 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::FileSessionStorage;
+
+/// An environment variable, `None` if it isn't set or empty.
+fn non_empty(name: &str) -> Option<OsString> {
+    std::env::var_os(name).filter(|value| !value.is_empty())
+}
+
+/// The folder the platform intends for application state, `None` if it can't be determined.
+fn platform_state_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        non_empty("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(any(target_os = "macos", target_os = "ios")) {
+        // On iOS the home folder is the sandbox of the app
+        non_empty("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else if cfg!(target_os = "android") {
+        android_files_dir()
+    } else {
+        non_empty("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+    }
+}
+
+/// The private files folder of the Android app this runs in, found from its package name.
+fn android_files_dir() -> Option<PathBuf> {
+    let cmdline = std::fs::read("/proc/self/cmdline").ok()?;
+    let process = cmdline.split(|&b| b == 0).next()?;
+    // Extra processes of an app are named `<package>:<process>`
+    let package = std::str::from_utf8(process).ok()?.split(':').next()?;
+    if package.is_empty() || package.contains('/') {
+        return None;
+    }
+    let data_dir = Path::new("/data/data").join(package);
+    data_dir.is_dir().then(|| data_dir.join("files"))
+}
+
+/// The folder for temporary files, from `TMPDIR` if it is set.
+///
+/// Unlike [`std::env::temp_dir`] this prefers `TMPDIR` on every platform, mobile platforms point
+/// it into the sandbox of the app.
+fn temp_dir() -> PathBuf {
+    non_empty("TMPDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+impl FileSessionStorage {
+    /// Create a store in the folder the platform intends for application state, instead of
+    /// `.sessions` in the current working directory.
+    ///
+    /// Sessions are placed in `<state dir>/<app_name>/sessions`, where the state dir is
+    /// `$XDG_STATE_HOME` (or `~/.local/state`) on Linux and other Unix systems,
+    /// `~/Library/Application Support` on macOS and iOS, the private files folder of the app on
+    /// Android and `%LOCALAPPDATA%` on Windows. On iOS and Android it falls back to `$TMPDIR`.
+    /// Returns `None` if the environment variable needed to find it isn't set.
+    pub fn in_platform_dir(app_name: &str) -> Option<Self> {
+        let state_dir = match platform_state_dir() {
+            Some(state_dir) => state_dir,
+            None if cfg!(any(target_os = "ios", target_os = "android")) => {
+                PathBuf::from(non_empty("TMPDIR")?)
+            }
+            None => return None,
+        };
+        let folder = state_dir.join(app_name).join("sessions");
+        Some(FileSessionStorage::new_in_folder(folder))
+    }
+
+    /// Create a store in `<temp dir>/<app_name>/sessions`, for sandboxes where the temporary
+    /// folder is the only place that can be written to.
+    ///
+    /// The temp dir is `$TMPDIR` if it is set, otherwise [`std::env::temp_dir`]. The system may
+    /// delete files there at any time, so sessions are lost more often than with
+    /// [`in_platform_dir`](Self::in_platform_dir).
+    pub fn in_temp_dir(app_name: &str) -> Self {
+        FileSessionStorage::new_in_folder(temp_dir().join(app_name).join("sessions"))
+    }
+}
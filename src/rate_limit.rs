@@ -0,0 +1,160 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use tower_sessions_core::session_store;
+
+use crate::FileSessionStorage;
+
+/// Start of the message of errors for creates over the limit, see
+/// [`FileSessionStorage::is_rate_limited_error`].
+const RATE_LIMITED_PREFIX: &str = "Rate limited: ";
+
+/// A token bucket limiting how often sessions are created, shared between clones of a store.
+#[derive(Debug)]
+pub(crate) struct CreateRateLimit {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    /// When `tokens` was last topped up, `None` before the first create.
+    updated: Option<SystemTime>,
+}
+
+impl CreateRateLimit {
+    /// A full bucket with the same limit, for a store that shouldn't share this one.
+    pub(crate) fn fresh(&self) -> Self {
+        CreateRateLimit {
+            tokens: self.burst,
+            updated: None,
+            ..*self
+        }
+    }
+
+    /// Take a token for one create, `false` if there are none left.
+    fn try_acquire(&mut self, now: SystemTime) -> bool {
+        if let Some(updated) = self.updated {
+            let elapsed = now.duration_since(updated).unwrap_or_default();
+            self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        }
+        self.updated = Some(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+impl FileSessionStorage {
+    /// Allow creating at most `per_second` sessions per second on average, with bursts of up to
+    /// `burst` sessions, so crawlers that don't keep cookies can't fill the disk faster than
+    /// expiry sweeps clean up.
+    ///
+    /// Creates over the limit fail without touching the disk, recognize them with
+    /// [`is_rate_limited_error`](Self::is_rate_limited_error) to respond with
+    /// `429 Too Many Requests`. Clones share the limit. There is no limit by default.
+    pub fn set_create_rate_limit(mut self, per_second: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        self.create_rate_limit = Some(Arc::new(Mutex::new(CreateRateLimit {
+            per_second: per_second.max(0.0),
+            burst,
+            tokens: burst,
+            updated: None,
+        })));
+        self
+    }
+
+    /// Whether `error` was returned because of the limit set with
+    /// [`set_create_rate_limit`](Self::set_create_rate_limit).
+    ///
+    /// `session_store::Error` can only hold a message, these errors are recognized by their
+    /// `Rate limited: ` prefix.
+    pub fn is_rate_limited_error(error: &session_store::Error) -> bool {
+        matches!(error, session_store::Error::Backend(message) if message.starts_with(RATE_LIMITED_PREFIX))
+    }
+
+    /// Fail if creating a session now would go over the limit.
+    pub(crate) fn check_create_rate(&self) -> session_store::Result<()> {
+        let Some(limit) = &self.create_rate_limit else {
+            return Ok(());
+        };
+        if limit.lock().unwrap().try_acquire(self.now()) {
+            Ok(())
+        } else {
+            Err(session_store::Error::Backend(format!(
+                "{RATE_LIMITED_PREFIX}too many sessions created"
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn creates_over_the_burst_are_refused() {
+        let (store, _, clock) = store();
+        let store = store.set_create_rate_limit(1.0, 2);
+        for _ in 0..2 {
+            store
+                .create(&mut record(&clock, Duration::from_secs(60)))
+                .await
+                .unwrap();
+        }
+        let error = store
+            .create(&mut record(&clock, Duration::from_secs(60)))
+            .await
+            .unwrap_err();
+        assert!(FileSessionStorage::is_rate_limited_error(&error));
+        assert_eq!(store.count_sessions().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn tokens_refill_over_time() {
+        let (store, _, clock) = store();
+        let store = store.set_create_rate_limit(0.5, 1);
+        store
+            .create(&mut record(&clock, Duration::from_secs(60)))
+            .await
+            .unwrap();
+
+        clock.advance(Duration::from_secs(1));
+        assert!(store.check_create_rate().is_err());
+        clock.advance(Duration::from_secs(1));
+        assert!(store.check_create_rate().is_ok());
+    }
+
+    #[test]
+    fn tokens_dont_build_up_past_the_burst() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut limit = CreateRateLimit {
+            per_second: 10.0,
+            burst: 2.0,
+            tokens: 2.0,
+            updated: None,
+        };
+        assert!(limit.try_acquire(now));
+        let later = now + Duration::from_secs(3600);
+        assert!(limit.try_acquire(later));
+        assert!(limit.try_acquire(later));
+        assert!(!limit.try_acquire(later));
+    }
+
+    #[test]
+    fn fresh_limit_doesnt_share_tokens() {
+        let (store, _, _) = store();
+        let store = store.set_create_rate_limit(0.0, 1);
+        let limit = store.create_rate_limit.as_ref().unwrap();
+        let mut fresh = limit.lock().unwrap().fresh();
+        assert!(store.check_create_rate().is_ok());
+        assert!(store.check_create_rate().is_err());
+        assert!(fresh.try_acquire(store.now()));
+    }
+}
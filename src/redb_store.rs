@@ -0,0 +1,256 @@
+use std::{path::Path, sync::Arc};
+
+use async_trait::async_trait;
+use redb::{Database, ReadableDatabase, ReadableTable, ReadableTableMetadata, TableDefinition};
+use time::OffsetDateTime;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+use crate::{
+    error::{decode_json, encode_json},
+    rt::unblock,
+    Clock, SystemClock,
+};
+
+/// Every session by ID, with its expiry date in nanoseconds since the Unix epoch.
+const SESSIONS: TableDefinition<i128, (i128, &[u8])> = TableDefinition::new("sessions");
+
+/// The ID of every session by its expiry date, so sweeps only visit expired sessions.
+const EXPIRIES: TableDefinition<(i128, i128), ()> = TableDefinition::new("expiries");
+
+/// A session storage that keeps all sessions in a single [redb](redb) database file.
+///
+/// Needs no setup just like [`FileSessionStorage`](crate::FileSessionStorage), but avoids a file
+/// per session, which is faster with many sessions and doesn't use up inodes. Sessions are JSON
+/// encoded like on disk and expired sessions are never loaded. Expiry sweeps only visit expired
+/// sessions, so they are cheap enough to run often.
+///
+/// Only one process can open the database at a time, clones of the store share it.
+#[derive(Debug, Clone)]
+pub struct RedbSessionStorage {
+    db: Arc<Database>,
+    clock: Arc<dyn Clock>,
+}
+
+fn backend(e: impl Into<redb::Error>) -> session_store::Error {
+    session_store::Error::Backend(format!("Session database failed: {}", e.into()))
+}
+
+fn expiry_key(expiry_date: OffsetDateTime) -> i128 {
+    expiry_date.unix_timestamp_nanos()
+}
+
+impl RedbSessionStorage {
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> session_store::Result<Self> {
+        let db = Database::create(path).map_err(backend)?;
+        let txn = db.begin_write().map_err(backend)?;
+        txn.open_table(SESSIONS).map_err(backend)?;
+        txn.open_table(EXPIRIES).map_err(backend)?;
+        txn.commit().map_err(backend)?;
+        Ok(RedbSessionStorage {
+            db: Arc::new(db),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Take the current time from `clock` instead of the system time when checking expiry, for
+    /// example a [`ManualClock`](crate::ManualClock) in tests.
+    pub fn set_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::from(self.clock.now())
+    }
+
+    /// Run `f` on the database on a thread where blocking is allowed.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Database) -> session_store::Result<T> + Send + 'static,
+    ) -> session_store::Result<T> {
+        let db = self.db.clone();
+        unblock(move || f(&db)).await
+    }
+
+    /// Count the sessions in the store, including expired ones that haven't been deleted yet.
+    pub async fn count_sessions(&self) -> session_store::Result<usize> {
+        self.run(|db| {
+            let txn = db.begin_read().map_err(backend)?;
+            let sessions = txn.open_table(SESSIONS).map_err(backend)?;
+            Ok(sessions.len().map_err(backend)? as usize)
+        })
+        .await
+    }
+
+    /// List the IDs of all sessions currently in the store, including expired ones that haven't
+    /// been deleted yet.
+    pub async fn list_session_ids(&self) -> session_store::Result<Vec<Id>> {
+        self.run(|db| {
+            let txn = db.begin_read().map_err(backend)?;
+            let sessions = txn.open_table(SESSIONS).map_err(backend)?;
+            sessions
+                .iter()
+                .map_err(backend)?
+                .map(|entry| Ok(Id(entry.map_err(backend)?.0.value())))
+                .collect()
+        })
+        .await
+    }
+
+    /// Get just the expiry date of a session, `None` if it doesn't exist.
+    pub async fn get_expiry(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<OffsetDateTime>> {
+        let session_id = session_id.0;
+        self.run(move |db| {
+            let txn = db.begin_read().map_err(backend)?;
+            let sessions = txn.open_table(SESSIONS).map_err(backend)?;
+            let Some(entry) = sessions.get(session_id).map_err(backend)? else {
+                return Ok(None);
+            };
+            let (expiry, _) = entry.value();
+            OffsetDateTime::from_unix_timestamp_nanos(expiry)
+                .map(Some)
+                .map_err(|e| session_store::Error::Decode(e.to_string()))
+        })
+        .await
+    }
+
+    /// Delete every session in the store, returns the number of sessions deleted.
+    pub async fn clear_all(&self) -> session_store::Result<usize> {
+        self.run(|db| {
+            let txn = db.begin_write().map_err(backend)?;
+            let deleted = {
+                let mut sessions = txn.open_table(SESSIONS).map_err(backend)?;
+                let mut expiries = txn.open_table(EXPIRIES).map_err(backend)?;
+                let deleted = sessions.len().map_err(backend)? as usize;
+                sessions.retain(|_, _| false).map_err(backend)?;
+                expiries.retain(|_, _| false).map_err(backend)?;
+                deleted
+            };
+            txn.commit().map_err(backend)?;
+            Ok(deleted)
+        })
+        .await
+    }
+
+    /// Write a session, replacing the existing one with the same ID unless `new` is set.
+    async fn write(&self, record: &Record, new: bool) -> session_store::Result<Id> {
+        let mut record = record.clone();
+        let expiry = expiry_key(record.expiry_date);
+        self.run(move |db| {
+            let txn = db.begin_write().map_err(backend)?;
+            {
+                let mut sessions = txn.open_table(SESSIONS).map_err(backend)?;
+                let mut expiries = txn.open_table(EXPIRIES).map_err(backend)?;
+                if new {
+                    // Session ID collision, try again with a fresh ID
+                    while sessions.get(record.id.0).map_err(backend)?.is_some() {
+                        record.id = Id::default();
+                    }
+                }
+                let session_id = record.id.0;
+                let contents = encode_json(&record)?;
+                let old = sessions
+                    .insert(session_id, (expiry, contents.as_slice()))
+                    .map_err(backend)?
+                    .map(|old| old.value().0);
+                if let Some(old_expiry) = old {
+                    expiries.remove((old_expiry, session_id)).map_err(backend)?;
+                }
+                expiries.insert((expiry, session_id), ()).map_err(backend)?;
+            }
+            txn.commit().map_err(backend)?;
+            Ok(record.id)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedbSessionStorage {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        record.id = self.write(record, true).await?;
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.write(record, false).await?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let session_id = session_id.0;
+        let now = expiry_key(self.now_utc());
+        let contents = self
+            .run(move |db| {
+                let txn = db.begin_read().map_err(backend)?;
+                let sessions = txn.open_table(SESSIONS).map_err(backend)?;
+                let Some(entry) = sessions.get(session_id).map_err(backend)? else {
+                    return Ok(None);
+                };
+                let (expiry, contents) = entry.value();
+                Ok((expiry >= now).then(|| contents.to_vec()))
+            })
+            .await?;
+        contents.as_deref().map(decode_json).transpose()
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        let session_id = session_id.0;
+        self.run(move |db| {
+            let txn = db.begin_write().map_err(backend)?;
+            {
+                let mut sessions = txn.open_table(SESSIONS).map_err(backend)?;
+                let mut expiries = txn.open_table(EXPIRIES).map_err(backend)?;
+                let old = sessions
+                    .remove(session_id)
+                    .map_err(backend)?
+                    .map(|old| old.value().0);
+                if let Some(old_expiry) = old {
+                    expiries.remove((old_expiry, session_id)).map_err(backend)?;
+                }
+            }
+            txn.commit().map_err(backend)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for RedbSessionStorage {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let now = expiry_key(self.now_utc());
+        self.run(move |db| {
+            let txn = db.begin_write().map_err(backend)?;
+            {
+                let mut sessions = txn.open_table(SESSIONS).map_err(backend)?;
+                let mut expiries = txn.open_table(EXPIRIES).map_err(backend)?;
+                let expired = expiries
+                    .extract_from_if(..(now, i128::MIN), |_, _| true)
+                    .map_err(backend)?;
+                for entry in expired {
+                    let (key, _) = entry.map_err(backend)?;
+                    let (_, session_id) = key.value();
+                    sessions.remove(session_id).map_err(backend)?;
+                }
+            }
+            txn.commit().map_err(backend)
+        })
+        .await
+    }
+
+    /// Like the default, but sleeps on the runtime picked by the crate features instead of
+    /// needing a tokio timer, and keeps going when a sweep fails.
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
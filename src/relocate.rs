@@ -0,0 +1,322 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use futures::TryStreamExt;
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{
+    access::ACCESS_FOLDER,
+    error::{FileError, IoResultExt},
+    index::INDEX_FOLDER,
+    naming::encode_id,
+    FileSessionStorage, Folders,
+};
+
+impl FileSessionStorage {
+    /// Also look for sessions in `legacy_folder`, moving them to the current folder as soon as
+    /// they are used.
+    ///
+    /// Lets you change the location of the sessions without logging everybody out. Sessions that
+    /// are never used again stay in the legacy folder, they aren't included in expiry sweeps or
+    /// listings. This store gets its own folders, the stores it was cloned from keep theirs.
+    pub fn set_legacy_folder(mut self, legacy_folder: impl Into<PathBuf>) -> Self {
+        let primary = self.folders.read().unwrap().primary.clone();
+        self.folders = Arc::new(RwLock::new(Folders {
+            primary,
+            legacy: Some(Arc::from(legacy_folder.into())),
+            primary_seen: false,
+            relocating: false,
+        }));
+        self
+    }
+
+    /// Move all sessions to a new folder while the store stays in use, for example when the disk
+    /// holding the current folder is being retired.
+    ///
+    /// The store switches to the new folder right away, sessions that haven't been moved yet are
+    /// moved as soon as they are used. Once every session was moved the old folder is removed if it
+    /// is empty. Only clones of this store know about the move, other processes using the same
+    /// folder need to be restarted with the new folder. Returns the number of sessions moved.
+    ///
+    /// Sessions still in the folder set with [`set_legacy_folder`](Self::set_legacy_folder) are
+    /// moved to the current folder first.
+    pub async fn relocate(&self, new_folder: impl Into<PathBuf>) -> session_store::Result<usize> {
+        let configured_legacy = {
+            let mut folders = self.folders.write().unwrap();
+            if folders.relocating {
+                return Err(session_store::Error::Backend(
+                    "Already moving sessions to another folder".to_string(),
+                ));
+            }
+            folders.relocating = true;
+            folders.legacy.clone()
+        };
+        let moved = self.relocate_to(new_folder.into(), configured_legacy).await;
+        self.folders.write().unwrap().relocating = false;
+        moved
+    }
+
+    async fn relocate_to(
+        &self,
+        new_folder: PathBuf,
+        configured_legacy: Option<Arc<Path>>,
+    ) -> session_store::Result<usize> {
+        // They are counted when they move on to the new folder
+        if let Some(legacy) = configured_legacy {
+            self.migrate_all(&legacy).await?;
+            self.folders.write().unwrap().legacy = None;
+            self.remove_old_folder(&legacy).await;
+        }
+
+        let new_folder = Arc::from(new_folder);
+        self.fs
+            .create_dir_all(&new_folder)
+            .await
+            .context("create folder", &new_folder)?;
+        let old_folder = {
+            let mut folders = self.folders.write().unwrap();
+            let old_folder = std::mem::replace(&mut folders.primary, new_folder);
+            folders.legacy = Some(old_folder.clone());
+            folders.primary_seen = true;
+            old_folder
+        };
+
+        let moved = self.migrate_all(&old_folder).await;
+        self.folders.write().unwrap().legacy = None;
+        let moved = moved?;
+        self.remove_old_folder(&old_folder).await;
+        Ok(moved)
+    }
+
+    /// Remove a folder every session was moved out of, if nothing else is left in it.
+    async fn remove_old_folder(&self, old_folder: &Path) {
+        // The indexes have been rebuilt in the new folder while moving
+        let _ = self.fs.remove_dir_all(&old_folder.join(INDEX_FOLDER)).await;
+        let _ = self.fs.remove_dir(&old_folder.join(ACCESS_FOLDER)).await;
+        let _ = self.fs.remove_dir(old_folder).await;
+    }
+
+    /// Move every session in `old_folder` to the current folder.
+    async fn migrate_all(&self, old_folder: &Arc<Path>) -> session_store::Result<usize> {
+        let mut moved = 0;
+        let mut entries = std::pin::pin!(self.entries_in(old_folder.clone()));
+        while let Some((session_id, _)) = entries.try_next().await? {
+            if self.ensure_migrated(&session_id).await? {
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Like [`migrate_session`](Self::migrate_session), but takes the lock itself.
+    pub(crate) async fn ensure_migrated(&self, session_id: &Id) -> session_store::Result<bool> {
+        if self.folders.read().unwrap().legacy.is_none() {
+            return Ok(false);
+        }
+        let _guard = self.locks.lock(*session_id).await;
+        self.migrate_session(session_id).await
+    }
+
+    /// If the session is still named the way an older version named it, rename it and its blobs
+    /// and last access time to the current names.
+    ///
+    /// Only needs to be called when the session wasn't found under its current name. Returns
+    /// `true` if the session was renamed.
+    pub(crate) async fn adopt_legacy_name(&self, session_id: &Id) -> session_store::Result<bool> {
+        let Some(legacy) = self.naming.legacy_path(session_id) else {
+            return Ok(false);
+        };
+        let folder = self.folder();
+        let old_path = folder.join(legacy);
+        let new_path = self.session_path(session_id);
+        if old_path == new_path || !self.fs.metadata(&old_path).await.is_ok_and(|m| m.is_file()) {
+            return Ok(false);
+        }
+        self.create_session_folder(&new_path).await?;
+        match self.fs.rename(&old_path, &new_path).await {
+            Ok(()) => {}
+            // Renamed by a concurrent call
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(FileError::new("rename", &old_path, e).into()),
+        }
+        let old_blobs = folder.join(format!("{session_id}.blobs"));
+        if self.fs.metadata(&old_blobs).await.is_ok_and(|m| m.is_dir()) {
+            self.fs
+                .rename(&old_blobs, &self.blob_folder(session_id))
+                .await
+                .context("rename blobs", &old_blobs)?;
+        }
+        let old_access = folder.join(ACCESS_FOLDER).join(session_id.to_string());
+        if self
+            .fs
+            .metadata(&old_access)
+            .await
+            .is_ok_and(|m| m.is_file())
+        {
+            let new_access = folder.join(ACCESS_FOLDER).join(encode_id(session_id));
+            self.fs
+                .rename(&old_access, &new_access)
+                .await
+                .context("rename access time", &old_access)?;
+        }
+        Ok(true)
+    }
+
+    /// If the session is still in the legacy folder, move it to the current folder.
+    ///
+    /// The caller must hold the lock for this session. Returns `true` if the session was moved.
+    pub(crate) async fn migrate_session(&self, session_id: &Id) -> session_store::Result<bool> {
+        let Some(legacy) = self.folders.read().unwrap().legacy.clone() else {
+            return Ok(false);
+        };
+        let mut old_path = legacy.join(self.naming.path(session_id));
+        if !self.fs.metadata(&old_path).await.is_ok_and(|m| m.is_file()) {
+            // The legacy folder may have been written by an older version
+            match self.naming.legacy_path(session_id) {
+                Some(path)
+                    if self
+                        .fs
+                        .metadata(&legacy.join(&path))
+                        .await
+                        .is_ok_and(|m| m.is_file()) =>
+                {
+                    old_path = legacy.join(path);
+                }
+                _ => return Ok(false),
+            }
+        }
+        let new_path = self.session_path(session_id);
+        if self.exists(session_id).await? {
+            // Already written in the new folder, which is more recent
+            let _ = self.fs.remove_file(&old_path).await;
+            return Ok(false);
+        }
+
+        self.create_session_folder(&new_path).await?;
+        self.move_file(&old_path, &new_path).await?;
+        for name in [encode_id(session_id), session_id.to_string()] {
+            let old_blobs = legacy.join(format!("{name}.blobs"));
+            if self.fs.metadata(&old_blobs).await.is_ok_and(|m| m.is_dir()) {
+                self.move_folder(&old_blobs, &self.blob_folder(session_id))
+                    .await?;
+            }
+            let old_access = legacy.join(ACCESS_FOLDER).join(&name);
+            if self
+                .fs
+                .metadata(&old_access)
+                .await
+                .is_ok_and(|m| m.is_file())
+            {
+                let new_access = self.folder().join(ACCESS_FOLDER);
+                self.fs
+                    .create_dir_all(&new_access)
+                    .await
+                    .context("create folder", &new_access)?;
+                self.move_file(&old_access, &new_access.join(encode_id(session_id)))
+                    .await?;
+            }
+        }
+        if !self.indexes.is_empty() {
+            if let Some(record) = self.read_record(session_id).await? {
+                self.add_to_indexes(&record).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Move a file, copying it if it can't be renamed, for example because the folders are on
+    /// different file systems.
+    async fn move_file(&self, from: &Path, to: &Path) -> session_store::Result<()> {
+        if self.fs.rename(from, to).await.is_ok() {
+            return Ok(());
+        }
+        self.fs.copy(from, to).await.context("move", from)?;
+        self.fs.remove_file(from).await.context("delete", from)
+    }
+
+    /// Move a folder of files, like the blobs of a session, copying them if it can't be renamed.
+    async fn move_folder(&self, from: &Path, to: &Path) -> session_store::Result<()> {
+        if self.fs.rename(from, to).await.is_ok() {
+            return Ok(());
+        }
+        self.fs
+            .create_dir_all(to)
+            .await
+            .context("create folder", to)?;
+        let mut entries = crate::fs::read_dir(&self.fs, from)
+            .await
+            .context("list folder", from)?;
+        while let Some(dir_entry) = entries.next_entry().await.context("list folder", from)? {
+            let path = dir_entry.path();
+            self.fs
+                .copy(&path, &to.join(dir_entry.file_name()))
+                .await
+                .context("move", &path)?;
+        }
+        self.fs.remove_dir_all(from).await.context("delete", from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::{
+        tests::{record, store},
+        Fs,
+    };
+
+    #[tokio::test]
+    async fn relocate_moves_every_session() {
+        let (store, fs, clock) = store();
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            let mut session = record(&clock, Duration::from_secs(60));
+            store.create(&mut session).await.unwrap();
+            sessions.push(session);
+        }
+
+        assert_eq!(store.relocate("/new").await.unwrap(), 3);
+        assert_eq!(&*store.folder(), Path::new("/new"));
+        assert!(!fs.try_exists(Path::new("/sessions")).await.unwrap());
+        for session in &sessions {
+            assert!(fs
+                .try_exists(&store.session_path(&session.id))
+                .await
+                .unwrap());
+            assert_eq!(
+                store.load(&session.id).await.unwrap().as_ref(),
+                Some(session)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_sessions_move_when_used() {
+        let (old_store, fs, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(60));
+        old_store.create(&mut session).await.unwrap();
+
+        let store = FileSessionStorage::new_in_folder(Path::new("/new"))
+            .set_fs(fs.clone())
+            .set_clock(clock.clone())
+            .set_legacy_folder("/sessions");
+        assert_eq!(
+            store.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert!(!fs
+            .try_exists(&old_store.session_path(&session.id))
+            .await
+            .unwrap());
+        assert!(fs
+            .try_exists(&store.session_path(&session.id))
+            .await
+            .unwrap());
+    }
+}
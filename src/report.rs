@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::TryStreamExt;
+use time::OffsetDateTime;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::FileSessionStorage;
+
+/// How many failed operations are kept for [`FileSessionStorage::dump_report`].
+pub(crate) const RECENT_ERROR_CAPACITY: usize = 16;
+
+/// How many of the largest sessions are listed by [`FileSessionStorage::dump_report`].
+const LARGEST_SESSIONS: usize = 5;
+
+/// A failed store operation, kept for [`FileSessionStorage::dump_report`].
+#[derive(Debug, Clone)]
+pub(crate) struct RecentError {
+    at: SystemTime,
+    operation: &'static str,
+    message: String,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl FileSessionStorage {
+    /// Remember a failed operation, dropping the oldest one once there are too many.
+    pub(crate) fn record_error(&self, operation: &'static str, error: &session_store::Error) {
+        let mut errors = self.recent_errors.lock().unwrap();
+        if errors.len() == RECENT_ERROR_CAPACITY {
+            errors.pop_front();
+        }
+        errors.push_back(RecentError {
+            at: self.now(),
+            operation,
+            message: crate::telemetry::redact_session_ids(&error.to_string()),
+        });
+    }
+
+    /// Write a human readable summary of the store to `writer`, for attaching to bug reports.
+    ///
+    /// Includes the configuration, session counts, the largest sessions, the soonest and latest
+    /// expiry dates and the most recent failed operations of this process. Session data and IDs
+    /// are not included, sessions are listed by a hash of their ID and IDs in error messages are
+    /// replaced by it. This needs to load every session.
+    pub async fn dump_report(
+        &self,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> session_store::Result<()> {
+        let mut active = 0;
+        let mut expired = 0;
+        let mut total_bytes = 0;
+        let mut largest: Vec<(u64, Id)> = Vec::new();
+        let mut soonest_expiry: Option<OffsetDateTime> = None;
+        let mut latest_expiry: Option<OffsetDateTime> = None;
+        let now = self.now_utc();
+
+        let mut entries = std::pin::pin!(self.session_entries());
+        while let Some((session_id, dir_entry)) = entries.try_next().await? {
+            let Ok(metadata) = dir_entry.metadata().await else {
+                // Deleted since we listed the folder
+                continue;
+            };
+            // Unreadable sessions shouldn't keep the report from showing the errors they cause
+            let Ok(Some(record)) = self.read_record(&session_id).await else {
+                continue;
+            };
+            total_bytes += metadata.len();
+            largest.push((metadata.len(), session_id));
+            largest.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+            largest.truncate(LARGEST_SESSIONS);
+            if record.expiry_date < now {
+                expired += 1;
+                continue;
+            }
+            active += 1;
+            soonest_expiry = Some(soonest_expiry.map_or(record.expiry_date, |soonest| {
+                soonest.min(record.expiry_date)
+            }));
+            latest_expiry = Some(
+                latest_expiry.map_or(record.expiry_date, |latest| latest.max(record.expiry_date)),
+            );
+        }
+
+        let mut out = String::new();
+        let legacy_folder = self.folders.read().unwrap().legacy.clone();
+        let _ = writeln!(out, "# Session store report");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "## Configuration");
+        let _ = writeln!(out, "folder: {}", self.folder().display());
+        if let Some(legacy) = legacy_folder {
+            let _ = writeln!(out, "legacy folder: {}", legacy.display());
+        }
+        if let Some(mirror) = &self.mirror {
+            let _ = writeln!(out, "mirror folder: {}", mirror.display());
+            let _ = writeln!(out, "read fallback: {:?}", self.read_fallback);
+        }
+        let _ = writeln!(
+            out,
+            "minimum expiry date: {}s",
+            self.minimum_expiry_date().as_secs()
+        );
+        let _ = writeln!(
+            out,
+            "sweep partition: {} of {}",
+            self.sweep_partition.index, self.sweep_partition.count
+        );
+        let _ = writeln!(out, "cross process locking: {}", self.cross_process_locking);
+        let index_names: Vec<&str> = self.indexes.iter().map(|index| index.name()).collect();
+        let _ = writeln!(out, "indexes: {}", index_names.join(", "));
+        let _ = writeln!(out, "minimum free space: {} bytes", self.minimum_free_space);
+        if let Some(threshold) = self.slow_operation_threshold() {
+            let _ = writeln!(out, "slow operation threshold: {}ms", threshold.as_millis());
+        }
+        let _ = writeln!(out, "track last access: {}", self.track_last_access);
+        let _ = writeln!(out);
+
+        let _ = writeln!(out, "## Sessions");
+        let _ = writeln!(out, "active: {active}");
+        let _ = writeln!(out, "expired: {expired}");
+        let _ = writeln!(out, "total size: {total_bytes} bytes");
+        if let (Some(soonest), Some(latest)) = (soonest_expiry, latest_expiry) {
+            let _ = writeln!(
+                out,
+                "soonest expiry: in {}s",
+                Duration::try_from(soonest - now)
+                    .unwrap_or_default()
+                    .as_secs()
+            );
+            let _ = writeln!(
+                out,
+                "latest expiry: in {}s",
+                Duration::try_from(latest - now)
+                    .unwrap_or_default()
+                    .as_secs()
+            );
+        }
+        let _ = writeln!(out, "largest:");
+        for (size, session_id) in &largest {
+            let _ = writeln!(
+                out,
+                "  session-{}\t{size} bytes",
+                crate::telemetry::session_hash(session_id)
+            );
+        }
+        let _ = writeln!(out);
+
+        let _ = writeln!(out, "## Last sweep");
+        match self.last_sweep() {
+            Some(sweep) => {
+                let _ = writeln!(
+                    out,
+                    "finished at {}, took {}ms, checked {}, deleted {}, failed {}",
+                    unix_secs(sweep.finished_at),
+                    sweep.duration.as_millis(),
+                    sweep.checked,
+                    sweep.deleted,
+                    sweep.failed
+                );
+            }
+            None => {
+                let _ = writeln!(out, "none");
+            }
+        }
+        let _ = writeln!(out);
+
+        let _ = writeln!(out, "## Recent errors");
+        let errors: VecDeque<RecentError> = self.recent_errors.lock().unwrap().clone();
+        if errors.is_empty() {
+            let _ = writeln!(out, "none");
+        }
+        for error in errors.iter().rev() {
+            let _ = writeln!(
+                out,
+                "{}\t{}\t{}",
+                unix_secs(error.at),
+                error.operation,
+                error.message
+            );
+        }
+
+        writer
+            .write_all(out.as_bytes())
+            .await
+            .map_err(|e| session_store::Error::Backend(format!("Failed to write report: {e}")))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| session_store::Error::Backend(format!("Failed to write report: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::{
+        naming::encode_id,
+        telemetry::session_hash,
+        tests::{record, store},
+    };
+
+    #[tokio::test]
+    async fn report_leaves_out_session_ids() {
+        let (store, fs, clock) = store();
+        let mut session = record(&clock, Duration::from_secs(3600));
+        store.create(&mut session).await.unwrap();
+        fs.fail_reads(Some(std::io::ErrorKind::PermissionDenied));
+        store.load(&session.id).await.unwrap_err();
+        fs.fail_reads(None);
+
+        let mut report = Vec::new();
+        store.dump_report(&mut report).await.unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(!report.contains(&encode_id(&session.id)));
+        assert!(!report.contains(&session.id.to_string()));
+        let hash = format!("session-{}", session_hash(&session.id));
+        // Once in the largest sessions, once in the error
+        assert_eq!(report.matches(&hash).count(), 2, "{report}");
+    }
+}
@@ -0,0 +1,83 @@
+//! The async runtime the store runs blocking file system calls and timers on, picked by the
+//! `tokio`, `async-std` and `smol` features, in that order if several are enabled.
+
+use std::time::Duration;
+
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+compile_error!("enable one of the `tokio`, `async-std` or `smol` features");
+
+/// Run a blocking function on a thread where blocking is allowed.
+#[cfg(all(feature = "tokio", not(target_os = "wasi")))]
+pub(crate) async fn unblock<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(_) => panic!("blocking task was cancelled"),
+        },
+    }
+}
+
+/// Run a blocking function on a thread where blocking is allowed.
+#[cfg(all(feature = "async-std", not(feature = "tokio"), not(target_os = "wasi")))]
+pub(crate) async fn unblock<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    async_std::task::spawn_blocking(f).await
+}
+
+/// Run a blocking function on a thread where blocking is allowed.
+#[cfg(all(
+    feature = "smol",
+    not(any(feature = "tokio", feature = "async-std")),
+    not(target_os = "wasi")
+))]
+pub(crate) async fn unblock<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    ::blocking::unblock(f).await
+}
+
+/// WASI preview 1 has no threads to offload to and its file system calls are host calls, so
+/// they run inline.
+#[cfg(target_os = "wasi")]
+pub(crate) async fn unblock<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    f()
+}
+
+/// Wait for `duration` to pass.
+#[cfg(feature = "tokio")]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Wait for `duration` to pass.
+#[cfg(all(feature = "async-std", not(feature = "tokio")))]
+pub(crate) async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+/// Wait for `duration` to pass.
+#[cfg(all(feature = "smol", not(any(feature = "tokio", feature = "async-std"))))]
+pub(crate) async fn sleep(duration: Duration) {
+    async_io::Timer::after(duration).await;
+}
+
+/// Note a failed expiry sweep, the next one tries again.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn sweep_failed(error: &tower_sessions_core::session_store::Error) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(%error, "deleting expired sessions failed, trying again next period");
+}
+
+/// Run `sweep` every `period` forever, for `continuously_delete_expired`. A failed sweep is
+/// logged and doesn't stop the loop, so a disk that is briefly unavailable doesn't end expiry
+/// for the rest of the process.
+pub(crate) async fn sweep_every<F, Fut>(period: Duration, mut sweep: F) -> !
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = tower_sessions_core::session_store::Result<()>>,
+{
+    loop {
+        sleep(period).await;
+        if let Err(e) = sweep().await {
+            sweep_failed(&e);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tower_sessions_core::session_store;
+
+use crate::{
+    error::{FileError, IoResultExt},
+    fs::LOCK_FILE_SUFFIX,
+    naming::decode_id,
+    FileSessionStorage, TEMP_FILE_SUFFIX,
+};
+
+/// Temporary files older than this are assumed to be left behind by a crash.
+const STALE_TEMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Why a file showed up in a [`ScanReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GarbageKind {
+    /// The file name is not a session ID.
+    InvalidName,
+    /// The file is named like a session, but its contents can't be parsed.
+    Unparseable,
+    /// A temporary file that was never renamed, probably left behind by a crash.
+    StaleTempFile,
+    /// A folder of blobs whose session no longer exists.
+    OrphanedBlobs,
+    /// A cross process lock file whose session no longer exists.
+    OrphanedLockFile,
+}
+
+/// A file in the sessions folder that isn't a valid session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GarbageFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Why the file isn't a valid session.
+    pub kind: GarbageKind,
+    /// Size of the file in bytes, 0 for folders.
+    pub size: u64,
+    /// Time since the file was last modified.
+    pub age: Duration,
+}
+
+/// The result of [`FileSessionStorage::scan_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanReport {
+    /// Number of valid sessions found.
+    pub sessions: usize,
+    /// Files that aren't valid sessions.
+    pub garbage: Vec<GarbageFile>,
+}
+
+impl FileSessionStorage {
+    /// Find files in the sessions folder that aren't valid sessions, without deleting anything.
+    ///
+    /// Sub folders are assumed to belong to namespaces and are skipped, except for blob folders
+    /// of sessions that no longer exist.
+    pub async fn scan_report(&self) -> session_store::Result<ScanReport> {
+        let mut report = ScanReport::default();
+        let folder = self.folder();
+        let mut entries = match crate::fs::read_dir(&self.fs, &folder).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+            Err(e) => return Err(FileError::new("list folder", &folder, e).into()),
+        };
+        while let Some(dir_entry) = entries.next_entry().await.context("list folder", &folder)? {
+            let Ok(metadata) = dir_entry.metadata().await else {
+                // Deleted since we listed the folder
+                continue;
+            };
+            let file_name = dir_entry.file_name();
+            let name = file_name.to_string_lossy();
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| self.now().duration_since(modified).ok())
+                .unwrap_or_default();
+
+            let kind = if metadata.is_dir() {
+                match name.strip_suffix(".blobs").map(decode_id) {
+                    Some(Some(session_id)) if !self.exists(&session_id).await? => {
+                        GarbageKind::OrphanedBlobs
+                    }
+                    _ => continue,
+                }
+            } else if let Some(session_id) = self.naming.session_id(Path::new(&file_name)) {
+                if self.read_record(&session_id).await.is_ok() {
+                    report.sessions += 1;
+                    continue;
+                }
+                GarbageKind::Unparseable
+            } else if let Some(locked) = name
+                .strip_prefix('.')
+                .and_then(|name| name.strip_suffix(LOCK_FILE_SUFFIX))
+            {
+                match self.naming.session_id(Path::new(locked)) {
+                    Some(session_id) if !self.exists(&session_id).await? => {
+                        GarbageKind::OrphanedLockFile
+                    }
+                    Some(_) => continue,
+                    None => GarbageKind::InvalidName,
+                }
+            } else if name.starts_with('.') && name.ends_with(TEMP_FILE_SUFFIX) {
+                if age < STALE_TEMP_FILE_AGE {
+                    continue;
+                }
+                GarbageKind::StaleTempFile
+            } else {
+                GarbageKind::InvalidName
+            };
+            report.garbage.push(GarbageFile {
+                path: dir_entry.path(),
+                kind,
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                age,
+            });
+        }
+        Ok(report)
+    }
+}
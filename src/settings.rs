@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use crate::FileSessionStorage;
+
+/// The settings that can be changed while the store is in use, shared between clones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RuntimeSettings {
+    pub(crate) minimum_expiry_date: Duration,
+    pub(crate) slow_operation_threshold: Option<Duration>,
+    pub(crate) sweep_rate_limit: Option<u32>,
+    pub(crate) memory_cache_capacity: usize,
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        RuntimeSettings {
+            minimum_expiry_date: Duration::from_secs(60),
+            slow_operation_threshold: None,
+            sweep_rate_limit: None,
+            memory_cache_capacity: 0,
+        }
+    }
+}
+
+impl FileSessionStorage {
+    /// The current settings, a copy so the lock isn't held.
+    pub(crate) fn settings(&self) -> RuntimeSettings {
+        *self.settings.read().unwrap()
+    }
+
+    /// Change the settings shared by this store and its clones, for the `set_*` methods.
+    pub(crate) fn with_settings(self, update: impl FnOnce(&mut RuntimeSettings)) -> Self {
+        update(&mut self.settings.write().unwrap());
+        self
+    }
+
+    /// Delete at most `per_second` sessions per second in expiry sweeps, so a sweep after a long
+    /// outage doesn't saturate the disk. Unlimited by default.
+    pub fn set_sweep_rate_limit(self, per_second: u32) -> Self {
+        self.with_settings(|settings| settings.sweep_rate_limit = Some(per_second.max(1)))
+    }
+
+    /// Change the minimum age of a file before the expiry sweep opens it, while the store is in
+    /// use.
+    ///
+    /// Like [`set_minimum_expiry_date`](Self::set_minimum_expiry_date) this applies to every
+    /// clone of the store, including namespaced stores created from it.
+    pub fn update_minimum_expiry_date(&self, duration: Duration) {
+        self.settings.write().unwrap().minimum_expiry_date = duration;
+    }
+
+    /// Change or disable the slow operation threshold while the store is in use, see
+    /// [`set_slow_operation_threshold`](Self::set_slow_operation_threshold).
+    ///
+    /// Applies to every clone of the store, including namespaced stores created from it.
+    pub fn update_slow_operation_threshold(&self, threshold: Option<Duration>) {
+        self.settings.write().unwrap().slow_operation_threshold = threshold;
+    }
+
+    /// Change or remove the limit set with [`set_sweep_rate_limit`](Self::set_sweep_rate_limit)
+    /// while the store is in use, a sweep that is running picks it up right away.
+    ///
+    /// Applies to every clone of the store, including namespaced stores created from it.
+    pub fn update_sweep_rate_limit(&self, per_second: Option<u32>) {
+        self.settings.write().unwrap().sweep_rate_limit = per_second.map(|limit| limit.max(1));
+    }
+
+    /// Change how many sessions the caches created with
+    /// [`with_memory_cache`](Self::with_memory_cache) hold while the store is in use. A smaller
+    /// cache drops its oldest sessions on the next insert.
+    ///
+    /// Applies to the caches of every clone of the store.
+    pub fn update_memory_cache_capacity(&self, capacity: usize) {
+        self.settings.write().unwrap().memory_cache_capacity = capacity;
+    }
+
+    /// The current limit on sessions deleted per second by expiry sweeps.
+    pub fn sweep_rate_limit(&self) -> Option<u32> {
+        self.settings().sweep_rate_limit
+    }
+
+    /// The current number of sessions the memory caches of the store hold.
+    pub fn memory_cache_capacity(&self) -> usize {
+        self.settings().memory_cache_capacity
+    }
+
+    /// The current minimum age of a file before the expiry sweep opens it.
+    pub fn minimum_expiry_date(&self) -> Duration {
+        self.settings().minimum_expiry_date
+    }
+
+    /// The current slow operation threshold, `None` if slow operations aren't reported.
+    pub fn slow_operation_threshold(&self) -> Option<Duration> {
+        self.settings().slow_operation_threshold
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use tokio::sync::broadcast;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+use crate::{
+    events, lock::SessionLocks, trash::Discard, BuildError, FileSessionStorage, Folders,
+    SessionEvent,
+};
+
+/// Sessions spread over several root folders, for example on different disks, so large
+/// deployments can spread the inodes and IO of their sessions.
+///
+/// Every session is assigned to a root by a hash of its ID and the root's path, using rendezvous
+/// hashing so adding or removing a root only moves the sessions that have to move. After changing
+/// the roots, sessions still in their old root are moved as soon as they are used, and
+/// [`rebalance`](Self::rebalance) moves the rest. Because of this, loading a session that
+/// doesn't exist checks every root.
+///
+/// Clones share the same roots.
+#[derive(Debug, Clone)]
+pub struct ShardedStore {
+    shards: Arc<[FileSessionStorage]>,
+}
+
+/// FNV-1a, which unlike the std hashers is guaranteed to stay the same between releases.
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl ShardedStore {
+    /// Spread sessions over `roots`, with the configuration of `base` for every root.
+    ///
+    /// The folder, legacy folder and mirror folder of `base` aren't used. Each root gets its own
+    /// circuit breaker, create rate limit and disk full state. Don't rename the roots, sessions
+    /// are assigned by their path.
+    ///
+    /// Fails with [`BuildError::NoShardRoots`] if `roots` is empty.
+    pub fn new(
+        base: &FileSessionStorage,
+        roots: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Result<Self, BuildError> {
+        let shards: Arc<[FileSessionStorage]> = roots
+            .into_iter()
+            .map(|root| base.with_root(root.into()))
+            .collect();
+        if shards.is_empty() {
+            return Err(BuildError::NoShardRoots);
+        }
+        Ok(ShardedStore { shards })
+    }
+
+    /// The store of every root, in the order they were passed to [`new`](Self::new).
+    pub fn shards(&self) -> &[FileSessionStorage] {
+        &self.shards
+    }
+
+    /// The store of the root a session belongs in.
+    pub fn shard_for(&self, session_id: &Id) -> &FileSessionStorage {
+        &self.shards[self.shard_index(session_id)]
+    }
+
+    fn shard_index(&self, session_id: &Id) -> usize {
+        let id = session_id.0.to_le_bytes();
+        self.shards
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, shard)| {
+                let root = shard.folder();
+                let root = root.to_string_lossy();
+                fnv1a(root.bytes().chain(id))
+            })
+            .map(|(index, _)| index)
+            .unwrap_or_default()
+    }
+
+    /// The store of the root a session belongs in, after moving the session there if it is in
+    /// another root.
+    async fn locate(&self, session_id: &Id) -> session_store::Result<&FileSessionStorage> {
+        let index = self.shard_index(session_id);
+        let shard = &self.shards[index];
+        if shard.exists(session_id).await? {
+            return Ok(shard);
+        }
+        for (other_index, other) in self.shards.iter().enumerate() {
+            if other_index != index && other.exists(session_id).await? {
+                move_session(other, shard, session_id).await?;
+                break;
+            }
+        }
+        Ok(shard)
+    }
+
+    async fn exists_in_any_root(&self, session_id: &Id) -> session_store::Result<bool> {
+        for shard in self.shards.iter() {
+            if shard.exists(session_id).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Move every session that is in the wrong root to the root it belongs in, for example after
+    /// adding a disk. Returns the number of sessions moved.
+    ///
+    /// The store can be used while this runs.
+    pub async fn rebalance(&self) -> session_store::Result<usize> {
+        let mut moved = 0;
+        for (index, shard) in self.shards.iter().enumerate() {
+            let misplaced: Vec<Id> = shard
+                .session_entries()
+                .try_filter_map(|(session_id, _)| {
+                    let misplaced = self.shard_index(&session_id) != index;
+                    async move { Ok(misplaced.then_some(session_id)) }
+                })
+                .try_collect()
+                .await?;
+            for session_id in misplaced {
+                if move_session(shard, self.shard_for(&session_id), &session_id).await? {
+                    moved += 1;
+                }
+            }
+        }
+        Ok(moved)
+    }
+}
+
+/// Move a session and its blobs between roots, returns `false` if it no longer exists.
+async fn move_session(
+    from: &FileSessionStorage,
+    to: &FileSessionStorage,
+    session_id: &Id,
+) -> session_store::Result<bool> {
+    let Some(record) = from.read_record(session_id).await? else {
+        return Ok(false);
+    };
+    let path = to.session_path(session_id);
+    to.create_session_folder(&path).await?;
+    let created_at = from.created_at_of(&from.session_path(session_id)).await?;
+    to.replace_file(session_id, &record, created_at).await?;
+    to.add_to_indexes(&record).await?;
+    for name in from.list_blobs(session_id).await? {
+        if let Some(bytes) = from.get_blob(session_id, &name).await? {
+            to.put_blob(session_id, &name, bytes).await?;
+        }
+    }
+    from.remove_session(session_id, Discard::Delete).await
+}
+
+impl FileSessionStorage {
+    /// A store with the same configuration, but with sessions placed in `root`.
+    fn with_root(&self, root: PathBuf) -> Self {
+        FileSessionStorage {
+            folders: Arc::new(RwLock::new(Folders {
+                primary: Arc::from(root),
+                legacy: None,
+                primary_seen: false,
+                relocating: false,
+            })),
+            mirror: None,
+            locks: SessionLocks::default(),
+            events: broadcast::Sender::new(events::EVENT_CHANNEL_CAPACITY),
+            last_sweep: Arc::new(Mutex::new(None)),
+            cached_stats: Arc::new(Mutex::new(None)),
+            recent_errors: Arc::default(),
+            disk_full_since: Arc::default(),
+            create_rate_limit: self
+                .create_rate_limit
+                .as_ref()
+                .map(|limit| Arc::new(Mutex::new(limit.lock().unwrap().fresh()))),
+            circuit_breaker: self
+                .circuit_breaker
+                .as_ref()
+                .map(|breaker| Arc::new(Mutex::new(breaker.lock().unwrap().fresh()))),
+            ..self.clone()
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for ShardedStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            // Pick an ID no root has yet, so the shard doesn't have to change it
+            while self.exists_in_any_root(&record.id).await? {
+                record.id = Id::default();
+            }
+            let shard = self.shard_for(&record.id);
+            shard.create(record).await?;
+            if std::ptr::eq(shard, self.shard_for(&record.id)) {
+                return Ok(());
+            }
+            // Collided with a concurrent create and got an ID of another root, undo it like a
+            // delete so subscribers and hooks don't keep a session that doesn't exist
+            if shard.remove_session(&record.id, Discard::Delete).await? {
+                shard.emit(SessionEvent::Deleted(record.id));
+                shard.hooks.deleted(&record.id).await;
+            }
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.locate(&record.id).await?.save(record).await
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        self.locate(session_id).await?.load(session_id).await
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.locate(session_id).await?.delete(session_id).await
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for ShardedStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        // Sweep every shard even if one fails, returning the first failure
+        let mut result = Ok(());
+        for shard in self.shards.iter() {
+            result = result.and(shard.delete_expired().await);
+        }
+        result
+    }
+
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[test]
+    fn needs_a_root() {
+        let (base, _, _) = store();
+        let roots: [&str; 0] = [];
+        assert_eq!(
+            ShardedStore::new(&base, roots).unwrap_err(),
+            BuildError::NoShardRoots
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_follow_their_root_after_adding_one() {
+        let (base, _, clock) = store();
+        let sharded = ShardedStore::new(&base, ["/disk-a"]).unwrap();
+        let mut sessions = Vec::new();
+        for _ in 0..8 {
+            let mut session = record(&clock, Duration::from_secs(60));
+            sharded.create(&mut session).await.unwrap();
+            sessions.push(session);
+        }
+
+        let sharded = ShardedStore::new(&base, ["/disk-a", "/disk-b"]).unwrap();
+        let misplaced = sessions
+            .iter()
+            .filter(|session| sharded.shard_for(&session.id).folder().ends_with("disk-b"))
+            .count();
+        assert_eq!(sharded.rebalance().await.unwrap(), misplaced);
+        for session in &sessions {
+            assert!(sharded
+                .shard_for(&session.id)
+                .exists(&session.id)
+                .await
+                .unwrap());
+            assert_eq!(
+                sharded.load(&session.id).await.unwrap().as_ref(),
+                Some(session)
+            );
+        }
+        assert_eq!(sharded.rebalance().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,290 @@
+use std::path::PathBuf;
+
+use futures::TryStreamExt;
+use time::OffsetDateTime;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store,
+};
+
+use crate::{
+    created::decode_created_at,
+    error::{decode_json, FileError, IoResultExt},
+    naming::encode_id,
+    session_id_from_file_name, FileSessionStorage, SessionEvent,
+};
+
+/// What [`FileSessionStorage::restore_from_dir`] does with a session that already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestoreConflict {
+    /// Keep the existing session.
+    #[default]
+    Skip,
+    /// Replace the existing session with the one from the snapshot.
+    Overwrite,
+    /// Don't restore anything and return an error.
+    Fail,
+}
+
+/// Options for [`FileSessionStorage::restore_from_dir`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// What to do with sessions that already exist.
+    pub on_conflict: RestoreConflict,
+    /// Only count what would be restored, without changing anything.
+    pub dry_run: bool,
+}
+
+/// The result of [`FileSessionStorage::restore_from_dir`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreSummary {
+    /// Number of sessions restored, or that would be restored in a dry run.
+    pub restored: usize,
+    /// Number of sessions skipped because they already exist.
+    pub skipped: usize,
+}
+
+impl FileSessionStorage {
+    /// Hard link every session file into `dest_folder`, giving backup tools a point in time view
+    /// of the store without copying any data.
+    ///
+    /// Sessions are always saved by replacing the file, so the links keep pointing at the contents
+    /// at the time of the snapshot. `dest_folder` must be on the same file system as the sessions
+    /// folder. Returns the number of sessions linked.
+    pub async fn snapshot_hardlink(
+        &self,
+        dest_folder: impl Into<PathBuf>,
+    ) -> session_store::Result<usize> {
+        let dest_folder = dest_folder.into();
+        self.fs
+            .create_dir_all(&dest_folder)
+            .await
+            .context("create folder", &dest_folder)?;
+        let mut linked = 0;
+        let mut entries = std::pin::pin!(self.session_entries());
+        while let Some((session_id, dir_entry)) = entries.try_next().await? {
+            let path = dir_entry.path();
+            match self
+                .fs
+                .hard_link(&path, &dest_folder.join(encode_id(&session_id)))
+                .await
+            {
+                Ok(_) => linked += 1,
+                // Deleted since we listed the folder
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(FileError::new("link", &path, e).into()),
+            }
+        }
+        Ok(linked)
+    }
+
+    /// Copy the sessions from a snapshot folder, like one created by
+    /// [`snapshot_hardlink`](Self::snapshot_hardlink), back into the store.
+    ///
+    /// Files in `src_folder` that aren't sessions are ignored. With [`RestoreConflict::Fail`] the
+    /// conflicts are checked before anything is restored.
+    pub async fn restore_from_dir(
+        &self,
+        src_folder: impl Into<PathBuf>,
+        options: RestoreOptions,
+    ) -> session_store::Result<RestoreSummary> {
+        let src_folder = src_folder.into();
+        let mut session_ids = Vec::new();
+        let mut entries = crate::fs::read_dir(&self.fs, &src_folder)
+            .await
+            .context("list folder", &src_folder)?;
+        while let Some(dir_entry) = entries
+            .next_entry()
+            .await
+            .context("list folder", &src_folder)?
+        {
+            if let Some(session_id) = session_id_from_file_name(&dir_entry.file_name()) {
+                session_ids.push((session_id, dir_entry.path()));
+            }
+        }
+
+        if options.on_conflict == RestoreConflict::Fail {
+            for (session_id, _) in &session_ids {
+                if self.exists(session_id).await? {
+                    return Err(session_store::Error::Backend(format!(
+                        "Session {session_id} already exists"
+                    )));
+                }
+            }
+        }
+
+        let mut summary = RestoreSummary::default();
+        for (session_id, path) in session_ids {
+            let exists = self.exists(&session_id).await?;
+            if exists && options.on_conflict != RestoreConflict::Overwrite {
+                summary.skipped += 1;
+                continue;
+            }
+            if !options.dry_run {
+                let contents = self.fs.read(&path).await.context("read", &path)?;
+                let record: Record = decode_json(&contents)?;
+                let created_at = decode_created_at(&contents);
+                self.restore_record(&session_id, &record, created_at)
+                    .await?;
+            }
+            summary.restored += 1;
+        }
+        Ok(summary)
+    }
+
+    /// Write a record under the given ID, whether or not it already exists.
+    async fn restore_record(
+        &self,
+        session_id: &Id,
+        record: &Record,
+        created_at: Option<OffsetDateTime>,
+    ) -> session_store::Result<()> {
+        let _guard = self.locks.lock(*session_id).await;
+        self.migrate_session(session_id).await?;
+        let old = self.read_record(session_id).await?;
+        self.create_session_folder(&self.session_path(session_id))
+            .await?;
+        self.replace_file(session_id, record, created_at).await?;
+        if let Some(old) = &old {
+            self.remove_from_indexes(old, Some(record)).await?;
+        }
+        self.add_to_indexes(record).await?;
+        match old {
+            Some(_) => {
+                self.emit(SessionEvent::Saved(*session_id));
+                self.hooks.saved(record).await;
+            }
+            None => {
+                self.emit(SessionEvent::Created(*session_id));
+                self.hooks.created(record).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn snapshot_keeps_contents_of_later_saves() {
+        let folder = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        let (_, _, clock) = store();
+        let store = FileSessionStorage::new_in_folder(folder.join("sessions"))
+            .set_clock(clock.clone())
+            .set_cross_process_locking(true);
+        let mut session = record(&clock, Duration::from_secs(3600));
+        store.create(&mut session).await.unwrap();
+
+        assert_eq!(
+            store
+                .snapshot_hardlink(folder.join("snapshot"))
+                .await
+                .unwrap(),
+            1
+        );
+        let mut saved = session.clone();
+        saved.data.insert("saved".to_string(), true.into());
+        store.save(&saved).await.unwrap();
+
+        let snapshot = FileSessionStorage::new_in_folder(folder.join("snapshot")).set_clock(clock);
+        assert_eq!(
+            snapshot.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert_eq!(store.load(&session.id).await.unwrap(), Some(saved));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn locked_saves_leave_no_empty_session_file() {
+        let folder = std::env::temp_dir().join(format!("lock-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        let (_, _, clock) = store();
+        let store = FileSessionStorage::new_in_folder(folder.clone())
+            .set_clock(clock.clone())
+            .set_cross_process_locking(true);
+        let session = record(&clock, Duration::from_secs(3600));
+        store.save(&session).await.unwrap();
+
+        let mut names: Vec<_> = std::fs::read_dir(&folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let name = encode_id(&session.id);
+        assert_eq!(names, [format!(".{name}.lock"), name]);
+        assert!(store.scan_report().await.unwrap().garbage.is_empty());
+
+        store.delete(&session.id).await.unwrap();
+        assert_eq!(std::fs::read_dir(&folder).unwrap().count(), 0);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    /// A store with a snapshot in `/snapshot` of `kept`, which is then changed, and of `deleted`,
+    /// which is then deleted.
+    async fn snapshotted() -> (FileSessionStorage, Record, Record) {
+        let (store, _, clock) = store();
+        let mut kept = record(&clock, Duration::from_secs(3600));
+        store.create(&mut kept).await.unwrap();
+        let mut deleted = record(&clock, Duration::from_secs(3600));
+        store.create(&mut deleted).await.unwrap();
+        assert_eq!(store.snapshot_hardlink("/snapshot").await.unwrap(), 2);
+
+        let mut changed = kept.clone();
+        changed.data.insert("changed".to_string(), true.into());
+        store.save(&changed).await.unwrap();
+        store.delete(&deleted.id).await.unwrap();
+        (store, kept, deleted)
+    }
+
+    #[tokio::test]
+    async fn restore_skips_existing_sessions_by_default() {
+        let (store, kept, deleted) = snapshotted().await;
+        let dry_run = RestoreOptions {
+            dry_run: true,
+            ..RestoreOptions::default()
+        };
+        let summary = store.restore_from_dir("/snapshot", dry_run).await.unwrap();
+        assert_eq!((summary.restored, summary.skipped), (1, 1));
+        assert_eq!(store.load(&deleted.id).await.unwrap(), None);
+
+        let summary = store
+            .restore_from_dir("/snapshot", RestoreOptions::default())
+            .await
+            .unwrap();
+        assert_eq!((summary.restored, summary.skipped), (1, 1));
+        assert_eq!(store.load(&deleted.id).await.unwrap(), Some(deleted));
+        assert_ne!(store.load(&kept.id).await.unwrap(), Some(kept));
+    }
+
+    #[tokio::test]
+    async fn restore_overwrites_or_fails_on_conflicts() {
+        let (store, kept, deleted) = snapshotted().await;
+        let fail = RestoreOptions {
+            on_conflict: RestoreConflict::Fail,
+            ..RestoreOptions::default()
+        };
+        assert!(store.restore_from_dir("/snapshot", fail).await.is_err());
+        assert_eq!(store.load(&deleted.id).await.unwrap(), None);
+
+        let overwrite = RestoreOptions {
+            on_conflict: RestoreConflict::Overwrite,
+            ..RestoreOptions::default()
+        };
+        let summary = store
+            .restore_from_dir("/snapshot", overwrite)
+            .await
+            .unwrap();
+        assert_eq!((summary.restored, summary.skipped), (2, 0));
+        assert_eq!(store.load(&kept.id).await.unwrap(), Some(kept));
+        assert_eq!(store.load(&deleted.id).await.unwrap(), Some(deleted));
+    }
+}
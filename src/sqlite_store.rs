@@ -0,0 +1,268 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use time::OffsetDateTime;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+use crate::{
+    error::{decode_json, encode_json},
+    rt::unblock,
+    Clock, SystemClock,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY NOT NULL,
+        expiry INTEGER NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sessions_expiry ON sessions (expiry);
+";
+
+/// A session storage that keeps all sessions in a single SQLite database file, `.sessions.db` by
+/// default.
+///
+/// For deployments that run out of inodes or list the sessions folder slowly with
+/// [`FileSessionStorage`](crate::FileSessionStorage), but still don't want a database server.
+/// SQLite is bundled, so nothing needs to be installed. Sessions are JSON encoded like on disk and
+/// expired sessions are never loaded. Several processes can share the file.
+#[derive(Debug, Clone)]
+pub struct SqliteFileSessionStorage {
+    connection: Arc<Mutex<Connection>>,
+    clock: Arc<dyn Clock>,
+}
+
+fn backend(e: rusqlite::Error) -> session_store::Error {
+    session_store::Error::Backend(format!("Session database failed: {e}"))
+}
+
+/// An expiry date as stored in the `expiry` column, nanoseconds since the Unix epoch.
+fn expiry_key(expiry_date: OffsetDateTime) -> i64 {
+    let nanos = expiry_date.unix_timestamp_nanos();
+    nanos.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+impl SqliteFileSessionStorage {
+    /// Open `.sessions.db` in the current folder, creating it if it doesn't exist.
+    pub fn new() -> session_store::Result<Self> {
+        SqliteFileSessionStorage::open(".sessions.db")
+    }
+
+    /// Open the database at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> session_store::Result<Self> {
+        let connection = Connection::open(path).map_err(backend)?;
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .map_err(backend)?;
+        connection.execute_batch(SCHEMA).map_err(backend)?;
+        Ok(SqliteFileSessionStorage {
+            connection: Arc::new(Mutex::new(connection)),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Take the current time from `clock` instead of the system time when checking expiry, for
+    /// example a [`ManualClock`](crate::ManualClock) in tests.
+    pub fn set_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::from(self.clock.now())
+    }
+
+    /// Run `f` on the connection on a thread where blocking is allowed.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> session_store::Result<T> + Send + 'static,
+    ) -> session_store::Result<T> {
+        let connection = self.connection.clone();
+        unblock(move || f(&mut connection.lock().unwrap())).await
+    }
+
+    /// Count the sessions in the store, including expired ones that haven't been deleted yet.
+    pub async fn count_sessions(&self) -> session_store::Result<usize> {
+        let count: i64 = self
+            .run(|connection| {
+                connection
+                    .query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))
+                    .map_err(backend)
+            })
+            .await?;
+        Ok(count as usize)
+    }
+
+    /// List the IDs of all sessions currently in the store, including expired ones that haven't
+    /// been deleted yet.
+    pub async fn list_session_ids(&self) -> session_store::Result<Vec<Id>> {
+        let ids: Vec<String> = self
+            .run(|connection| {
+                let mut statement = connection
+                    .prepare("SELECT id FROM sessions")
+                    .map_err(backend)?;
+                let ids = statement
+                    .query_map([], |row| row.get(0))
+                    .map_err(backend)?
+                    .collect::<Result<_, _>>()
+                    .map_err(backend)?;
+                Ok(ids)
+            })
+            .await?;
+        Ok(ids.iter().filter_map(|id| id.parse().ok()).collect())
+    }
+
+    /// Get just the expiry date of a session, `None` if it doesn't exist.
+    pub async fn get_expiry(
+        &self,
+        session_id: &Id,
+    ) -> session_store::Result<Option<OffsetDateTime>> {
+        let session_id = session_id.to_string();
+        let expiry: Option<i64> = self
+            .run(move |connection| {
+                connection
+                    .query_row(
+                        "SELECT expiry FROM sessions WHERE id = ?1",
+                        [session_id],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                    .map_err(backend)
+            })
+            .await?;
+        expiry
+            .map(|expiry| {
+                OffsetDateTime::from_unix_timestamp_nanos(expiry as i128)
+                    .map_err(|e| session_store::Error::Decode(e.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Delete every session in the store, returns the number of sessions deleted.
+    pub async fn clear_all(&self) -> session_store::Result<usize> {
+        self.run(|connection| {
+            connection
+                .execute("DELETE FROM sessions", [])
+                .map_err(backend)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteFileSessionStorage {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let mut new_record = record.clone();
+        *record = self
+            .run(move |connection| {
+                let txn = connection.transaction().map_err(backend)?;
+                loop {
+                    let exists = txn
+                        .query_row(
+                            "SELECT 1 FROM sessions WHERE id = ?1",
+                            [new_record.id.to_string()],
+                            |_| Ok(()),
+                        )
+                        .optional()
+                        .map_err(backend)?
+                        .is_some();
+                    if !exists {
+                        break;
+                    }
+                    // Session ID collision, try again with a fresh ID
+                    new_record.id = Id::default();
+                }
+                txn.execute(
+                    "INSERT INTO sessions (id, expiry, data) VALUES (?1, ?2, ?3)",
+                    params![
+                        new_record.id.to_string(),
+                        expiry_key(new_record.expiry_date),
+                        encode_json(&new_record)?
+                    ],
+                )
+                .map_err(backend)?;
+                txn.commit().map_err(backend)?;
+                Ok(new_record)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let params = (
+            record.id.to_string(),
+            expiry_key(record.expiry_date),
+            encode_json(record)?,
+        );
+        self.run(move |connection| {
+            connection
+                .execute(
+                    "INSERT INTO sessions (id, expiry, data) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (id) DO UPDATE SET expiry = excluded.expiry, data = excluded.data",
+                    params,
+                )
+                .map_err(backend)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let session_id = session_id.to_string();
+        let now = expiry_key(self.now_utc());
+        let contents: Option<Vec<u8>> = self
+            .run(move |connection| {
+                connection
+                    .query_row(
+                        "SELECT data FROM sessions WHERE id = ?1 AND expiry >= ?2",
+                        params![session_id, now],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                    .map_err(backend)
+            })
+            .await?;
+        contents.as_deref().map(decode_json).transpose()
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        let session_id = session_id.to_string();
+        self.run(move |connection| {
+            connection
+                .execute("DELETE FROM sessions WHERE id = ?1", [session_id])
+                .map_err(backend)?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for SqliteFileSessionStorage {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let now = expiry_key(self.now_utc());
+        self.run(move |connection| {
+            connection
+                .execute("DELETE FROM sessions WHERE expiry < ?1", [now])
+                .map_err(backend)?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Like the default, but sleeps on the runtime picked by the crate features instead of
+    /// needing a tokio timer, and keeps going when a sweep fails.
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::{Duration, SystemTime};
+
+use futures::TryStreamExt;
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::FileSessionStorage;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const KIB: u64 = 1024;
+
+/// How many of the largest sessions are listed by [`FileSessionStorage::size_histogram`].
+const HISTOGRAM_LARGEST: usize = 10;
+
+/// The result of the last call to `delete_expired`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SweepReport {
+    /// When the sweep finished.
+    pub finished_at: SystemTime,
+    /// How long the sweep took.
+    pub duration: Duration,
+    /// Number of sessions that were loaded to check if they expired.
+    pub checked: usize,
+    /// Number of expired sessions that were deleted.
+    pub deleted: usize,
+    /// Number of session files that were skipped because they couldn't be read or deleted, the
+    /// errors are included in [`dump_report`](crate::FileSessionStorage::dump_report).
+    pub failed: usize,
+}
+
+/// Session counts grouped by a duration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DurationBuckets {
+    /// Less than an hour.
+    pub under_an_hour: usize,
+    /// At least an hour, less than a day.
+    pub under_a_day: usize,
+    /// At least a day, less than a week.
+    pub under_a_week: usize,
+    /// A week or more.
+    pub longer: usize,
+}
+
+impl DurationBuckets {
+    fn add(&mut self, duration: Duration) {
+        let bucket = if duration < HOUR {
+            &mut self.under_an_hour
+        } else if duration < DAY {
+            &mut self.under_a_day
+        } else if duration < WEEK {
+            &mut self.under_a_week
+        } else {
+            &mut self.longer
+        };
+        *bucket += 1;
+    }
+}
+
+/// Session counts grouped by the size of their file, returned by
+/// [`FileSessionStorage::size_histogram`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SizeHistogram {
+    /// Less than 1 KiB.
+    pub under_1_kib: usize,
+    /// At least 1 KiB, less than 4 KiB.
+    pub under_4_kib: usize,
+    /// At least 4 KiB, less than 16 KiB.
+    pub under_16_kib: usize,
+    /// At least 16 KiB, less than 64 KiB.
+    pub under_64_kib: usize,
+    /// 64 KiB or more.
+    pub larger: usize,
+    /// The IDs and sizes in bytes of the largest sessions, largest first.
+    pub largest: Vec<(Id, u64)>,
+}
+
+impl SizeHistogram {
+    fn add(&mut self, session_id: Id, size: u64) {
+        let bucket = if size < KIB {
+            &mut self.under_1_kib
+        } else if size < 4 * KIB {
+            &mut self.under_4_kib
+        } else if size < 16 * KIB {
+            &mut self.under_16_kib
+        } else if size < 64 * KIB {
+            &mut self.under_64_kib
+        } else {
+            &mut self.larger
+        };
+        *bucket += 1;
+        if self.largest.len() < HISTOGRAM_LARGEST
+            || self
+                .largest
+                .last()
+                .is_some_and(|&(_, smallest)| size > smallest)
+        {
+            self.largest.push((session_id, size));
+            self.largest
+                .sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+            self.largest.truncate(HISTOGRAM_LARGEST);
+        }
+    }
+}
+
+/// A summary of the store, returned by [`FileSessionStorage::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StoreStats {
+    /// Number of sessions in the store, including expired ones that weren't deleted yet.
+    pub sessions: usize,
+    /// Combined size of all session files in bytes.
+    pub total_bytes: u64,
+    /// Sessions grouped by the time since they were last saved.
+    pub by_age: DurationBuckets,
+    /// Sessions grouped by the time since they were created, see
+    /// [`SessionMetadata::created`](crate::SessionMetadata::created).
+    pub by_time_since_created: DurationBuckets,
+    /// Sessions that haven't expired, grouped by how long until they do.
+    pub by_time_until_expiry: DurationBuckets,
+    /// Sessions that expired but weren't deleted yet.
+    pub expired: usize,
+    /// The result of the last expiry sweep run by this process.
+    pub last_sweep: Option<SweepReport>,
+}
+
+impl FileSessionStorage {
+    /// The result of the last expiry sweep run by this process, `None` if there hasn't been one.
+    pub fn last_sweep(&self) -> Option<SweepReport> {
+        *self.last_sweep.lock().unwrap()
+    }
+
+    pub(crate) fn record_sweep(&self, report: SweepReport) {
+        *self.last_sweep.lock().unwrap() = Some(report);
+    }
+
+    /// Collect statistics about the sessions in the store, for dashboards.
+    ///
+    /// This needs to load every session, unless
+    /// [`continuously_update_stats`](Self::continuously_update_stats) is running, then the
+    /// statistics from its last refresh are returned right away.
+    pub async fn stats(&self) -> session_store::Result<StoreStats> {
+        let cached = *self.cached_stats.lock().unwrap();
+        match cached {
+            Some(stats) => Ok(StoreStats {
+                last_sweep: self.last_sweep(),
+                ..stats
+            }),
+            None => self.collect_stats().await,
+        }
+    }
+
+    /// Periodically collect the statistics returned by [`stats`](Self::stats), so it is cheap
+    /// enough to call from request handlers.
+    ///
+    /// Spawn it like
+    /// [`continuously_delete_expired`](tower_sessions_core::ExpiredDeletion::continuously_delete_expired),
+    /// the statistics are collected right away and then every `period`. When collecting fails the
+    /// last statistics are kept and the failure is included in
+    /// [`dump_report`](Self::dump_report).
+    pub async fn continuously_update_stats(self, period: Duration) -> session_store::Result<()> {
+        loop {
+            match self.collect_stats().await {
+                Ok(stats) => *self.cached_stats.lock().unwrap() = Some(stats),
+                Err(e) => self.record_error("stats", &e),
+            }
+            crate::rt::sleep(period).await;
+        }
+    }
+
+    /// Count the sessions by the size of their file and find the largest ones, to track down
+    /// handlers that put unexpectedly large data in the session.
+    ///
+    /// This only lists the folder and doesn't open any files. Expired sessions that weren't
+    /// deleted yet are included.
+    pub async fn size_histogram(&self) -> session_store::Result<SizeHistogram> {
+        let mut histogram = SizeHistogram::default();
+        let mut entries = std::pin::pin!(self.session_entries());
+        while let Some((session_id, dir_entry)) = entries.try_next().await? {
+            let Ok(metadata) = dir_entry.metadata().await else {
+                // Deleted since we listed the folder
+                continue;
+            };
+            histogram.add(session_id, metadata.len());
+        }
+        Ok(histogram)
+    }
+
+    async fn collect_stats(&self) -> session_store::Result<StoreStats> {
+        let mut stats = StoreStats {
+            sessions: 0,
+            total_bytes: 0,
+            by_age: DurationBuckets::default(),
+            by_time_since_created: DurationBuckets::default(),
+            by_time_until_expiry: DurationBuckets::default(),
+            expired: 0,
+            last_sweep: self.last_sweep(),
+        };
+        let mut entries = std::pin::pin!(self.session_entries());
+        while let Some((session_id, dir_entry)) = entries.try_next().await? {
+            let Ok(metadata) = dir_entry.metadata().await else {
+                // Deleted since we listed the folder
+                continue;
+            };
+            // Skip files that can't be read instead of failing the whole count, like the sweep
+            let record = match self.read_record(&session_id).await {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.record_error("stats", &e);
+                    continue;
+                }
+            };
+            stats.sessions += 1;
+            stats.total_bytes += metadata.len();
+            if let Ok(modified) = metadata.modified() {
+                stats
+                    .by_age
+                    .add(self.now().duration_since(modified).unwrap_or_default());
+            }
+            match self.created_at_of(&dir_entry.path()).await {
+                Ok(Some(created_at)) => stats
+                    .by_time_since_created
+                    .add(Duration::try_from(self.now_utc() - created_at).unwrap_or_default()),
+                Ok(None) => {}
+                Err(e) => self.record_error("stats", &e),
+            }
+            match Duration::try_from(record.expiry_date - self.now_utc()) {
+                Ok(until_expiry) => stats.by_time_until_expiry.add(until_expiry),
+                Err(_) => stats.expired += 1,
+            }
+        }
+        crate::telemetry::record_sessions_on_disk(stats.sessions);
+        Ok(stats)
+    }
+}
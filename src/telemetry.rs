@@ -0,0 +1,328 @@
+use std::{
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::FileSessionStorage;
+
+/// The store operations that are instrumented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Create,
+    Save,
+    Load,
+    Delete,
+    DeleteExpired,
+}
+
+impl Operation {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Save => "save",
+            Operation::Load => "load",
+            Operation::Delete => "delete",
+            Operation::DeleteExpired => "delete_expired",
+        }
+    }
+}
+
+/// An operation that took longer than the threshold set with
+/// [`FileSessionStorage::set_slow_operation_threshold`], passed to
+/// [`SessionStoreHooks::slow_operation`](crate::SessionStoreHooks::slow_operation).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SlowOperation {
+    /// The name of the operation, like `save` or `delete_expired`.
+    pub operation: &'static str,
+    /// The session file, or the sessions folder for operations that aren't about one session.
+    ///
+    /// The file name contains the session ID, so this is as sensitive as the session cookie.
+    /// Log [`session`](Self::session) instead.
+    pub path: PathBuf,
+    /// A hash of the session ID, for operations on one session.
+    pub session: Option<String>,
+    /// How long the operation took.
+    pub duration: Duration,
+}
+
+/// A hash of the session ID, so traces can be correlated without leaking the ID itself.
+pub(crate) fn session_hash(session_id: &Id) -> String {
+    let mut hasher = DefaultHasher::new();
+    session_id.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// `message` with every session ID in it replaced by its [`session_hash`], for error messages
+/// that contain the path of a session file.
+///
+/// Finds IDs named like [`encode_id`](crate::naming::encode_id) does and in the base64 form of
+/// older versions, names from a custom [`FileNaming`](crate::FileNaming) aren't recognized.
+pub(crate) fn redact_session_ids(message: &str) -> String {
+    let mut redacted = String::with_capacity(message.len());
+    let mut rest = message;
+    while !rest.is_empty() {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        let start = rest.find(is_word).unwrap_or(rest.len());
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        match crate::naming::decode_id(word) {
+            Some(session_id) => {
+                redacted.push_str("session-");
+                redacted.push_str(&session_hash(&session_id));
+            }
+            None => redacted.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    redacted
+}
+
+/// Record the size of the session that was read or written by the current operation.
+pub(crate) fn record_bytes(bytes: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("bytes", bytes);
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("tower_sessions_file_store_record_size_bytes").record(bytes as f64);
+    let _ = bytes;
+}
+
+/// Report the number of session files currently on disk.
+pub(crate) fn record_sessions_on_disk(sessions: usize) {
+    #[cfg(feature = "metrics")]
+    metrics::gauge!("tower_sessions_file_store_sessions").set(sessions as f64);
+    let _ = sessions;
+}
+
+impl FileSessionStorage {
+    /// Run a store operation, reporting how long it took and whether it succeeded.
+    pub(crate) async fn observe<T>(
+        &self,
+        operation: Operation,
+        session_id: Option<Id>,
+        future: impl Future<Output = session_store::Result<T>>,
+    ) -> session_store::Result<T> {
+        self.observe_as(operation, session_id, future, |_| None)
+            .await
+    }
+
+    /// [`observe`](Self::observe) for an operation that can change the ID of the session, like
+    /// `create` after a collision, `final_session_id` gets the ID from its result.
+    pub(crate) async fn observe_as<T>(
+        &self,
+        operation: Operation,
+        mut session_id: Option<Id>,
+        future: impl Future<Output = session_store::Result<T>>,
+        final_session_id: impl FnOnce(&T) -> Option<Id>,
+    ) -> session_store::Result<T> {
+        let started = Instant::now();
+        let future = self.guard_circuit(future);
+
+        #[cfg(feature = "tracing")]
+        let span = self.operation_span(operation, session_id);
+        #[cfg(feature = "tracing")]
+        let result = tracing::Instrument::instrument(future, span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        let result = future.await;
+
+        let duration = started.elapsed();
+        match &result {
+            Ok(value) => {
+                if let Some(final_id) = final_session_id(value) {
+                    session_id = Some(final_id);
+                    #[cfg(feature = "tracing")]
+                    span.record("session", session_hash(&final_id));
+                }
+            }
+            Err(e) => self.record_error(operation.name(), e),
+        }
+        #[cfg(feature = "tracing")]
+        {
+            span.record("duration_us", duration.as_micros() as u64);
+            match &result {
+                Ok(_) => span.record("result", "ok"),
+                // The message holds the path of the session file, and with it the session ID
+                Err(e) => span.record("result", tracing::field::debug(Self::error_kind(e))),
+            };
+            #[cfg(feature = "opentelemetry")]
+            if result.is_err() {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        #[cfg(feature = "metrics")]
+        {
+            let outcome = if result.is_ok() { "ok" } else { "error" };
+            metrics::counter!(
+                "tower_sessions_file_store_operations_total",
+                "operation" => operation.name(),
+                "outcome" => outcome,
+            )
+            .increment(1);
+            metrics::histogram!(
+                "tower_sessions_file_store_operation_duration_seconds",
+                "operation" => operation.name(),
+            )
+            .record(duration.as_secs_f64());
+        }
+        if self
+            .slow_operation_threshold()
+            .is_some_and(|threshold| duration >= threshold)
+        {
+            self.report_slow_operation(SlowOperation {
+                operation: operation.name(),
+                path: match &session_id {
+                    Some(session_id) => self.session_path(session_id),
+                    None => self.folder().to_path_buf(),
+                },
+                session: session_id.as_ref().map(session_hash),
+                duration,
+            })
+            .await;
+        }
+        result
+    }
+
+    /// Warn about every operation that takes at least `threshold`, to catch degraded disks or
+    /// overloaded network file systems early.
+    ///
+    /// Slow operations are passed to the [hooks](crate::SessionStoreHooks::slow_operation) and,
+    /// with the `tracing` feature, logged as a warning with the operation, a hash of the session
+    /// ID and the duration.
+    pub fn set_slow_operation_threshold(self, threshold: Duration) -> Self {
+        self.with_settings(|settings| settings.slow_operation_threshold = Some(threshold))
+    }
+
+    async fn report_slow_operation(&self, slow: SlowOperation) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            operation = slow.operation,
+            session = slow.session.as_deref(),
+            duration_ms = slow.duration.as_millis() as u64,
+            "slow session store operation"
+        );
+        self.hooks.slow_operation(&slow).await;
+    }
+
+    #[cfg(all(feature = "tracing", not(feature = "opentelemetry")))]
+    fn operation_span(&self, operation: Operation, session_id: Option<Id>) -> tracing::Span {
+        tracing::debug_span!(
+            "session_store",
+            operation = operation.name(),
+            session = session_id.as_ref().map(session_hash),
+            bytes = tracing::field::Empty,
+            duration_us = tracing::field::Empty,
+            result = tracing::field::Empty,
+        )
+    }
+
+    /// The same span, with the attributes the OpenTelemetry semantic conventions define for
+    /// database calls, so tracing-opentelemetry exports it as a client span of a `file` database.
+    #[cfg(feature = "opentelemetry")]
+    fn operation_span(&self, operation: Operation, session_id: Option<Id>) -> tracing::Span {
+        tracing::debug_span!(
+            "session_store",
+            operation = operation.name(),
+            session = session_id.as_ref().map(session_hash),
+            bytes = tracing::field::Empty,
+            duration_us = tracing::field::Empty,
+            result = tracing::field::Empty,
+            otel.name = operation.name(),
+            otel.kind = "client",
+            otel.status_code = tracing::field::Empty,
+            db.system = "file",
+            db.operation.name = operation.name(),
+            db.namespace = %self.folder().display(),
+        )
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl FileSessionStorage {
+    /// Periodically count the sessions on disk and their combined size, and report them as the
+    /// `tower_sessions_file_store_sessions` and `tower_sessions_file_store_bytes` gauges.
+    ///
+    /// Only lists the folder, no sessions are loaded. Spawn it like
+    /// [`continuously_delete_expired`](tower_sessions_core::ExpiredDeletion::continuously_delete_expired),
+    /// the gauges are updated right away and then every `period`. When counting fails the gauges
+    /// keep their last values and the failure is included in
+    /// [`dump_report`](Self::dump_report).
+    pub async fn continuously_report_usage(self, period: Duration) -> session_store::Result<()> {
+        loop {
+            match self.count_sessions().await {
+                Ok(sessions) => record_sessions_on_disk(sessions),
+                Err(e) => self.record_error("report_usage", &e),
+            }
+            match self.total_disk_usage().await {
+                Ok(bytes) => metrics::gauge!("tower_sessions_file_store_bytes").set(bytes as f64),
+                Err(e) => self.record_error("report_usage", &e),
+            }
+            crate::rt::sleep(period).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use tower_sessions_core::{ExpiredDeletion, SessionStore};
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn usage_reporting_survives_failures() {
+        use futures::future::{select, Either};
+
+        let (store, fs, _) = store();
+        fs.fail_reads(Some(std::io::ErrorKind::Other));
+        let reporting = std::pin::pin!(store
+            .clone()
+            .continuously_report_usage(Duration::from_millis(1)));
+        let waited = std::pin::pin!(crate::rt::sleep(Duration::from_millis(50)));
+        assert!(matches!(select(reporting, waited).await, Either::Right(_)));
+
+        fs.fail_reads(None);
+        let mut report = Vec::new();
+        store.dump_report(&mut report).await.unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.matches("\treport_usage\t").count() > 2);
+    }
+
+    #[derive(Default)]
+    struct SlowOperations(std::sync::Mutex<Vec<SlowOperation>>);
+
+    #[async_trait::async_trait]
+    impl crate::SessionStoreHooks for Arc<SlowOperations> {
+        async fn slow_operation(&self, operation: &SlowOperation) {
+            self.0.lock().unwrap().push(operation.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_operations_carry_a_hash_of_the_session_id() {
+        let (store, _, clock) = store();
+        let slow = Arc::new(SlowOperations::default());
+        let store = store
+            .set_slow_operation_threshold(Duration::ZERO)
+            .add_hooks(slow.clone());
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        store.delete_expired().await.unwrap();
+
+        let slow = slow.0.lock().unwrap();
+        assert_eq!(slow[0].operation, "create");
+        assert_eq!(slow[0].session, Some(session_hash(&session.id)));
+        assert_eq!(slow[0].path, store.session_path(&session.id));
+        assert_eq!(slow[1].operation, "delete_expired");
+        assert_eq!(slow[1].session, None);
+    }
+}
@@ -0,0 +1,42 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{error::IoResultExt, FileSessionStorage};
+
+/// Removes a temporary sessions folder once the last store using it is dropped.
+#[derive(Debug)]
+pub(crate) struct TempFolder(PathBuf);
+
+impl Drop for TempFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl FileSessionStorage {
+    /// Create a store in a new, uniquely named folder in the system temp directory, which is
+    /// removed again when the store and all its clones are dropped.
+    ///
+    /// Meant for tests, so they don't leave `.sessions` folders behind or see each other's
+    /// sessions.
+    pub fn temporary() -> session_store::Result<Self> {
+        let folder = std::env::temp_dir().join(format!("tower-sessions-{}", Id::default()));
+        std::fs::create_dir_all(&folder).context("create folder", &folder)?;
+        let temp_folder = Arc::new(TempFolder(folder.clone()));
+        let mut storage = FileSessionStorage::new_in_folder(folder);
+        storage.temp_folder = Some(temp_folder);
+        Ok(storage)
+    }
+
+    /// The folder of a store created with [`temporary`](Self::temporary), `None` for other
+    /// stores.
+    pub fn temporary_folder(&self) -> Option<&Path> {
+        self.temp_folder
+            .as_ref()
+            .map(|temp_folder| temp_folder.0.as_path())
+    }
+}
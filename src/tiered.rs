@@ -0,0 +1,135 @@
+use async_trait::async_trait;
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, ExpiredDeletion, SessionStore,
+};
+
+use crate::FileSessionStorage;
+
+/// A fast `Hot` store in front of a [`FileSessionStorage`], for example a `MemoryStore` or a Redis
+/// store.
+///
+/// The file store is the durable tier: writes go to it first and then to the hot store, loads
+/// try the hot store first and copy sessions found only on disk into it. If the hot store loses
+/// its sessions, like a `MemoryStore` after a restart, they are loaded from disk again.
+///
+/// Unlike [`with_memory_cache`](FileSessionStorage::with_memory_cache) this works with any hot
+/// store, and expiry sweeps run on both tiers when `Hot` implements [`ExpiredDeletion`].
+#[derive(Debug, Clone)]
+pub struct TieredStore<Hot> {
+    hot: Hot,
+    cold: FileSessionStorage,
+}
+
+impl<Hot: SessionStore> TieredStore<Hot> {
+    /// Put `hot` in front of `cold`.
+    pub fn new(hot: Hot, cold: FileSessionStorage) -> Self {
+        TieredStore { hot, cold }
+    }
+
+    /// The hot tier.
+    pub fn hot(&self) -> &Hot {
+        &self.hot
+    }
+
+    /// The file store, for operations the hot tier doesn't need to know about like listing
+    /// sessions.
+    pub fn cold(&self) -> &FileSessionStorage {
+        &self.cold
+    }
+}
+
+#[async_trait]
+impl<Hot: SessionStore> SessionStore for TieredStore<Hot> {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        // The file store picks the final ID on collisions, the hot tier just stores it
+        self.cold.create(record).await?;
+        self.hot.save(record).await
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.cold.save(record).await?;
+        self.hot.save(record).await
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        if let Some(record) = self.hot.load(session_id).await? {
+            return Ok(Some(record));
+        }
+        let record = self.cold.load(session_id).await?;
+        if let Some(record) = &record {
+            self.hot.save(record).await?;
+        }
+        Ok(record)
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.cold.delete(session_id).await?;
+        self.hot.delete(session_id).await
+    }
+}
+
+#[async_trait]
+impl<Hot: SessionStore + ExpiredDeletion> ExpiredDeletion for TieredStore<Hot> {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        // Sweep both even if one fails
+        let cold = self.cold.delete_expired().await;
+        let hot = self.hot.delete_expired().await;
+        cold.and(hot)
+    }
+
+    async fn continuously_delete_expired(
+        self,
+        period: tokio::time::Duration,
+    ) -> session_store::Result<()> {
+        crate::rt::sweep_every(period, || self.delete_expired()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    /// A tiered store with another file store on its own file system as the hot tier.
+    fn tiered() -> (TieredStore<FileSessionStorage>, crate::ManualClock) {
+        let (hot, _, _) = store();
+        let (cold, _, clock) = store();
+        (TieredStore::new(hot.set_clock(clock.clone()), cold), clock)
+    }
+
+    #[tokio::test]
+    async fn writes_reach_both_tiers() {
+        let (tiered, clock) = tiered();
+        let mut session = record(&clock, Duration::from_secs(60));
+        tiered.create(&mut session).await.unwrap();
+        assert_eq!(
+            tiered.hot().load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert_eq!(
+            tiered.cold().load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+
+        tiered.delete(&session.id).await.unwrap();
+        assert_eq!(tiered.hot().load(&session.id).await.unwrap(), None);
+        assert_eq!(tiered.cold().load(&session.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn sessions_lost_by_the_hot_tier_are_loaded_from_disk() {
+        let (tiered, clock) = tiered();
+        let mut session = record(&clock, Duration::from_secs(60));
+        tiered.create(&mut session).await.unwrap();
+        tiered.hot().delete(&session.id).await.unwrap();
+
+        assert_eq!(
+            tiered.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert_eq!(tiered.hot().load(&session.id).await.unwrap(), Some(session));
+    }
+}
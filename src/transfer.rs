@@ -0,0 +1,354 @@
+use futures::TryStreamExt;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, SessionStore,
+};
+
+use crate::{
+    error::{decode_json, encode_json},
+    FileSessionStorage,
+};
+
+/// What [`FileSessionStorage::import_jsonl`] does with a record whose ID is already in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportConflict {
+    /// Keep the existing session and skip the imported one.
+    #[default]
+    Skip,
+    /// Replace the existing session with the imported one.
+    Overwrite,
+    /// Import the record under a new ID.
+    NewId,
+}
+
+/// Options for [`FileSessionStorage::import_jsonl`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportOptions {
+    /// Don't import records that already expired.
+    pub skip_expired: bool,
+    /// What to do with records whose ID is already in use.
+    pub on_conflict: ImportConflict,
+}
+
+/// The result of [`FileSessionStorage::import_jsonl`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Number of records written to the store.
+    pub imported: usize,
+    /// Number of records skipped because they expired or their ID was already in use.
+    pub skipped: usize,
+    /// Number of records whose ID was already in use, handled as set in
+    /// [`ImportOptions::on_conflict`].
+    pub conflicts: usize,
+}
+
+/// Options for [`FileSessionStorage::migrate_to`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrateOptions {
+    /// Don't migrate sessions that already expired.
+    pub skip_expired: bool,
+    /// Delete each session from this store once it was written to the target.
+    pub delete_migrated: bool,
+}
+
+/// How far along [`FileSessionStorage::migrate_to`] is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationProgress {
+    /// Number of sessions written to the target.
+    pub migrated: usize,
+    /// Number of sessions skipped because they expired.
+    pub skipped: usize,
+}
+
+impl FileSessionStorage {
+    /// Write every session in the store to `writer` as JSON Lines, one record per line.
+    ///
+    /// Useful for backups or moving to another store. Expired sessions that haven't been deleted
+    /// yet are included. Returns the number of sessions written.
+    pub async fn export_jsonl(
+        &self,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> session_store::Result<usize> {
+        let mut sessions = std::pin::pin!(self.iter_sessions());
+        let mut exported = 0;
+        while let Some(record) = sessions.try_next().await? {
+            let mut line = encode_json(&record)?;
+            line.push(b'\n');
+            writer.write_all(&line).await.map_err(|e| {
+                session_store::Error::Backend(format!("Failed to write export: {e}"))
+            })?;
+            exported += 1;
+        }
+        writer
+            .flush()
+            .await
+            .map_err(|e| session_store::Error::Backend(format!("Failed to write export: {e}")))?;
+        Ok(exported)
+    }
+
+    /// Read sessions from JSON Lines, as written by [`export_jsonl`](Self::export_jsonl), and
+    /// write them to the store.
+    ///
+    /// Empty lines are ignored. Stops at the first line that isn't a valid record, records before
+    /// it stay imported.
+    pub async fn import_jsonl(
+        &self,
+        reader: impl AsyncBufRead + Unpin,
+        options: ImportOptions,
+    ) -> session_store::Result<ImportSummary> {
+        let mut lines = reader.lines();
+        let mut summary = ImportSummary::default();
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|e| session_store::Error::Backend(format!("Failed to read import: {e}")))?
+        {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut record: Record = decode_json(line.as_bytes())?;
+            if options.skip_expired && record.expiry_date < self.now_utc() {
+                summary.skipped += 1;
+                continue;
+            }
+
+            if !self.create_with_id(&record).await? {
+                summary.conflicts += 1;
+                match options.on_conflict {
+                    ImportConflict::Skip => {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    ImportConflict::Overwrite => self.save(&record).await?,
+                    ImportConflict::NewId => {
+                        record.id = Id::default();
+                        self.create(&mut record).await?;
+                    }
+                }
+            }
+            summary.imported += 1;
+        }
+        Ok(summary)
+    }
+
+    /// Copy sessions from another store, for example when moving from a `MemoryStore` or database
+    /// to this store.
+    ///
+    /// Since stores can't be listed, the IDs of the sessions to copy have to be provided. IDs that
+    /// don't exist in `source` are skipped, existing sessions with the same ID are overwritten.
+    /// Returns the number of sessions copied.
+    pub async fn copy_from(
+        &self,
+        source: &impl SessionStore,
+        session_ids: impl IntoIterator<Item = Id>,
+    ) -> session_store::Result<usize> {
+        let mut copied = 0;
+        for session_id in session_ids {
+            let Some(record) = source.load(&session_id).await? else {
+                continue;
+            };
+            if !self.create_with_id(&record).await? {
+                self.save(&record).await?;
+            }
+            copied += 1;
+        }
+        Ok(copied)
+    }
+
+    /// Move every session in this store into another store, for example when switching to a
+    /// database.
+    ///
+    /// Sessions are written to `target` using `save`, so their IDs are kept. `progress` is called
+    /// after each session with the totals so far, and the final totals are returned.
+    pub async fn migrate_to(
+        &self,
+        target: &impl SessionStore,
+        options: MigrateOptions,
+        mut progress: impl FnMut(MigrationProgress),
+    ) -> session_store::Result<MigrationProgress> {
+        let mut sessions = std::pin::pin!(self.iter_sessions());
+        let mut totals = MigrationProgress::default();
+        while let Some(record) = sessions.try_next().await? {
+            if options.skip_expired && record.expiry_date < self.now_utc() {
+                totals.skipped += 1;
+            } else {
+                target.save(&record).await?;
+                if options.delete_migrated {
+                    self.delete_session(&record.id).await?;
+                }
+                totals.migrated += 1;
+            }
+            progress(totals);
+        }
+        Ok(totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    #[tokio::test]
+    async fn export_writes_one_line_per_session() {
+        let (store, _, clock) = store();
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            let mut session = record(&clock, Duration::from_secs(60));
+            store.create(&mut session).await.unwrap();
+            sessions.push(session);
+        }
+
+        let mut exported = Vec::new();
+        assert_eq!(store.export_jsonl(&mut exported).await.unwrap(), 3);
+        let mut lines: Vec<Record> = exported
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        lines.sort_by_key(|record| record.id.to_string());
+        sessions.sort_by_key(|record| record.id.to_string());
+        assert_eq!(lines, sessions);
+    }
+
+    /// The line `export_jsonl` writes for `session`.
+    fn exported(session: &Record) -> Vec<u8> {
+        let mut line = encode_json(session).unwrap();
+        line.push(b'\n');
+        line
+    }
+
+    #[tokio::test]
+    async fn import_skips_conflicts_by_default() {
+        let (store, _, clock) = store();
+        let mut existing = record(&clock, Duration::from_secs(60));
+        store.create(&mut existing).await.unwrap();
+        let mut imported = existing.clone();
+        imported.data.insert("imported".to_string(), true.into());
+
+        let summary = store
+            .import_jsonl(&exported(&imported)[..], ImportOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 0,
+                skipped: 1,
+                conflicts: 1,
+            }
+        );
+        assert_eq!(store.load(&existing.id).await.unwrap(), Some(existing));
+    }
+
+    #[tokio::test]
+    async fn import_overwrites_or_renames_conflicts() {
+        let (store, _, clock) = store();
+        let mut existing = record(&clock, Duration::from_secs(60));
+        store.create(&mut existing).await.unwrap();
+        let mut imported = existing.clone();
+        imported.data.insert("imported".to_string(), true.into());
+        let line = exported(&imported);
+
+        let options = ImportOptions {
+            on_conflict: ImportConflict::Overwrite,
+            ..ImportOptions::default()
+        };
+        let summary = store.import_jsonl(&line[..], options).await.unwrap();
+        assert_eq!((summary.imported, summary.conflicts), (1, 1));
+        assert_eq!(store.load(&existing.id).await.unwrap(), Some(imported));
+
+        let options = ImportOptions {
+            on_conflict: ImportConflict::NewId,
+            ..ImportOptions::default()
+        };
+        let summary = store.import_jsonl(&line[..], options).await.unwrap();
+        assert_eq!((summary.imported, summary.conflicts), (1, 1));
+        assert_eq!(store.count_sessions().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn import_skips_expired_records_if_asked() {
+        let (store, _, clock) = store();
+        let session = record(&clock, Duration::from_secs(60));
+        let line = exported(&session);
+        clock.advance(Duration::from_secs(120));
+
+        let options = ImportOptions {
+            skip_expired: true,
+            ..ImportOptions::default()
+        };
+        let summary = store.import_jsonl(&line[..], options).await.unwrap();
+        assert_eq!((summary.imported, summary.skipped), (0, 1));
+        assert_eq!(store.count_sessions().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn import_stops_at_an_invalid_line() {
+        let (store, _, clock) = store();
+        let session = record(&clock, Duration::from_secs(60));
+        let mut lines = exported(&session);
+        lines.extend_from_slice(b"\nnot json\n");
+
+        let error = store
+            .import_jsonl(&lines[..], ImportOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, session_store::Error::Decode(_)));
+        assert_eq!(store.list_session_ids().await.unwrap(), vec![session.id]);
+    }
+
+    #[tokio::test]
+    async fn copy_from_keeps_ids_and_overwrites() {
+        let (source, _, clock) = store();
+        let (target, _, _) = store();
+        let mut copied = record(&clock, Duration::from_secs(60));
+        source.create(&mut copied).await.unwrap();
+        let mut stale = copied.clone();
+        stale.data.insert("stale".to_string(), true.into());
+        target.save(&stale).await.unwrap();
+
+        let count = target
+            .copy_from(&source, [copied.id, Id::default()])
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(target.load(&copied.id).await.unwrap(), Some(copied));
+        assert_eq!(target.count_sessions().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn migrate_to_reports_progress_and_deletes_if_asked() {
+        let (source, _, clock) = store();
+        let (target, _, _) = store();
+        let mut live = record(&clock, Duration::from_secs(3600));
+        let mut expired = record(&clock, Duration::from_secs(60));
+        source.create(&mut live).await.unwrap();
+        source.create(&mut expired).await.unwrap();
+        clock.advance(Duration::from_secs(120));
+
+        let mut reported = Vec::new();
+        let options = MigrateOptions {
+            skip_expired: true,
+            delete_migrated: true,
+        };
+        let totals = source
+            .migrate_to(&target, options, |progress| reported.push(progress))
+            .await
+            .unwrap();
+        assert_eq!(
+            totals,
+            MigrationProgress {
+                migrated: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported.last(), Some(&totals));
+        assert_eq!(target.list_session_ids().await.unwrap(), vec![live.id]);
+        assert_eq!(source.list_session_ids().await.unwrap(), vec![expired.id]);
+    }
+}
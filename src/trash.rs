@@ -0,0 +1,272 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    naming::{decode_id, encode_id},
+    ArchiveExpired, FileSessionStorage, SessionEvent,
+};
+
+/// Folder in the sessions folder deleted sessions are kept in, see
+/// [`FileSessionStorage::set_soft_delete`].
+const TRASH_FOLDER: &str = ".trash";
+
+/// What happens to the file of a session that is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Discard {
+    Delete,
+    /// Move it to the trash, see [`FileSessionStorage::set_soft_delete`].
+    Trash,
+    /// Move it to the archive of expired sessions, see
+    /// [`FileSessionStorage::set_archive_expired`].
+    Archive(ArchiveExpired),
+}
+
+impl FileSessionStorage {
+    /// Move deleted sessions to the `.trash` folder in the sessions folder and keep them there
+    /// for `retention`, instead of deleting them right away.
+    ///
+    /// Sessions deleted by accident, for example by a mass deletion, can be recovered from there
+    /// with [`undelete`](Self::undelete), and auditors get a window to inspect ended sessions. Expired sessions are still deleted
+    /// right away. Expiry sweeps empty the trash of sessions deleted longer than `retention` ago.
+    /// Disabled by default.
+    pub fn set_soft_delete(mut self, retention: Duration) -> Self {
+        self.soft_delete = Some(retention);
+        self
+    }
+
+    /// List the IDs of the sessions in the trash, that can be recovered with
+    /// [`undelete`](Self::undelete).
+    pub async fn list_deleted(&self) -> session_store::Result<Vec<Id>> {
+        let trash = self.trash_folder();
+        let mut entries = match crate::fs::read_dir(&self.fs, &trash).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(FileError::new("list folder", &trash, e).into()),
+        };
+        let mut session_ids = Vec::new();
+        while let Some(dir_entry) = entries.next_entry().await.context("list folder", &trash)? {
+            // Blob folders end in `.blobs` and don't parse
+            if let Some(session_id) = dir_entry.file_name().to_str().and_then(decode_id) {
+                session_ids.push(session_id);
+            }
+        }
+        Ok(session_ids)
+    }
+
+    /// Move a deleted session and its blobs out of the trash, returns `false` if it isn't in the
+    /// trash, for example because its retention passed.
+    ///
+    /// The session is restored as it was when it was deleted, and sent as
+    /// [`SessionEvent::Created`]. Fails if a session with the same ID exists.
+    pub async fn undelete(&self, session_id: &Id) -> session_store::Result<bool> {
+        let _guard = self.locks.lock(*session_id).await;
+        let trashed = self.trash_path(session_id);
+        let Some(record) = self.read_record_file(&trashed).await? else {
+            return Ok(false);
+        };
+        if self.read_record(session_id).await?.is_some() {
+            return Err(session_store::Error::Backend(format!(
+                "Session {session_id} already exists"
+            )));
+        }
+        let path = self.session_path(session_id);
+        self.create_session_folder(&path).await?;
+        match self.fs.rename(&trashed, &path).await {
+            Ok(()) => {}
+            // Purged since it was read
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(FileError::new("undelete", &trashed, e).into()),
+        }
+        let blobs = self.trash_blob_folder(session_id);
+        match self.fs.rename(&blobs, &self.blob_folder(session_id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(FileError::new("undelete", &blobs, e).into()),
+        }
+        self.add_to_indexes(&record).await?;
+        self.emit(SessionEvent::Created(*session_id));
+        self.hooks.created(&record).await;
+        Ok(true)
+    }
+
+    pub(crate) fn trash_folder(&self) -> PathBuf {
+        self.folder().join(TRASH_FOLDER)
+    }
+
+    /// Where the file of a deleted session is kept.
+    pub(crate) fn trash_path(&self, session_id: &Id) -> PathBuf {
+        self.trash_folder().join(encode_id(session_id))
+    }
+
+    /// Where the blobs of a deleted session are kept.
+    pub(crate) fn trash_blob_folder(&self, session_id: &Id) -> PathBuf {
+        self.trash_folder()
+            .join(format!("{}.blobs", encode_id(session_id)))
+    }
+
+    /// Delete the file of a session at `path`, or move it to the trash or archive.
+    pub(crate) async fn discard_file(
+        &self,
+        session_id: &Id,
+        path: &Path,
+        discard: Discard,
+    ) -> io::Result<()> {
+        match discard {
+            Discard::Delete => return self.fs.remove_file(path).await,
+            Discard::Archive(options) => return self.archive_file(session_id, path, options).await,
+            Discard::Trash => {}
+        }
+        self.fs.create_dir_all(&self.trash_folder()).await?;
+        let trashed = self.trash_path(session_id);
+        self.fs.rename(path, &trashed).await?;
+        // The retention counts from now, renaming kept when the session was last saved. If the
+        // file system can't change it, the session just leaves the trash sooner.
+        let _ = self.fs.set_modified(&trashed, self.now()).await;
+        Ok(())
+    }
+
+    /// Move the blobs of a deleted session to the trash, replacing older ones of the same
+    /// session.
+    pub(crate) async fn trash_blobs(&self, session_id: &Id) -> session_store::Result<()> {
+        let folder = self.blob_folder(session_id);
+        let trashed = self.trash_blob_folder(session_id);
+        match self.fs.remove_dir_all(&trashed).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(FileError::new("delete", &trashed, e).into()),
+        }
+        match self.fs.rename(&folder, &trashed).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(FileError::new("move to trash", &folder, e).into()),
+        }
+    }
+
+    /// Delete sessions that were moved to the trash longer than `retention` ago.
+    pub(crate) async fn purge_trash(&self, retention: Duration) -> session_store::Result<()> {
+        let trash = self.trash_folder();
+        let mut entries = match crate::fs::read_dir(&self.fs, &trash).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(FileError::new("list folder", &trash, e).into()),
+        };
+        while let Some(dir_entry) = entries.next_entry().await.context("list folder", &trash)? {
+            let path = dir_entry.path();
+            let Ok(metadata) = dir_entry.metadata().await else {
+                // Recovered since we listed the folder
+                continue;
+            };
+            if metadata.is_dir() {
+                // Blobs are purged once their session is
+                let has_session = match dir_entry.file_name().to_str() {
+                    Some(name) => match name.strip_suffix(".blobs") {
+                        Some(session) => self
+                            .fs
+                            .try_exists(&trash.join(session))
+                            .await
+                            .context("open", &path)?,
+                        None => true,
+                    },
+                    None => true,
+                };
+                if !has_session {
+                    match self.fs.remove_dir_all(&path).await {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(FileError::new("delete", &path, e).into()),
+                    }
+                }
+                continue;
+            }
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| self.now().duration_since(modified).ok())
+                .unwrap_or_default();
+            if age < retention {
+                continue;
+            }
+            match self.fs.remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(FileError::new("delete", &path, e).into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_sessions_core::{ExpiredDeletion, SessionStore};
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[tokio::test]
+    async fn deleted_sessions_stay_in_the_trash_for_the_retention() {
+        let (store, _, clock) = store();
+        let store = store.set_soft_delete(Duration::from_secs(60));
+        let mut session = record(&clock, DAY);
+        store.create(&mut session).await.unwrap();
+        store.delete(&session.id).await.unwrap();
+
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+        assert_eq!(store.list_deleted().await.unwrap(), vec![session.id]);
+        clock.advance(Duration::from_secs(30));
+        store.delete_expired().await.unwrap();
+        assert_eq!(store.list_deleted().await.unwrap(), vec![session.id]);
+        clock.advance(Duration::from_secs(60));
+        store.delete_expired().await.unwrap();
+        assert_eq!(store.list_deleted().await.unwrap(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn undelete_restores_the_session() {
+        let (store, _, clock) = store();
+        let store = store.set_soft_delete(DAY);
+        let mut session = record(&clock, DAY);
+        store.create(&mut session).await.unwrap();
+        store
+            .put_blob(&session.id, "avatar", vec![1, 2, 3])
+            .await
+            .unwrap();
+        store.delete(&session.id).await.unwrap();
+
+        assert!(store.undelete(&session.id).await.unwrap());
+        assert_eq!(
+            store.load(&session.id).await.unwrap(),
+            Some(session.clone())
+        );
+        assert_eq!(
+            store.get_blob(&session.id, "avatar").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert!(store.list_deleted().await.unwrap().is_empty());
+        assert!(!store.undelete(&session.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn undelete_refuses_to_replace_a_session() {
+        let (store, _, clock) = store();
+        let store = store.set_soft_delete(DAY);
+        let mut session = record(&clock, DAY);
+        store.create(&mut session).await.unwrap();
+        store.delete(&session.id).await.unwrap();
+        let mut replacement = session.clone();
+        replacement.data.insert("new".to_string(), true.into());
+        assert!(store.create_with_id(&replacement).await.unwrap());
+
+        assert!(store.undelete(&session.id).await.is_err());
+        assert_eq!(store.load(&session.id).await.unwrap(), Some(replacement));
+        assert_eq!(store.list_deleted().await.unwrap(), vec![session.id]);
+    }
+}
@@ -0,0 +1,261 @@
+use std::{borrow::Cow, collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use time::OffsetDateTime;
+use tower_sessions_core::session::Record;
+
+use crate::{BuildError, FileSessionStorage};
+
+type Classifier = dyn Fn(&Record) -> Option<String> + Send + Sync;
+
+/// Limits on how long the sessions of one class live, see [`FileSessionStorage::add_ttl_policy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TtlPolicy {
+    /// Expire sessions that weren't saved for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Expire sessions this long after they were created, however often they are saved.
+    pub max_lifetime: Option<Duration>,
+}
+
+/// The classes and policies set on a store, shared between clones.
+#[derive(Clone, Default)]
+pub(crate) struct TtlPolicies {
+    classify: Option<Arc<Classifier>>,
+    policies: Arc<HashMap<String, TtlPolicy>>,
+}
+
+impl fmt::Debug for TtlPolicies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TtlPolicies")
+            .field("policies", &self.policies)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TtlPolicies {
+    pub(crate) fn set_classify(
+        &mut self,
+        classify: impl Fn(&Record) -> Option<String> + Send + Sync + 'static,
+    ) {
+        self.classify = Some(Arc::new(classify));
+    }
+
+    pub(crate) fn insert(&mut self, class: String, policy: TtlPolicy) {
+        Arc::make_mut(&mut self.policies).insert(class, policy);
+    }
+
+    /// Check that every policy limits something and can be applied.
+    pub(crate) fn validate(&self) -> Result<(), BuildError> {
+        if self.classify.is_none() && !self.policies.is_empty() {
+            return Err(BuildError::InvalidTtlPolicy(
+                "policies need a class to sort sessions into them".to_string(),
+            ));
+        }
+        for (class, policy) in self.policies.iter() {
+            let limits = [policy.idle_timeout, policy.max_lifetime];
+            if limits.iter().all(Option::is_none) {
+                return Err(BuildError::InvalidTtlPolicy(format!(
+                    "the policy of {class:?} has no limits"
+                )));
+            }
+            if limits.contains(&Some(Duration::ZERO)) {
+                return Err(BuildError::InvalidTtlPolicy(format!(
+                    "the policy of {class:?} has a limit of zero"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The policy of the class `record` belongs to, if it has one.
+    fn policy_for(&self, record: &Record) -> Option<TtlPolicy> {
+        let classify = self.classify.as_ref()?;
+        self.policies.get(&classify(record)?).copied()
+    }
+}
+
+impl FileSessionStorage {
+    /// Sort sessions into classes for [`add_ttl_policy`](Self::add_ttl_policy), by the class
+    /// `classify` returns for their record, for example from a `remember_me` or `role` field in
+    /// the session data.
+    ///
+    /// Sessions it returns `None` for, or whose class has no policy, keep the expiry date they are
+    /// saved with.
+    pub fn set_ttl_class(
+        mut self,
+        classify: impl Fn(&Record) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.ttl_policies.set_classify(classify);
+        self
+    }
+
+    /// Limit how long the sessions of `class` live, for example 30 days for "remember me"
+    /// sessions and 8 hours for admin sessions.
+    ///
+    /// The expiry date sessions are written with is lowered to the earliest of the limits, after
+    /// which they expire like any other session. The idle timeout counts from the last
+    /// save, tower-sessions saves on every request with `Expiry::OnInactivity`.
+    /// Adding a policy for the same class replaces it.
+    pub fn add_ttl_policy(mut self, class: impl Into<String>, policy: TtlPolicy) -> Self {
+        self.ttl_policies.insert(class.into(), policy);
+        self
+    }
+
+    /// Whether the class of `record` has a policy.
+    pub(crate) fn has_ttl_policy(&self, record: &Record) -> bool {
+        self.ttl_policies.policy_for(record).is_some()
+    }
+
+    /// The record as it should be written, with its expiry date limited by the policy of its
+    /// class.
+    pub(crate) fn apply_ttl_policy<'a>(
+        &self,
+        record: &'a Record,
+        created_at: OffsetDateTime,
+    ) -> Cow<'a, Record> {
+        let Some(policy) = self.ttl_policies.policy_for(record) else {
+            return Cow::Borrowed(record);
+        };
+        // Limits too far away to represent don't limit anything
+        let after = |time: OffsetDateTime, duration: Duration| {
+            time::Duration::try_from(duration)
+                .ok()
+                .and_then(|duration| time.checked_add(duration))
+        };
+        let limits = [
+            policy
+                .idle_timeout
+                .and_then(|timeout| after(self.now_utc(), timeout)),
+            policy
+                .max_lifetime
+                .and_then(|lifetime| after(created_at, lifetime)),
+        ];
+        match limits.into_iter().flatten().min() {
+            Some(limit) if limit < record.expiry_date => {
+                let mut record = record.clone();
+                record.expiry_date = limit;
+                Cow::Owned(record)
+            }
+            _ => Cow::Borrowed(record),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_sessions_core::SessionStore;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    fn with_policies(store: FileSessionStorage) -> FileSessionStorage {
+        store
+            .set_ttl_class(|record| {
+                record
+                    .data
+                    .get("class")
+                    .and_then(|class| class.as_str())
+                    .map(str::to_owned)
+            })
+            .add_ttl_policy(
+                "admin",
+                TtlPolicy {
+                    idle_timeout: Some(Duration::from_secs(600)),
+                    max_lifetime: Some(Duration::from_secs(3600)),
+                },
+            )
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_caps_expiry_date() {
+        let (store, _, clock) = store();
+        let store = with_policies(store);
+        let mut session = record(&clock, Duration::from_secs(86400));
+        session.data.insert("class".to_string(), "admin".into());
+        store.create(&mut session).await.unwrap();
+
+        let loaded = store.load(&session.id).await.unwrap().unwrap();
+        assert_eq!(
+            loaded.expiry_date,
+            store.now_utc() + Duration::from_secs(600)
+        );
+    }
+
+    #[tokio::test]
+    async fn max_lifetime_counts_from_creation() {
+        let (store, _, clock) = store();
+        let store = with_policies(store);
+        let mut session = record(&clock, Duration::from_secs(86400));
+        session.data.insert("class".to_string(), "admin".into());
+        store.create(&mut session).await.unwrap();
+        let created_at = store.now_utc();
+
+        // Saving within the idle timeout keeps the session alive, up to the max lifetime
+        for _ in 0..7 {
+            clock.advance(Duration::from_secs(500));
+            session.expiry_date = store.now_utc() + Duration::from_secs(86400);
+            store.save(&session).await.unwrap();
+        }
+        let loaded = store.load(&session.id).await.unwrap().unwrap();
+        assert_eq!(loaded.expiry_date, created_at + Duration::from_secs(3600));
+
+        clock.advance(Duration::from_secs(200));
+        assert_eq!(store.load(&session.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn sessions_without_policy_keep_expiry_date() {
+        let (store, _, clock) = store();
+        let store = with_policies(store);
+        let mut session = record(&clock, Duration::from_secs(86400));
+        session.data.insert("class".to_string(), "user".into());
+        store.create(&mut session).await.unwrap();
+
+        assert_eq!(store.load(&session.id).await.unwrap(), Some(session));
+    }
+
+    #[test]
+    fn huge_limits_dont_overflow() {
+        let (store, _, clock) = store();
+        let store = store
+            .set_ttl_class(|_| Some("all".to_string()))
+            .add_ttl_policy(
+                "all",
+                TtlPolicy {
+                    idle_timeout: Some(Duration::MAX),
+                    max_lifetime: None,
+                },
+            );
+        let session = record(&clock, Duration::from_secs(60));
+        let applied = store.apply_ttl_policy(&session, store.now_utc());
+        assert_eq!(applied.expiry_date, session.expiry_date);
+    }
+
+    #[test]
+    fn validate_rejects_empty_and_zero_policies() {
+        let mut policies = TtlPolicies::default();
+        policies.set_classify(|_| None);
+        policies.insert("empty".to_string(), TtlPolicy::default());
+        assert!(policies.validate().is_err());
+
+        let mut policies = TtlPolicies::default();
+        policies.set_classify(|_| None);
+        policies.insert(
+            "zero".to_string(),
+            TtlPolicy {
+                idle_timeout: Some(Duration::ZERO),
+                max_lifetime: None,
+            },
+        );
+        assert!(policies.validate().is_err());
+
+        let mut policies = TtlPolicies::default();
+        policies.insert(
+            "unclassified".to_string(),
+            TtlPolicy {
+                idle_timeout: Some(Duration::from_secs(60)),
+                max_lifetime: None,
+            },
+        );
+        assert!(policies.validate().is_err());
+    }
+}
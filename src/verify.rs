@@ -0,0 +1,95 @@
+use std::{path::Path, time::Duration};
+
+use futures::StreamExt;
+use tower_sessions_core::{session::Id, session_store};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    FileSessionStorage, SessionEvent,
+};
+
+/// Folder in the sessions folder corrupt sessions are moved to, see
+/// [`FileSessionStorage::set_quarantine_corrupt_sessions`].
+const QUARANTINE_FOLDER: &str = ".quarantine";
+
+impl FileSessionStorage {
+    /// Move sessions that [`continuously_verify`](Self::continuously_verify) finds corrupt to the
+    /// `.quarantine` folder in the sessions folder, instead of leaving them in place.
+    ///
+    /// They can be inspected there and are no longer loaded, so the user gets a new session
+    /// instead of an error. Defaults to `false`.
+    pub fn set_quarantine_corrupt_sessions(mut self, enabled: bool) -> Self {
+        self.quarantine_corrupt = enabled;
+        self
+    }
+
+    /// Slowly read every session and check that it can be parsed, so files damaged by the disk
+    /// are found before a user loads them.
+    ///
+    /// Spawn it like
+    /// [`continuously_delete_expired`](tower_sessions_core::ExpiredDeletion::continuously_delete_expired).
+    /// One session is checked every `interval`, once all were checked it starts over. Corrupt
+    /// sessions are reported with [`SessionEvent::Corrupted`] and included in
+    /// [`dump_report`](Self::dump_report), and quarantined with
+    /// [`set_quarantine_corrupt_sessions`](Self::set_quarantine_corrupt_sessions).
+    pub async fn continuously_verify(self, interval: Duration) -> session_store::Result<()> {
+        loop {
+            let mut checked = 0;
+            let mut entries = std::pin::pin!(self.session_entries());
+            while let Some(entry) = entries.next().await {
+                let result = match entry {
+                    Ok((session_id, dir_entry)) => {
+                        checked += 1;
+                        self.verify_session(&session_id, &dir_entry.path()).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    self.record_error("verify", &e);
+                }
+                crate::rt::sleep(interval).await;
+            }
+            // Don't spin on an empty or unreadable folder
+            if checked == 0 {
+                crate::rt::sleep(interval).await;
+            }
+        }
+    }
+
+    /// Check one session file, reporting and quarantining it if it is corrupt.
+    async fn verify_session(&self, session_id: &Id, path: &Path) -> session_store::Result<()> {
+        match self.read_record_file(path).await {
+            Err(session_store::Error::Decode(_)) => {}
+            // I/O errors say nothing about the file
+            other => return other.map(|_| ()),
+        }
+        // Read it again under the lock, the first read can race with a save that isn't done yet
+        let _guard = self.locks.lock(*session_id).await;
+        let error = match self.read_record_file(path).await {
+            Err(e @ session_store::Error::Decode(_)) => e,
+            // I/O errors say nothing about the file
+            other => return other.map(|_| ()),
+        };
+        self.emit(SessionEvent::Corrupted(*session_id));
+        #[cfg(feature = "tracing")]
+        tracing::warn!(path = %path.display(), %error, "session file is corrupt");
+        if self.quarantine_corrupt {
+            let quarantine = self.folder().join(QUARANTINE_FOLDER);
+            self.fs
+                .create_dir_all(&quarantine)
+                .await
+                .context("create folder", &quarantine)?;
+            let to = quarantine.join(path.file_name().unwrap_or_default());
+            match self.fs.rename(path, &to).await {
+                Ok(()) => {}
+                // Deleted or quarantined since it was read
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(FileError::new("quarantine", path, e).into()),
+            }
+        }
+        Err(session_store::Error::Decode(format!(
+            "Corrupt session file {}: {error}",
+            path.display()
+        )))
+    }
+}
@@ -0,0 +1,200 @@
+use std::{io, path::PathBuf};
+
+use tower_sessions_core::{
+    session::{Id, Record},
+    session_store, SessionStore,
+};
+
+use crate::{
+    error::{FileError, IoResultExt},
+    naming::encode_id,
+    FileSessionStorage,
+};
+
+/// Folder in the sessions folder older versions of sessions are kept in, see
+/// [`FileSessionStorage::set_keep_versions`].
+const VERSIONS_FOLDER: &str = ".versions";
+
+/// The most versions [`FileSessionStorageBuilder::keep_versions`](crate::FileSessionStorageBuilder::keep_versions)
+/// accepts, since every save renames each kept version.
+pub const MAX_KEEP_VERSIONS: u32 = 1000;
+
+impl FileSessionStorage {
+    /// Keep the previous `count` versions of every session in the `.versions` folder in the
+    /// sessions folder, as `<id>.v1` for the one before the current version, `<id>.v2` for the
+    /// one before that and so on.
+    ///
+    /// Useful to find out what changed in the session of a user, read them with
+    /// [`load_version`](Self::load_version) and go back with [`roll_back`](Self::roll_back).
+    /// Each save then costs an extra rename per kept version. Versions are deleted with their
+    /// session. Defaults to 0.
+    pub fn set_keep_versions(mut self, count: u32) -> Self {
+        self.keep_versions = count;
+        self
+    }
+
+    fn version_path(&self, session_id: &Id, version: u32) -> PathBuf {
+        self.folder()
+            .join(VERSIONS_FOLDER)
+            .join(format!("{}.v{version}", encode_id(session_id)))
+    }
+
+    /// Read an older version of a session, 1 is the one before the current version. `None` if
+    /// there is no such version.
+    pub async fn load_version(
+        &self,
+        session_id: &Id,
+        version: u32,
+    ) -> session_store::Result<Option<Record>> {
+        if version == 0 {
+            return self.load(session_id).await;
+        }
+        self.read_record_file(&self.version_path(session_id, version))
+            .await
+    }
+
+    /// List the older versions of a session that are kept, newest first.
+    pub async fn list_versions(&self, session_id: &Id) -> session_store::Result<Vec<u32>> {
+        let mut versions = Vec::new();
+        for version in 1..=self.keep_versions {
+            let path = self.version_path(session_id, version);
+            if !self.fs.try_exists(&path).await.context("open", &path)? {
+                break;
+            }
+            versions.push(version);
+        }
+        Ok(versions)
+    }
+
+    /// Save an older version of a session as the current one, returns `false` if there is no such
+    /// version.
+    ///
+    /// The current version becomes version 1, so rolling back can be undone.
+    pub async fn roll_back(&self, session_id: &Id, version: u32) -> session_store::Result<bool> {
+        let Some(record) = self.load_version(session_id, version).await? else {
+            return Ok(false);
+        };
+        self.save(&record).await?;
+        Ok(true)
+    }
+
+    /// Keep the current file of a session as version 1 before it is replaced, moving the older
+    /// versions up by one. Has to be called with the session locked.
+    pub(crate) async fn keep_version(&self, session_id: &Id) -> session_store::Result<()> {
+        if self.keep_versions == 0 {
+            return Ok(());
+        }
+        let path = self.session_path(session_id);
+        match self.fs.metadata(&path).await {
+            // Left by older versions, which created an empty file to lock it
+            Ok(metadata) if metadata.is_empty() => return Ok(()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(FileError::new("get metadata of", &path, e).into()),
+        }
+        let folder = self.folder().join(VERSIONS_FOLDER);
+        self.fs
+            .create_dir_all(&folder)
+            .await
+            .context("create folder", &folder)?;
+        for version in (1..self.keep_versions).rev() {
+            let from = self.version_path(session_id, version);
+            match self
+                .fs
+                .rename(&from, &self.version_path(session_id, version + 1))
+                .await
+            {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(FileError::new("rename", &from, e).into()),
+            }
+        }
+        let first = self.version_path(session_id, 1);
+        match self.fs.remove_file(&first).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(FileError::new("delete", &first, e).into()),
+        }
+        // The file is replaced by a rename, so a hard link keeps the old contents
+        if self.fs.hard_link(&path, &first).await.is_err() {
+            self.fs.copy(&path, &first).await.context("copy", &path)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the older versions of a deleted session.
+    pub(crate) async fn remove_versions(&self, session_id: &Id) -> session_store::Result<()> {
+        // Versions fill up from 1, more than `keep_versions` remain if it was lowered
+        for version in 1.. {
+            let path = self.version_path(session_id, version);
+            match self.fs.remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(FileError::new("delete", &path, e).into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tests::{record, store};
+
+    /// `session` saved with `value` as its only data.
+    async fn save_value(store: &FileSessionStorage, session: &mut Record, value: u32) {
+        session.data.insert("value".to_string(), value.into());
+        store.save(session).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn saves_keep_the_previous_versions() {
+        let (store, _, clock) = store();
+        let store = store.set_keep_versions(2);
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        for value in 1..=3 {
+            save_value(&store, &mut session, value).await;
+        }
+
+        assert_eq!(store.list_versions(&session.id).await.unwrap(), vec![1, 2]);
+        let value = |record: Option<Record>| record.unwrap().data["value"].clone();
+        assert_eq!(value(store.load_version(&session.id, 0).await.unwrap()), 3);
+        assert_eq!(value(store.load_version(&session.id, 1).await.unwrap()), 2);
+        assert_eq!(value(store.load_version(&session.id, 2).await.unwrap()), 1);
+        assert_eq!(store.load_version(&session.id, 3).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn roll_back_can_be_undone() {
+        let (store, _, clock) = store();
+        let store = store.set_keep_versions(3);
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        save_value(&store, &mut session, 1).await;
+        save_value(&store, &mut session, 2).await;
+
+        assert!(store.roll_back(&session.id, 1).await.unwrap());
+        let current = store.load(&session.id).await.unwrap().unwrap();
+        assert_eq!(current.data["value"], 1);
+        let undone = store.load_version(&session.id, 1).await.unwrap().unwrap();
+        assert_eq!(undone.data["value"], 2);
+        assert!(!store.roll_back(&session.id, 9).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn versions_are_deleted_with_their_session() {
+        let (store, _, clock) = store();
+        let store = store.set_keep_versions(2);
+        let mut session = record(&clock, Duration::from_secs(60));
+        store.create(&mut session).await.unwrap();
+        save_value(&store, &mut session, 1).await;
+        assert_eq!(store.list_versions(&session.id).await.unwrap(), vec![1]);
+
+        store.delete(&session.id).await.unwrap();
+        assert!(store.list_versions(&session.id).await.unwrap().is_empty());
+    }
+}